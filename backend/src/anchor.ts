@@ -1,7 +1,31 @@
 import { AnchorProvider, Program, Wallet, web3, Idl, BN } from '@coral-xyz/anchor'
 import { readFileSync } from 'fs'
 import { resolve } from 'path'
-import { getAssociatedTokenAddress, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, createAssociatedTokenAccountInstruction } from '@solana/spl-token'
+import { getAssociatedTokenAddress, TOKEN_PROGRAM_ID, TOKEN_2022_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID, createAssociatedTokenAccountInstruction } from '@solana/spl-token'
+
+// Token-2022 transfer hook set on restricted shares mints (programs/shares_transfer_hook)
+const SHARES_TRANSFER_HOOK_ID = new web3.PublicKey('3sr3Q2ukAuj4CaS3LzSY8FjRVfYZCBMYMNrYhPuQpXfh')
+
+// Restricted shares live under Token-2022; everything else uses spl-token
+function sharesTokenProgramOf(inv: any): web3.PublicKey {
+  return inv.sharesRestricted ? TOKEN_2022_PROGRAM_ID : TOKEN_PROGRAM_ID
+}
+
+// Settlement mints may be classic spl-token or Token-2022 (e.g. with a transfer fee)
+export async function usdcTokenProgramOf(program: Program, usdcMint: web3.PublicKey): Promise<web3.PublicKey> {
+  const conn = (program.provider as any).connection as web3.Connection
+  const info = await conn.getAccountInfo(usdcMint)
+  if (!info) throw new Error(`usdc mint ${usdcMint.toBase58()} not found`)
+  return info.owner
+}
+
+// Exposure counters: ['seller_profile', seller], ['investor_profile', investor], ['position', invoice, investor]
+function profilePda(program: Program, seed: string, ...keys: web3.PublicKey[]): web3.PublicKey {
+  return web3.PublicKey.findProgramAddressSync(
+    [Buffer.from(seed), ...keys.map((key) => key.toBuffer())],
+    program.programId,
+  )[0]
+}
 
 const DEFAULT_COMMITMENT: web3.Commitment = 'confirmed'
 
@@ -30,7 +54,8 @@ export async function buildCancelListingV2Tx(
     [Buffer.from('market'), listingPda.toBuffer()],
     program.programId,
   )
-  const sellerSharesAta = await getAssociatedTokenAddress(sharesMint, seller)
+  const sharesTokenProgram = sharesTokenProgramOf(inv)
+  const sellerSharesAta = await getAssociatedTokenAddress(sharesMint, seller, false, sharesTokenProgram)
   const preIxs: web3.TransactionInstruction[] = []
   const conn = (program.provider as any).connection as web3.Connection
   if (!(await conn.getAccountInfo(sellerSharesAta))) {
@@ -39,7 +64,7 @@ export async function buildCancelListingV2Tx(
       sellerSharesAta,
       seller,
       sharesMint,
-      TOKEN_PROGRAM_ID,
+      sharesTokenProgram,
       ASSOCIATED_TOKEN_PROGRAM_ID,
     ))
   }
@@ -51,7 +76,7 @@ export async function buildCancelListingV2Tx(
       listing: listingPda,
       marketAuthority: marketAuthority,
       sellerSharesAta: sellerSharesAta,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenProgram: sharesTokenProgram,
     })
     .preInstructions(preIxs)
     .transaction()
@@ -159,6 +184,7 @@ export async function mintInvoice(
       seller,
       usdcMint: params.usdcMint,
       systemProgram: web3.SystemProgram.programId,
+      sellerProfile: profilePda(program, 'seller_profile', seller),
     })
     .signers([invoice])
     .rpc()
@@ -173,7 +199,8 @@ export async function createEscrow(program: Program, invoicePk: web3.PublicKey){
     [Buffer.from('escrow'), invoicePk.toBuffer()],
     program.programId
   )
-  const escrowToken = await getAssociatedTokenAddress(usdcMint, escrowAuthority, true)
+  const tokenProgram = await usdcTokenProgramOf(program, usdcMint)
+  const escrowToken = await getAssociatedTokenAddress(usdcMint, escrowAuthority, true, tokenProgram)
 
   const sig = await (program.methods as any)
     .createEscrow()
@@ -183,7 +210,7 @@ export async function createEscrow(program: Program, invoicePk: web3.PublicKey){
       usdcMint,
       escrowAuthority,
       escrowToken,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenProgram,
       associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       systemProgram: web3.SystemProgram.programId,
     })
@@ -212,8 +239,9 @@ export async function settleInvoice(program: Program, invoicePk: web3.PublicKey,
     program.programId
   )
 
-  const escrowToken = await getAssociatedTokenAddress(usdcMint, escrowAuthority, true)
-  const sellerAta = await getAssociatedTokenAddress(usdcMint, seller)
+  const tokenProgram = await usdcTokenProgramOf(program, usdcMint)
+  const escrowToken = await getAssociatedTokenAddress(usdcMint, escrowAuthority, true, tokenProgram)
+  const sellerAta = await getAssociatedTokenAddress(usdcMint, seller, false, tokenProgram)
 
   const txSig = await program.methods
     .setSettled(fundedAmount)
@@ -224,7 +252,9 @@ export async function settleInvoice(program: Program, invoicePk: web3.PublicKey,
       sellerAta,
       escrowToken,
       escrowAuthority,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenProgram,
+      usdcMint,
+      sellerProfile: profilePda(program, 'seller_profile', seller),
     })
     .rpc()
 
@@ -305,10 +335,12 @@ export async function buildFulfillListingV2Tx(
     [Buffer.from('market'), listingPda.toBuffer()],
     program.programId,
   )
-  const buyerUsdcAta = await getAssociatedTokenAddress(usdcMint, buyer)
-  const sellerUsdcAta = await getAssociatedTokenAddress(usdcMint, seller)
-  const sellerSharesAta = await getAssociatedTokenAddress(sharesMint, seller)
-  const buyerSharesAta = await getAssociatedTokenAddress(sharesMint, buyer)
+  const tokenProgram = await usdcTokenProgramOf(program, usdcMint)
+  const buyerUsdcAta = await getAssociatedTokenAddress(usdcMint, buyer, false, tokenProgram)
+  const sellerUsdcAta = await getAssociatedTokenAddress(usdcMint, seller, false, tokenProgram)
+  const sharesTokenProgram = sharesTokenProgramOf(inv)
+  const sellerSharesAta = await getAssociatedTokenAddress(sharesMint, seller, false, sharesTokenProgram)
+  const buyerSharesAta = await getAssociatedTokenAddress(sharesMint, buyer, false, sharesTokenProgram)
   const preIxs: web3.TransactionInstruction[] = []
   const conn = (program.provider as any).connection as web3.Connection
   // Ensure buyer ATAs exist
@@ -318,7 +350,7 @@ export async function buildFulfillListingV2Tx(
       buyerUsdcAta,
       buyer,
       usdcMint,
-      TOKEN_PROGRAM_ID,
+      tokenProgram,
       ASSOCIATED_TOKEN_PROGRAM_ID,
     ))
   }
@@ -328,10 +360,25 @@ export async function buildFulfillListingV2Tx(
       buyerSharesAta,
       buyer,
       sharesMint,
-      TOKEN_PROGRAM_ID,
+      sharesTokenProgram,
       ASSOCIATED_TOKEN_PROGRAM_ID,
     ))
   }
+  // Restricted shares: accounts the transfer hook resolves, passed through to Token-2022
+  const hookAccounts: web3.AccountMeta[] = []
+  if (inv.sharesRestricted) {
+    const [extraAccountMetas] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from('extra-account-metas'), sharesMint.toBuffer()],
+      SHARES_TRANSFER_HOOK_ID,
+    )
+    const [attestation] = web3.PublicKey.findProgramAddressSync(
+      [Buffer.from('attestation'), buyer.toBuffer()],
+      program.programId,
+    )
+    for (const pubkey of [extraAccountMetas, program.programId, attestation, SHARES_TRANSFER_HOOK_ID]) {
+      hookAccounts.push({ pubkey, isSigner: false, isWritable: false })
+    }
+  }
 
   const tx = await (program.methods as any)
     .fulfillListingV2(qty)
@@ -346,10 +393,15 @@ export async function buildFulfillListingV2Tx(
       buyerSharesAta: buyerSharesAta,
       sharesMint: sharesMint,
       usdcMint: usdcMint,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenProgram,
       associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       systemProgram: web3.SystemProgram.programId,
+      sharesTokenProgram,
+      buyerProfile: profilePda(program, 'investor_profile', buyer),
+      buyerPosition: profilePda(program, 'position', invoicePk, buyer),
+      sellerPosition: profilePda(program, 'position', invoicePk, seller),
     })
+    .remainingAccounts(hookAccounts)
     .preInstructions(preIxs)
     .transaction()
   return { tx, listingPda, marketAuthority }
@@ -373,8 +425,9 @@ export async function buildFulfillListingTx(
     [Buffer.from('market'), listingPda.toBuffer()],
     program.programId,
   )
-  const buyerUsdcAta = await getAssociatedTokenAddress(usdcMint, buyer)
-  const sellerUsdcAta = await getAssociatedTokenAddress(usdcMint, seller)
+  const tokenProgram = await usdcTokenProgramOf(program, usdcMint)
+  const buyerUsdcAta = await getAssociatedTokenAddress(usdcMint, buyer, false, tokenProgram)
+  const sellerUsdcAta = await getAssociatedTokenAddress(usdcMint, seller, false, tokenProgram)
   const escrowSharesAta = await getAssociatedTokenAddress(sharesMint, marketAuthority, true)
   const buyerSharesAta = await getAssociatedTokenAddress(sharesMint, buyer)
   const preIxs: web3.TransactionInstruction[] = []
@@ -386,7 +439,7 @@ export async function buildFulfillListingTx(
       buyerUsdcAta,
       buyer,
       usdcMint,
-      TOKEN_PROGRAM_ID,
+      tokenProgram,
       ASSOCIATED_TOKEN_PROGRAM_ID,
     ))
   }
@@ -396,7 +449,7 @@ export async function buildFulfillListingTx(
       sellerUsdcAta,
       seller,
       usdcMint,
-      TOKEN_PROGRAM_ID,
+      tokenProgram,
       ASSOCIATED_TOKEN_PROGRAM_ID,
     ))
   }
@@ -413,9 +466,14 @@ export async function buildFulfillListingTx(
       escrowSharesAta: escrowSharesAta,
       buyerSharesAta: buyerSharesAta,
       sharesMint: sharesMint,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenProgram,
       associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       systemProgram: web3.SystemProgram.programId,
+      usdcMint,
+      sharesTokenProgram: TOKEN_PROGRAM_ID,
+      buyerProfile: profilePda(program, 'investor_profile', buyer),
+      buyerPosition: profilePda(program, 'position', invoicePk, buyer),
+      sellerPosition: profilePda(program, 'position', invoicePk, seller),
     })
     .transaction()
   return { tx, listingPda, marketAuthority }
@@ -462,6 +520,7 @@ export async function buildCancelListingTx(
       escrowSharesAta: escrowSharesAta,
       sellerSharesAta: sellerSharesAta,
       tokenProgram: TOKEN_PROGRAM_ID,
+      sharesMint,
     })
     .transaction()
   return { tx, listingPda, marketAuthority }
@@ -497,6 +556,7 @@ export async function fundInvoiceFractional(program: Program, invoicePk: web3.Pu
   const inv: any = await (program.account as any)['invoice'].fetch(invoicePk)
   const usdcMint = new web3.PublicKey(inv.usdcMint)
   const sharesMint = new web3.PublicKey(inv.sharesMint)
+  const seller = new web3.PublicKey(inv.seller)
 
   const investor = (program.provider as AnchorProvider).wallet.publicKey
   const [escrowAuthority] = web3.PublicKey.findProgramAddressSync(
@@ -504,9 +564,11 @@ export async function fundInvoiceFractional(program: Program, invoicePk: web3.Pu
     program.programId
   )
 
-  const investorAta = await getAssociatedTokenAddress(usdcMint, investor)
-  const escrowToken = await getAssociatedTokenAddress(usdcMint, escrowAuthority, true)
-  const investorSharesAta = await getAssociatedTokenAddress(sharesMint, investor)
+  const tokenProgram = await usdcTokenProgramOf(program, usdcMint)
+  const investorAta = await getAssociatedTokenAddress(usdcMint, investor, false, tokenProgram)
+  const escrowToken = await getAssociatedTokenAddress(usdcMint, escrowAuthority, true, tokenProgram)
+  const sharesTokenProgram = sharesTokenProgramOf(inv)
+  const investorSharesAta = await getAssociatedTokenAddress(sharesMint, investor, false, sharesTokenProgram)
 
   const txSig = await (program.methods as any)
     .fundInvoiceFractional(amount)
@@ -518,9 +580,14 @@ export async function fundInvoiceFractional(program: Program, invoicePk: web3.Pu
       escrowAuthority,
       sharesMint,
       investorSharesAta,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenProgram,
       associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
       systemProgram: web3.SystemProgram.programId,
+      sharesTokenProgram,
+      usdcMint,
+      sellerProfile: profilePda(program, 'seller_profile', seller),
+      investorProfile: profilePda(program, 'investor_profile', investor),
+      position: profilePda(program, 'position', invoicePk, investor),
     })
     .rpc()
 
@@ -530,6 +597,7 @@ export async function fundInvoiceFractional(program: Program, invoicePk: web3.Pu
 export async function fundInvoice(program: Program, invoicePk: web3.PublicKey, amount: BN){
   const invoice = await fetchInvoice(program, invoicePk)
   const usdcMint = new web3.PublicKey((invoice as any).usdcMint)
+  const seller = new web3.PublicKey((invoice as any).seller)
   const investor = (program.provider as AnchorProvider).wallet.publicKey
 
   const [escrowAuthority] = web3.PublicKey.findProgramAddressSync(
@@ -537,8 +605,9 @@ export async function fundInvoice(program: Program, invoicePk: web3.PublicKey, a
     program.programId
   )
 
-  const investorAta = await getAssociatedTokenAddress(usdcMint, investor)
-  const escrowToken = await getAssociatedTokenAddress(usdcMint, escrowAuthority, true)
+  const tokenProgram = await usdcTokenProgramOf(program, usdcMint)
+  const investorAta = await getAssociatedTokenAddress(usdcMint, investor, false, tokenProgram)
+  const escrowToken = await getAssociatedTokenAddress(usdcMint, escrowAuthority, true, tokenProgram)
 
   const txSig = await (program.methods as any)
     .fundInvoice(amount)
@@ -548,7 +617,12 @@ export async function fundInvoice(program: Program, invoicePk: web3.PublicKey, a
       investorAta,
       escrowToken,
       escrowAuthority,
-      tokenProgram: TOKEN_PROGRAM_ID,
+      tokenProgram,
+      usdcMint,
+      sellerProfile: profilePda(program, 'seller_profile', seller),
+      investorProfile: profilePda(program, 'investor_profile', investor),
+      position: profilePda(program, 'position', invoicePk, investor),
+      systemProgram: web3.SystemProgram.programId,
     })
     .rpc()
 
@@ -28,6 +28,11 @@ export function initDb(customPath?: string){
       escrow_authority TEXT,
       escrow_token TEXT,
       shares_mint TEXT,
+      risk_tier INTEGER,
+      target_apr_bps INTEGER,
+      debtor TEXT,
+      acknowledged INTEGER,
+      repaid_amount TEXT,
       created_at INTEGER,
       updated_at INTEGER,
       last_sig TEXT
@@ -136,6 +141,20 @@ export function initDb(customPath?: string){
     if (!hasShares) {
       db.exec('ALTER TABLE invoices ADD COLUMN shares_mint TEXT')
     }
+    const hasRisk = Array.isArray(cols) && cols.some((c: any) => c && (c.name === 'risk_tier'))
+    if (!hasRisk) {
+      db.exec('ALTER TABLE invoices ADD COLUMN risk_tier INTEGER')
+      db.exec('ALTER TABLE invoices ADD COLUMN target_apr_bps INTEGER')
+    }
+    const hasDebtor = Array.isArray(cols) && cols.some((c: any) => c && (c.name === 'debtor'))
+    if (!hasDebtor) {
+      db.exec('ALTER TABLE invoices ADD COLUMN debtor TEXT')
+      db.exec('ALTER TABLE invoices ADD COLUMN acknowledged INTEGER')
+    }
+    const hasRepaid = Array.isArray(cols) && cols.some((c: any) => c && (c.name === 'repaid_amount'))
+    if (!hasRepaid) {
+      db.exec('ALTER TABLE invoices ADD COLUMN repaid_amount TEXT')
+    }
   } catch {}
 }
 
@@ -145,6 +164,8 @@ function getDb(): ReturnType<typeof Database> {
 }
 
 function toStatusString(status: any): string {
+  // Zero-copy invoices (version 2+) store the status as a byte
+  if (typeof status === 'number') return ['Open', 'Funded', 'Settled', 'Defaulted'][status] ?? 'unknown'
   if (!status) return 'unknown'
   const k = Object.keys(status)[0]
   // Normalize to Title case as in IDL variants
@@ -153,6 +174,7 @@ function toStatusString(status: any): string {
   if (t === 'open') return 'Open'
   if (t === 'funded') return 'Funded'
   if (t === 'settled') return 'Settled'
+  if (t === 'defaulted') return 'Defaulted'
   return k
 }
 
@@ -187,9 +209,21 @@ export async function upsertInvoiceFromChain(program: Program, invoicePk: web3.P
   const investor = new web3.PublicKey((invoice as any).investor)
   const amount = (invoice as any).amount.toString()
   const fundedAmount = (invoice as any).fundedAmount.toString()
-  const metadataHash = (invoice as any).metadataHash as string
+  const rawHash = (invoice as any).metadataHash
+  const metadataHash = typeof rawHash === 'string'
+    ? rawHash
+    : Buffer.from(rawHash).subarray(0, (invoice as any).metadataLen).toString('utf8')
   const dueDate = Number((invoice as any).dueDate)
   const status = toStatusString((invoice as any).status)
+  // 0 until the underwriter rates the invoice
+  const riskTier = Number((invoice as any).riskTier ?? 0)
+  const targetAprBps = Number((invoice as any).targetAprBps ?? 0)
+  // Default pubkey when the seller hasn't named a debtor
+  const debtorOnChain = (invoice as any).debtor ? new web3.PublicKey((invoice as any).debtor) : null
+  const debtor = debtorOnChain && !debtorOnChain.equals(web3.PublicKey.default) ? debtorOnChain.toBase58() : null
+  const acknowledged = Number((invoice as any).acknowledged ?? 0) ? 1 : 0
+  // Running total of pay_invoice deposits into the repayment vault
+  const repaidAmount = ((invoice as any).repaidAmount ?? 0).toString()
 
   const [escrowAuthority] = web3.PublicKey.findProgramAddressSync(
     [Buffer.from('escrow'), invoicePk.toBuffer()],
@@ -200,7 +234,7 @@ export async function upsertInvoiceFromChain(program: Program, invoicePk: web3.P
   const exists = db.prepare('SELECT id FROM invoices WHERE invoice_pk = ?').get(invoicePk.toBase58())
   const ts = nowMs()
   if (exists) {
-    db.prepare(`UPDATE invoices SET seller = ?, investor = ?, usdc_mint = ?, amount = ?, funded_amount = ?, status = ?, metadata_hash = ?, due_date = ?, escrow_authority = ?, escrow_token = ?, shares_mint = ?, updated_at = ?, last_sig = ? WHERE invoice_pk = ?`)
+    db.prepare(`UPDATE invoices SET seller = ?, investor = ?, usdc_mint = ?, amount = ?, funded_amount = ?, status = ?, metadata_hash = ?, due_date = ?, escrow_authority = ?, escrow_token = ?, shares_mint = ?, risk_tier = ?, target_apr_bps = ?, debtor = ?, acknowledged = ?, repaid_amount = ?, updated_at = ?, last_sig = ? WHERE invoice_pk = ?`)
       .run(
         seller.toBase58(),
         investor.toBase58(),
@@ -213,12 +247,17 @@ export async function upsertInvoiceFromChain(program: Program, invoicePk: web3.P
         escrowAuthority.toBase58(),
         escrowToken.toBase58(),
         sharesMint ? sharesMint.toBase58() : null,
+        riskTier,
+        targetAprBps,
+        debtor,
+        acknowledged,
+        repaidAmount,
         ts,
         lastSig || null,
         invoicePk.toBase58()
       )
   } else {
-    db.prepare(`INSERT INTO invoices (invoice_pk, seller, investor, usdc_mint, amount, funded_amount, status, metadata_hash, due_date, escrow_authority, escrow_token, shares_mint, created_at, updated_at, last_sig) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)`)
+    db.prepare(`INSERT INTO invoices (invoice_pk, seller, investor, usdc_mint, amount, funded_amount, status, metadata_hash, due_date, escrow_authority, escrow_token, shares_mint, risk_tier, target_apr_bps, debtor, acknowledged, repaid_amount, created_at, updated_at, last_sig) VALUES (?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?,?)`)
       .run(
         invoicePk.toBase58(),
         seller.toBase58(),
@@ -232,6 +271,11 @@ export async function upsertInvoiceFromChain(program: Program, invoicePk: web3.P
         escrowAuthority.toBase58(),
         escrowToken.toBase58(),
         sharesMint ? sharesMint.toBase58() : null,
+        riskTier,
+        targetAprBps,
+        debtor,
+        acknowledged,
+        repaidAmount,
         ts,
         ts,
         lastSig || null
@@ -4,7 +4,7 @@ import bodyParser from 'body-parser';
 import type { Request, Response } from 'express';
 import crypto from 'crypto';
 import { web3, BN } from '@coral-xyz/anchor';
-import { getProgram, fetchInvoice, settleInvoice, fundInvoice, mintInvoice as mintIx, createEscrow as createEscrowIx, initShares as initSharesIx, fundInvoiceFractional as fundInvoiceFractionalIx, buildCreateListingTx, buildFulfillListingTx, buildCancelListingTx, buildFulfillListingV2Tx, buildCreateListingV2Tx, buildCancelListingV2Tx } from './anchor';
+import { getProgram, fetchInvoice, settleInvoice, fundInvoice, mintInvoice as mintIx, createEscrow as createEscrowIx, initShares as initSharesIx, fundInvoiceFractional as fundInvoiceFractionalIx, buildCreateListingTx, buildFulfillListingTx, buildCancelListingTx, buildFulfillListingV2Tx, buildCreateListingV2Tx, buildCancelListingV2Tx, usdcTokenProgramOf } from './anchor';
 import cors from 'cors';
 import { readFileSync } from 'fs';
 import { TOKEN_PROGRAM_ID, getAssociatedTokenAddress, getMint, createApproveCheckedInstruction, createRevokeInstruction, getAccount, createAssociatedTokenAccountInstruction, ASSOCIATED_TOKEN_PROGRAM_ID } from '@solana/spl-token';
@@ -278,9 +278,10 @@ app.post('/api/listings/:id/build-revoke-usdc', async (req: Request, res: Respon
     const buyerPk = new web3.PublicKey(buyerHeader)
     const inv: any = await fetchInvoice(program, invoicePk)
     const usdcMint = new web3.PublicKey(inv.usdcMint)
-    const buyerUsdcAta = await getAssociatedTokenAddress(usdcMint, buyerPk)
+    const usdcTokenProgram = await usdcTokenProgramOf(program, usdcMint)
+    const buyerUsdcAta = await getAssociatedTokenAddress(usdcMint, buyerPk, false, usdcTokenProgram)
     const tx = new web3.Transaction()
-    tx.add(createRevokeInstruction(buyerUsdcAta, buyerPk, [], TOKEN_PROGRAM_ID))
+    tx.add(createRevokeInstruction(buyerUsdcAta, buyerPk, [], usdcTokenProgram))
     tx.feePayer = buyerPk
     tx.recentBlockhash = (await (program.provider as any).connection.getLatestBlockhash()).blockhash
     const b64 = Buffer.from(tx.serialize({ requireAllSignatures: false, verifySignatures: false })).toString('base64')
@@ -419,9 +420,10 @@ app.post('/api/listings/:id/build-approve-usdc', async (req: Request, res: Respo
     const inv: any = await fetchInvoice(program, invoicePk)
     const usdcMint = new web3.PublicKey(inv.usdcMint)
     const conn = (program.provider as any).connection as web3.Connection
-    const mintInfo = await getMint(conn, usdcMint)
+    const usdcTokenProgram = await usdcTokenProgramOf(program, usdcMint)
+    const mintInfo = await getMint(conn, usdcMint, undefined, usdcTokenProgram)
     const decimals = mintInfo.decimals
-    const buyerUsdcAta = await getAssociatedTokenAddress(usdcMint, buyerPk)
+    const buyerUsdcAta = await getAssociatedTokenAddress(usdcMint, buyerPk, false, usdcTokenProgram)
     const preIxs: web3.TransactionInstruction[] = []
     if (!(await conn.getAccountInfo(buyerUsdcAta))) {
       preIxs.push(createAssociatedTokenAccountInstruction(
@@ -429,7 +431,7 @@ app.post('/api/listings/:id/build-approve-usdc', async (req: Request, res: Respo
         buyerUsdcAta,
         buyerPk,
         usdcMint,
-        TOKEN_PROGRAM_ID,
+        usdcTokenProgram,
         ASSOCIATED_TOKEN_PROGRAM_ID,
       ))
     }
@@ -444,7 +446,7 @@ app.post('/api/listings/:id/build-approve-usdc', async (req: Request, res: Respo
       total,
       decimals,
       [],
-      TOKEN_PROGRAM_ID,
+      usdcTokenProgram,
     )
     const tx = new web3.Transaction()
     for (const ix of preIxs) tx.add(ix)
@@ -0,0 +1,401 @@
+//! Pretty-printers for `inspect` subcommands.
+
+use anchor_spl::{token, token_2022};
+use anyhow::{anyhow, bail, Result};
+use invoice_manager_client::accounts::{
+    decode_admin_config, decode_investor_attestation, decode_investor_position, decode_investor_profile, decode_invoice,
+    decode_listing, decode_pending_config_change, decode_repayment_schedule, decode_seller_profile, invoice_filters, Memcmp,
+};
+use invoice_manager_client::invoice_manager::{
+    INVARIANT_ESCROW_SHORTFALL, INVARIANT_LISTING_UNBACKED, INVARIANT_OVERFUNDED,
+    INVARIANT_SHARES_EXCEED_FUNDING, INVARIANT_STATUS, INVOICE_RISK_TIER_OFFSET, PAUSE_FUNDING, PAUSE_SETTLEMENT,
+    PAUSE_TRADING,
+};
+use invoice_manager_client::{decode_invariant_report, instructions, pda};
+use invoice_manager_client::{AdminConfig, ConfigChange, ExposureLimits, Invoice, InvoiceStatus, Listing, Pubkey, SellerStatus};
+use solana_transaction::Transaction;
+
+use crate::rpc::RpcClient;
+
+fn row(key: &str, value: impl std::fmt::Display) {
+    println!("  {key:<20} {value}");
+}
+
+fn header(title: &str, address: &Pubkey) {
+    println!("{title} {address}");
+}
+
+pub fn status_name(status: &InvoiceStatus) -> &'static str {
+    match status {
+        InvoiceStatus::Open => "Open",
+        InvoiceStatus::Funded => "Funded",
+        InvoiceStatus::Settled => "Settled",
+        InvoiceStatus::Defaulted => "Defaulted",
+    }
+}
+
+pub fn seller_status_name(status: &SellerStatus) -> &'static str {
+    match status {
+        SellerStatus::Pending => "Pending",
+        SellerStatus::Approved => "Approved",
+        SellerStatus::Suspended => "Suspended",
+    }
+}
+
+pub fn parse_seller_status(name: &str) -> Result<SellerStatus, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "pending" => Ok(SellerStatus::Pending),
+        "approved" => Ok(SellerStatus::Approved),
+        "suspended" => Ok(SellerStatus::Suspended),
+        _ => Err(format!("unknown seller status {name:?}, expected pending, approved or suspended")),
+    }
+}
+
+pub fn parse_status(name: &str) -> Result<InvoiceStatus, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "open" => Ok(InvoiceStatus::Open),
+        "funded" => Ok(InvoiceStatus::Funded),
+        "settled" => Ok(InvoiceStatus::Settled),
+        "defaulted" => Ok(InvoiceStatus::Defaulted),
+        _ => Err(format!("unknown status {name:?}, expected open, funded, settled or defaulted")),
+    }
+}
+
+pub fn describe_change(change: &ConfigChange) -> String {
+    match change {
+        ConfigChange::Admin(key) => format!("admin -> {key}"),
+        ConfigChange::Compliance(key) => format!("compliance -> {key}"),
+        ConfigChange::TimelockDelay(secs) => format!("timelock_delay -> {secs}s"),
+        ConfigChange::SettlementOracle(key) => format!("settlement_oracle -> {key}"),
+        ConfigChange::AddSettlementMint(entry) => format!(
+            "add settlement mint {} (decimals {}, min invoice {})",
+            entry.mint, entry.decimals, entry.min_invoice_amount
+        ),
+        ConfigChange::RemoveSettlementMint(mint) => format!("remove settlement mint {mint}"),
+        ConfigChange::ExposureLimits(limits) => format!("exposure limits -> {}", describe_limits(limits)),
+        ConfigChange::Underwriter(key) => format!("underwriter -> {key}"),
+    }
+}
+
+fn describe_limits(limits: &ExposureLimits) -> String {
+    let cap = |value: u64| if value == 0 { "none".to_string() } else { value.to_string() };
+    format!(
+        "position {}, share {}, seller outstanding {}",
+        cap(limits.max_investor_position),
+        if limits.max_investor_share_bps == 0 { "none".to_string() } else { format!("{}bps", limits.max_investor_share_bps) },
+        cap(limits.max_seller_outstanding)
+    )
+}
+
+pub fn describe_paused(paused: u8) -> String {
+    let names: Vec<&str> = [
+        (PAUSE_FUNDING, "funding"),
+        (PAUSE_TRADING, "trading"),
+        (PAUSE_SETTLEMENT, "settlement"),
+    ]
+    .into_iter()
+    .filter(|(bit, _)| paused & bit != 0)
+    .map(|(_, name)| name)
+    .collect();
+    if names.is_empty() {
+        format!("{paused:#04x} (none)")
+    } else {
+        format!("{paused:#04x} ({})", names.join(", "))
+    }
+}
+
+pub fn describe_violations(violations: u8) -> String {
+    let names: Vec<&str> = [
+        (INVARIANT_OVERFUNDED, "overfunded"),
+        (INVARIANT_STATUS, "status"),
+        (INVARIANT_ESCROW_SHORTFALL, "escrow-shortfall"),
+        (INVARIANT_SHARES_EXCEED_FUNDING, "shares-exceed-funding"),
+        (INVARIANT_LISTING_UNBACKED, "listing-unbacked"),
+    ]
+    .into_iter()
+    .filter(|(bit, _)| violations & bit != 0)
+    .map(|(_, name)| name)
+    .collect();
+    if names.is_empty() {
+        "none".to_string()
+    } else {
+        format!("{violations:#04x} ({})", names.join(", "))
+    }
+}
+
+fn optional_key(key: &Pubkey) -> String {
+    if *key == Pubkey::default() {
+        "-".to_string()
+    } else {
+        key.to_string()
+    }
+}
+
+fn fetch(rpc: &RpcClient, address: &Pubkey) -> Result<Vec<u8>> {
+    rpc.account_data(address)?
+        .ok_or_else(|| anyhow!("account {address} not found"))
+}
+
+pub fn fetch_invoice(rpc: &RpcClient, address: &Pubkey) -> Result<Invoice> {
+    Ok(decode_invoice(&fetch(rpc, address)?)?)
+}
+
+pub fn fetch_listing(rpc: &RpcClient, address: &Pubkey) -> Result<Listing> {
+    Ok(decode_listing(&fetch(rpc, address)?)?)
+}
+
+pub fn fetch_config(rpc: &RpcClient) -> Result<AdminConfig> {
+    Ok(decode_admin_config(&fetch(rpc, &pda::config().0)?)?)
+}
+
+pub fn invoice(rpc: &RpcClient, address: &Pubkey) -> Result<()> {
+    let invoice = fetch_invoice(rpc, address)?;
+    header("Invoice", address);
+    row("seller", invoice.seller);
+    row("amount", invoice.amount);
+    row("metadata_hash", invoice.metadata_hash());
+    row("due_date", invoice.due_date);
+    row("status", invoice.status().map_or("Unknown", |status| status_name(&status)));
+    row("investor", optional_key(&invoice.investor));
+    row("funded_amount", invoice.funded_amount);
+    row("repaid_amount", invoice.repaid_amount);
+    row("investors_claimed", invoice.investors_claimed);
+    row("seller_claimed", invoice.seller_claimed);
+    row("refunded_amount", invoice.refunded_amount);
+    row("usdc_mint", invoice.usdc_mint);
+    row("shares_mint", optional_key(&invoice.shares_mint));
+    row("shares_restricted", invoice.shares_restricted != 0);
+    row("frozen", invoice.is_frozen());
+    row("kyc_required", invoice.kyc_required != 0);
+    row("debtor", optional_key(&invoice.debtor));
+    row("acknowledged", invoice.acknowledged != 0);
+    row("acknowledgement_required", invoice.acknowledgement_required != 0);
+    row("risk_tier", if invoice.risk_tier == 0 { "unrated".to_string() } else { invoice.risk_tier.to_string() });
+    row("target_apr_bps", invoice.target_apr_bps);
+    row("seq", invoice.seq);
+    row("version", invoice.version);
+    println!("Derived");
+    let (authority, bump) = pda::escrow_authority(address);
+    row("escrow_authority", format!("{authority} (bump {bump})"));
+    row("escrow_token", pda::escrow_token(address, &invoice.usdc_mint));
+    row("repayment_vault", pda::repayment_vault(address).0);
+    let schedule_address = pda::repayment_schedule(address).0;
+    if let Some(data) = rpc.account_data(&schedule_address)? {
+        let schedule = decode_repayment_schedule(&data)?;
+        header("RepaymentSchedule", &schedule_address);
+        let now = crate::unix_now()?;
+        for (index, installment) in schedule.installments().iter().enumerate() {
+            let state = if installment.repaid == installment.amount {
+                "paid"
+            } else if installment.is_overdue(now) {
+                "overdue"
+            } else {
+                "due"
+            };
+            row(
+                &format!("installment {index}"),
+                format!("due {} amount {} repaid {} ({state})", installment.due_date, installment.amount, installment.repaid),
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Lists invoices with a single filtered `getProgramAccounts` call
+pub fn invoices(rpc: &RpcClient, status: Option<InvoiceStatus>, usdc_mint: Option<Pubkey>, risk_tier: Option<u8>) -> Result<()> {
+    let mut filters = invoice_filters(status, usdc_mint.as_ref());
+    if let Some(tier) = risk_tier {
+        filters.push(Memcmp { offset: INVOICE_RISK_TIER_OFFSET, bytes: vec![tier] });
+    }
+    let mut accounts = rpc.program_accounts(&invoice_manager_client::PROGRAM_ID, Invoice::SPACE, &filters)?;
+    accounts.sort_by_key(|(address, _)| *address);
+    println!("{:<44} {:<9} {:>16} {:>16} {:>12} {:>4} {:>6}", "invoice", "status", "amount", "funded", "due_date", "tier", "apr");
+    for (address, data) in &accounts {
+        let invoice = decode_invoice(data)?;
+        let status = invoice.status().map_or("Unknown", |status| status_name(&status));
+        println!(
+            "{:<44} {:<9} {:>16} {:>16} {:>12} {:>4} {:>6}",
+            address.to_string(), status, invoice.amount, invoice.funded_amount, invoice.due_date,
+            invoice.risk_tier, invoice.target_apr_bps
+        );
+    }
+    println!("{} invoice(s)", accounts.len());
+    Ok(())
+}
+
+pub fn listing(rpc: &RpcClient, address: &Pubkey) -> Result<()> {
+    let listing = fetch_listing(rpc, address)?;
+    header("Listing", address);
+    row("invoice", listing.invoice);
+    row("seller", listing.seller);
+    row("shares_mint", listing.shares_mint);
+    row("usdc_mint", listing.usdc_mint);
+    row("price", listing.price);
+    row("remaining_qty", listing.remaining_qty);
+    row("bump", listing.bump);
+    row("market_bump", listing.market_bump);
+    row("seq", listing.seq);
+    row("version", listing.version);
+    println!("Derived");
+    row("market_authority", pda::market_authority(address).0);
+    row("escrow_shares (v1)", pda::escrow_shares(address, &listing.shares_mint));
+    row("seller_shares_ata", pda::ata(&listing.seller, &listing.shares_mint));
+    Ok(())
+}
+
+/// Token program owning `mint`: spl-token, or Token-2022 for extension mints.
+pub fn token_program_of(rpc: &RpcClient, mint: &Pubkey) -> Result<Pubkey> {
+    match rpc.account_owner(mint)? {
+        Some(owner) if owner == token::ID || owner == token_2022::ID => Ok(owner),
+        Some(owner) => bail!("{mint} is owned by {owner}, not a token program"),
+        None => bail!("mint {mint} not found"),
+    }
+}
+
+/// Simulates `verify_invariants` for `address` and the listings of `sellers`.
+/// The invoice's seller is used as fee payer, so no keypair is needed.
+pub fn invariants(rpc: &RpcClient, address: &Pubkey, sellers: &[Pubkey]) -> Result<bool> {
+    let invoice = fetch_invoice(rpc, address)?;
+    let shares_mint = (invoice.shares_mint != Pubkey::default()).then_some(invoice.shares_mint);
+    let shares_token_program = if invoice.shares_restricted != 0 { token_2022::ID } else { token::ID };
+    let mut listings = Vec::new();
+    for seller in sellers {
+        let listing = pda::listing(address, seller).0;
+        // V1 listings hold their shares in a market escrow; V2 ones never create it
+        let escrow = pda::escrow_shares(&listing, &invoice.shares_mint);
+        let backing = match rpc.account_data(&escrow)? {
+            Some(_) => escrow,
+            None => pda::ata_with_program(seller, &invoice.shares_mint, &shares_token_program),
+        };
+        listings.push((listing, backing));
+    }
+    let token_program = token_program_of(rpc, &invoice.usdc_mint)?;
+    let ix = instructions::verify_invariants(*address, invoice.usdc_mint, token_program, shares_mint, &listings);
+    let tx = Transaction::new_with_payer(&[ix], Some(&invoice.seller));
+    let report = decode_invariant_report(&rpc.simulate_return_data(&tx)?)?;
+
+    header("Invariants", address);
+    row("status", status_name(&report.status));
+    row("amount", report.amount);
+    row("funded_amount", report.funded_amount);
+    row("escrow_balance", report.escrow_balance);
+    row("shares_supply", report.shares_supply);
+    row("listings_checked", report.listings_checked);
+    row("listed_qty", report.listed_qty);
+    row("violations", describe_violations(report.violations));
+    Ok(report.violations == 0)
+}
+
+pub fn attestation(rpc: &RpcClient, investor: &Pubkey) -> Result<()> {
+    let address = pda::attestation(investor).0;
+    let attestation = decode_investor_attestation(&fetch(rpc, &address)?)?;
+    header("InvestorAttestation", &address);
+    row("investor", attestation.investor);
+    row("issuer", attestation.issuer);
+    row("jurisdiction", String::from_utf8_lossy(&attestation.jurisdiction));
+    row("issued_at", attestation.issued_at);
+    row("expires_at", attestation.expires_at);
+    row("version", attestation.version);
+    Ok(())
+}
+
+/// Exposure counters of a wallet, as a seller and as an investor, plus its
+/// position in `invoice` when given. Profiles that don't exist yet are skipped.
+pub fn profile(rpc: &RpcClient, wallet: &Pubkey, invoice: Option<&Pubkey>) -> Result<()> {
+    let address = pda::seller_profile(wallet).0;
+    match rpc.account_data(&address)? {
+        Some(data) => {
+            let profile = decode_seller_profile(&data)?;
+            header("SellerProfile", &address);
+            row("status", seller_status_name(&profile.status));
+            row("credit_limit", profile.credit_limit);
+            row("outstanding", profile.outstanding);
+            row("total_issued", profile.total_issued);
+            row("total_funded", profile.total_funded);
+            row("total_repaid", profile.total_repaid);
+            row("total_defaulted", profile.total_defaulted);
+        }
+        None => println!("No seller profile"),
+    }
+    let address = pda::investor_profile(wallet).0;
+    match rpc.account_data(&address)? {
+        Some(data) => {
+            let profile = decode_investor_profile(&data)?;
+            header("InvestorProfile", &address);
+            row("total_funded", profile.total_funded);
+            row("total_purchased", profile.total_purchased);
+        }
+        None => println!("No investor profile"),
+    }
+    if let Some(invoice) = invoice {
+        let address = pda::position(invoice, wallet).0;
+        match rpc.account_data(&address)? {
+            Some(data) => {
+                let position = decode_investor_position(&data)?;
+                header("InvestorPosition", &address);
+                row("invoice", position.invoice);
+                row("amount", position.amount);
+                row("claim_basis", position.claim_basis);
+                row("claimed_repayment", position.claimed_repayment);
+                row("refunded", position.refunded);
+            }
+            None => println!("No position in {invoice}"),
+        }
+    }
+    Ok(())
+}
+
+pub fn config(rpc: &RpcClient) -> Result<()> {
+    let config = fetch_config(rpc)?;
+    header("AdminConfig", &pda::config().0);
+    row("admin", config.admin);
+    row("pending_admin", optional_key(&config.pending_admin));
+    row("deployer", config.deployer);
+    row("paused", describe_paused(config.paused));
+    row("compliance", optional_key(&config.compliance));
+    row("underwriter", optional_key(&config.underwriter));
+    row("timelock_delay", format!("{}s", config.timelock_delay));
+    row("settlement_oracle", optional_key(&config.settlement_oracle));
+    row("kyc_required", config.kyc_required != 0);
+    row("version", config.version);
+    row("exposure_limits", describe_limits(&config.exposure_limits));
+    println!("Settlement mints");
+    for entry in config.settlement_mints() {
+        row(&entry.mint.to_string(), format!("decimals {}, min invoice {}", entry.decimals, entry.min_invoice_amount));
+    }
+
+    let change_address = pda::config_change().0;
+    match rpc.account_data(&change_address)? {
+        Some(data) => {
+            let pending = decode_pending_config_change(&data)?;
+            header("PendingConfigChange", &change_address);
+            row("change", describe_change(&pending.change));
+            row("proposer", pending.proposer);
+            row("queued_at", pending.queued_at);
+            row("eta", pending.eta);
+        }
+        None => println!("No pending config change"),
+    }
+    Ok(())
+}
+
+pub fn pdas(invoice: Option<Pubkey>, seller: Option<Pubkey>, usdc_mint: Option<Pubkey>) {
+    println!("Program {}", invoice_manager_client::PROGRAM_ID);
+    row("config", pda::config().0);
+    row("config_change", pda::config_change().0);
+    row("program_data", pda::program_data());
+    if let Some(invoice) = invoice {
+        println!("Invoice {invoice}");
+        row("escrow_authority", pda::escrow_authority(&invoice).0);
+        row("repayment_vault", pda::repayment_vault(&invoice).0);
+        row("repayment_schedule", pda::repayment_schedule(&invoice).0);
+        if let Some(usdc_mint) = usdc_mint {
+            row("escrow_token", pda::escrow_token(&invoice, &usdc_mint));
+        }
+        if let Some(seller) = seller {
+            let listing = pda::listing(&invoice, &seller).0;
+            row("listing", listing);
+            row("market_authority", pda::market_authority(&listing).0);
+        }
+    }
+}
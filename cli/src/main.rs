@@ -0,0 +1,758 @@
+//! `invoice-cli`: operator tool for the `invoice_manager` program.
+//!
+//! Builds instructions with `invoice_manager_client`, signs them with a local keypair
+//! file and sends them to any JSON-RPC endpoint. Amounts, prices and quantities are
+//! 6-decimal base units, as on-chain.
+
+mod inspect;
+mod rpc;
+
+use std::path::PathBuf;
+
+use anchor_spl::token_2022::spl_token_2022;
+use anchor_spl::{token, token_2022};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use invoice_manager_client::events::parse_logs;
+use invoice_manager_client::invoice_manager::{PAUSE_FUNDING, PAUSE_SETTLEMENT, PAUSE_TRADING};
+use invoice_manager_client::{
+    instructions, pda, ConfigChange, ExposureLimits, InstallmentTerms, Instruction, InvoiceStatus, Pubkey, SellerStatus,
+    SettlementAttestation, SettlementMint,
+};
+use solana_keypair::{read_keypair_file, Keypair};
+use solana_signer::Signer;
+use solana_transaction::Transaction;
+
+use crate::rpc::RpcClient;
+
+#[derive(Parser)]
+#[command(name = "invoice-cli", version, about = "Operate the invoice_manager program")]
+struct Cli {
+    /// JSON-RPC endpoint
+    #[arg(long, short = 'u', global = true, env = "CLUSTER_URL", default_value = "https://api.devnet.solana.com")]
+    url: String,
+    /// Fee payer and signer keypair [default: ~/.config/solana/id.json]
+    #[arg(long, short = 'k', global = true, env = "KEYPAIR")]
+    keypair: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Create a new invoice owned by the signer
+    Mint {
+        #[arg(long)]
+        usdc_mint: Pubkey,
+        /// Face amount
+        #[arg(long)]
+        amount: u64,
+        /// Due date, unix seconds
+        #[arg(long)]
+        due_date: i64,
+        #[arg(long)]
+        metadata_hash: String,
+    },
+    /// Create the invoice's USDC escrow (seller only)
+    Escrow {
+        #[arg(long)]
+        invoice: Pubkey,
+    },
+    /// Create the invoice's fractional shares mint
+    Shares {
+        #[arg(long)]
+        invoice: Pubkey,
+        /// Token-2022 mint whose transfer hook only admits attested investors (V2 market only)
+        #[arg(long)]
+        restricted: bool,
+    },
+    /// Name the invoice's debtor (seller)
+    Debtor {
+        #[arg(long)]
+        invoice: Pubkey,
+        #[arg(long)]
+        debtor: Pubkey,
+        /// Block funding until the debtor acknowledges
+        #[arg(long)]
+        require_ack: bool,
+    },
+    /// Confirm an invoice's amount and due date as its debtor
+    Acknowledge {
+        #[arg(long)]
+        invoice: Pubkey,
+        #[arg(long)]
+        amount: u64,
+        #[arg(long)]
+        due_date: i64,
+    },
+    /// Rate an invoice before it is funded (underwriter)
+    Rate {
+        #[arg(long)]
+        invoice: Pubkey,
+        /// 1 (lowest risk) to 5
+        #[arg(long)]
+        tier: u8,
+        /// Expected annualized yield, basis points
+        #[arg(long)]
+        apr_bps: u16,
+    },
+    /// Fund an invoice from the signer's USDC account
+    Fund {
+        #[arg(long)]
+        invoice: Pubkey,
+        #[arg(long)]
+        amount: u64,
+        /// Mint shares 1:1 instead of taking the invoice whole
+        #[arg(long)]
+        fractional: bool,
+    },
+    /// List shares for sale
+    List {
+        #[arg(long)]
+        invoice: Pubkey,
+        #[arg(long)]
+        qty: u64,
+        /// USDC per share
+        #[arg(long)]
+        price: u64,
+        #[command(flatten)]
+        market: Market,
+    },
+    /// Buy shares from a listing
+    Fulfill {
+        #[arg(long)]
+        invoice: Pubkey,
+        #[arg(long)]
+        seller: Pubkey,
+        #[arg(long)]
+        qty: u64,
+        #[command(flatten)]
+        market: Market,
+    },
+    /// Close the signer's listing
+    Cancel {
+        #[arg(long)]
+        invoice: Pubkey,
+        #[command(flatten)]
+        market: Market,
+    },
+    /// Settle a funded invoice, releasing the escrow to the seller
+    Settle {
+        #[arg(long)]
+        invoice: Pubkey,
+        /// Oracle keypair signing an attestation; without it the signer must be the admin
+        #[arg(long, requires = "payment_ref")]
+        oracle_keypair: Option<PathBuf>,
+        /// 32-byte payment reference, hex
+        #[arg(long)]
+        payment_ref: Option<String>,
+    },
+    /// Write off a funded invoice past its due date (admin)
+    Default {
+        #[arg(long)]
+        invoice: Pubkey,
+    },
+    /// Refund the signer's funding out of a defaulted invoice's escrow, burning its shares
+    Refund {
+        #[arg(long)]
+        invoice: Pubkey,
+    },
+    /// Repay a funded invoice from the signer's USDC account; settles it at face value
+    Pay {
+        #[arg(long)]
+        invoice: Pubkey,
+        #[arg(long)]
+        amount: u64,
+    },
+    /// Split repayment into installments before funding (seller)
+    Schedule {
+        #[arg(long)]
+        invoice: Pubkey,
+        /// DUE_DATE:AMOUNT, repeated in due date order; amounts must sum to the face value
+        #[arg(long = "installment", required = true)]
+        installments: Vec<String>,
+    },
+    /// Flag installments past their due date that aren't fully repaid
+    FlagOverdue {
+        #[arg(long)]
+        invoice: Pubkey,
+    },
+    /// Withdraw the signer's share of the repayments received so far, burning all of its
+    /// shares (they can't be traded afterwards)
+    Claim {
+        #[arg(long)]
+        invoice: Pubkey,
+    },
+    /// Upgrade an invoice account to the current layout, paying any extra rent
+    Migrate {
+        #[arg(long)]
+        invoice: Pubkey,
+    },
+    /// Upgrade a listing account to the current layout, paying any extra rent
+    MigrateListing {
+        #[arg(long)]
+        listing: Pubkey,
+    },
+    /// Admin configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+    /// Decode and print program accounts
+    #[command(subcommand)]
+    Inspect(InspectCommand),
+}
+
+#[derive(Args)]
+struct Market {
+    /// Use the V1 escrow marketplace instead of the V2 delegate marketplace
+    #[arg(long)]
+    v1: bool,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Create the config; the signer must be the program's upgrade authority
+    Init {
+        /// Initial admin [default: signer]
+        #[arg(long)]
+        admin: Option<Pubkey>,
+    },
+    /// Queue a timelocked admin rotation
+    QueueAdmin { key: Pubkey },
+    /// Queue a timelocked compliance change
+    QueueCompliance { key: Pubkey },
+    /// Queue a timelocked underwriter change
+    QueueUnderwriter { key: Pubkey },
+    /// Queue a timelocked settlement oracle change
+    QueueOracle { key: Pubkey },
+    /// Queue a timelocked change of the timelock delay
+    QueueTimelock { seconds: i64 },
+    /// Queue listing (or updating) an accepted settlement mint
+    QueueAddMint {
+        mint: Pubkey,
+        #[arg(long)]
+        decimals: u8,
+        /// Smallest invoice amount accepted in this mint, base units
+        #[arg(long, default_value_t = 0)]
+        min_invoice_amount: u64,
+    },
+    /// Queue removing an accepted settlement mint
+    QueueRemoveMint { mint: Pubkey },
+    /// Queue new exposure limits; omitted or 0 disables a cap
+    QueueLimits {
+        /// Most one investor may hold in a single invoice, base units
+        #[arg(long, default_value_t = 0)]
+        max_position: u64,
+        /// Largest fraction of an invoice one investor may hold, basis points
+        #[arg(long, default_value_t = 0)]
+        max_share_bps: u16,
+        /// Most financing a seller may have outstanding, base units
+        #[arg(long, default_value_t = 0)]
+        max_seller_outstanding: u64,
+    },
+    /// Apply the pending change once its eta has passed
+    Execute,
+    /// Drop the pending change (admin)
+    Cancel,
+    /// Accept a pending admin rotation (pending admin)
+    AcceptAdmin,
+    /// Drop a pending admin rotation (admin)
+    CancelAdminProposal,
+    /// Grow a config created before the settlement mint allow-list, paying any extra rent
+    Migrate,
+    /// Set the pause flags: comma-separated funding, trading, settlement, all or none
+    Pause { flags: String },
+    /// Freeze or unfreeze an invoice (compliance)
+    Freeze {
+        #[arg(long)]
+        invoice: Pubkey,
+        #[arg(long)]
+        unfreeze: bool,
+    },
+    /// Issue or renew an investor's KYC attestation (compliance)
+    Attest {
+        investor: Pubkey,
+        /// ISO 3166-1 alpha-2 country code, e.g. US
+        #[arg(long)]
+        jurisdiction: String,
+        /// Unix timestamp the attestation expires at
+        #[arg(long)]
+        expires_at: i64,
+    },
+    /// Onboard a seller or change its status and credit line (admin or compliance)
+    Seller {
+        seller: Pubkey,
+        /// pending, approved or suspended
+        #[arg(long, value_parser = inspect::parse_seller_status)]
+        status: SellerStatus,
+        /// Most financing the seller may have outstanding at mint time, base units
+        #[arg(long)]
+        credit_limit: u64,
+    },
+    /// Revoke an investor's KYC attestation (compliance)
+    RevokeAttestation { investor: Pubkey },
+    /// Require KYC attestations globally, or for one invoice with --invoice (compliance)
+    Kyc {
+        #[arg(long)]
+        invoice: Option<Pubkey>,
+        /// Turn the requirement off instead
+        #[arg(long)]
+        off: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum InspectCommand {
+    Invoice { address: Pubkey },
+    /// List invoices in the fixed-size layout, filtered server-side
+    Invoices {
+        /// open, funded, settled or defaulted
+        #[arg(long, value_parser = inspect::parse_status)]
+        status: Option<InvoiceStatus>,
+        #[arg(long)]
+        usdc_mint: Option<Pubkey>,
+        /// Only invoices rated this tier (0 for unrated)
+        #[arg(long)]
+        risk_tier: Option<u8>,
+    },
+    Listing {
+        /// Listing address, or `--invoice` and `--seller` to derive it
+        address: Option<Pubkey>,
+        #[arg(long, requires = "seller", conflicts_with = "address")]
+        invoice: Option<Pubkey>,
+        #[arg(long)]
+        seller: Option<Pubkey>,
+    },
+    Config,
+    /// An investor's KYC attestation
+    Attestation { investor: Pubkey },
+    /// Exposure counters of a seller or investor, and its position in `--invoice`
+    Profile {
+        wallet: Pubkey,
+        #[arg(long)]
+        invoice: Option<Pubkey>,
+    },
+    /// Simulate verify_invariants; exits non-zero if any invariant is broken
+    Invariants {
+        invoice: Pubkey,
+        /// Seller whose listing on the invoice is checked too (repeatable)
+        #[arg(long = "seller")]
+        sellers: Vec<Pubkey>,
+    },
+    /// Print derived addresses without touching the network
+    Pdas {
+        #[arg(long)]
+        invoice: Option<Pubkey>,
+        #[arg(long)]
+        seller: Option<Pubkey>,
+        #[arg(long)]
+        usdc_mint: Option<Pubkey>,
+    },
+}
+
+struct Session {
+    rpc: RpcClient,
+    payer: Keypair,
+}
+
+impl Session {
+    fn send(&self, ixs: &[Instruction], extra_signers: &[&Keypair]) -> Result<()> {
+        let mut signers: Vec<&Keypair> = vec![&self.payer];
+        signers.extend_from_slice(extra_signers);
+        let blockhash = self.rpc.latest_blockhash()?;
+        let tx = Transaction::new_signed_with_payer(ixs, Some(&self.payer.pubkey()), &signers, blockhash);
+        let signature = self.rpc.send_and_confirm(&tx)?;
+        println!("Signature {signature}");
+        for event in parse_logs(&self.rpc.transaction_logs(&signature)?) {
+            println!("  event {}", event.name());
+        }
+        Ok(())
+    }
+
+    fn signer(&self) -> Pubkey {
+        self.payer.pubkey()
+    }
+}
+
+fn default_keypair_path() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME").ok_or_else(|| anyhow!("HOME not set; pass --keypair"))?;
+    Ok(PathBuf::from(home).join(".config/solana/id.json"))
+}
+
+fn load_keypair(path: &PathBuf) -> Result<Keypair> {
+    read_keypair_file(path).map_err(|e| anyhow!("reading keypair {}: {e}", path.display()))
+}
+
+fn parse_pause_flags(flags: &str) -> Result<u8> {
+    flags.split(',').map(str::trim).try_fold(0u8, |acc, flag| {
+        Ok(acc
+            | match flag {
+                "funding" => PAUSE_FUNDING,
+                "trading" => PAUSE_TRADING,
+                "settlement" => PAUSE_SETTLEMENT,
+                "all" => PAUSE_FUNDING | PAUSE_TRADING | PAUSE_SETTLEMENT,
+                "none" | "" => 0,
+                other => bail!("unknown pause flag `{other}`"),
+            })
+    })
+}
+
+fn parse_payment_ref(hex: &str) -> Result<[u8; 32]> {
+    let hex = hex.trim_start_matches("0x");
+    if hex.len() != 64 {
+        bail!("payment reference must be 32 bytes (64 hex characters)");
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).context("invalid hex in payment reference")?;
+    }
+    Ok(out)
+}
+
+fn parse_jurisdiction(code: &str) -> Result<[u8; 2]> {
+    let code = code.to_ascii_uppercase();
+    match code.as_bytes() {
+        &[a, b] if a.is_ascii_uppercase() && b.is_ascii_uppercase() => Ok([a, b]),
+        _ => bail!("jurisdiction must be a two-letter country code"),
+    }
+}
+
+fn parse_installment(term: &str) -> Result<InstallmentTerms> {
+    let (due_date, amount) = term.split_once(':').context("installment must be DUE_DATE:AMOUNT")?;
+    Ok(InstallmentTerms {
+        due_date: due_date.trim().parse().context("invalid installment due date")?,
+        amount: amount.trim().parse().context("invalid installment amount")?,
+    })
+}
+
+fn unix_now() -> Result<i64> {
+    Ok(std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs() as i64)
+}
+
+/// Returns the invoice's shares mint, its USDC mint and the shares mint's token program.
+fn shares_mint_of(ctx: &Session, invoice: &Pubkey) -> Result<(Pubkey, Pubkey, Pubkey)> {
+    let invoice = inspect::fetch_invoice(&ctx.rpc, invoice)?;
+    if invoice.shares_mint == Pubkey::default() {
+        bail!("invoice has no shares mint; run `invoice-cli shares` first");
+    }
+    let shares_token_program = if invoice.shares_restricted != 0 { token_2022::ID } else { token::ID };
+    Ok((invoice.shares_mint, invoice.usdc_mint, shares_token_program))
+}
+
+/// The invoice's shares mint and its token program when `owner` has a shares
+/// account, whose whole balance `claim_repayment` and `refund_defaulted` burn.
+fn held_shares(ctx: &Session, invoice: &Pubkey, owner: &Pubkey) -> Result<Option<(Pubkey, Pubkey)>> {
+    let invoice = inspect::fetch_invoice(&ctx.rpc, invoice)?;
+    if invoice.shares_mint == Pubkey::default() {
+        return Ok(None);
+    }
+    let shares_token_program = if invoice.shares_restricted != 0 { token_2022::ID } else { token::ID };
+    let holding = pda::ata_with_program(owner, &invoice.shares_mint, &shares_token_program);
+    Ok(ctx.rpc.account_data(&holding)?.map(|_| (invoice.shares_mint, shares_token_program)))
+}
+
+fn require_v2_for_restricted(shares_token_program: &Pubkey) -> Result<()> {
+    if *shares_token_program == token_2022::ID {
+        bail!("restricted shares trade on the V2 market only; drop --v1");
+    }
+    Ok(())
+}
+
+fn run(cli: Cli) -> Result<()> {
+    let rpc = RpcClient::new(cli.url);
+
+    if let Command::Inspect(cmd) = cli.command {
+        return match cmd {
+            InspectCommand::Invoice { address } => inspect::invoice(&rpc, &address),
+            InspectCommand::Invoices { status, usdc_mint, risk_tier } => inspect::invoices(&rpc, status, usdc_mint, risk_tier),
+            InspectCommand::Listing { address, invoice, seller } => {
+                let address = match (address, invoice, seller) {
+                    (Some(address), _, _) => address,
+                    (None, Some(invoice), Some(seller)) => pda::listing(&invoice, &seller).0,
+                    _ => bail!("pass a listing address or --invoice and --seller"),
+                };
+                inspect::listing(&rpc, &address)
+            }
+            InspectCommand::Config => inspect::config(&rpc),
+            InspectCommand::Attestation { investor } => inspect::attestation(&rpc, &investor),
+            InspectCommand::Profile { wallet, invoice } => inspect::profile(&rpc, &wallet, invoice.as_ref()),
+            InspectCommand::Invariants { invoice, sellers } => {
+                if !inspect::invariants(&rpc, &invoice, &sellers)? {
+                    std::process::exit(2);
+                }
+                Ok(())
+            }
+            InspectCommand::Pdas { invoice, seller, usdc_mint } => {
+                inspect::pdas(invoice, seller, usdc_mint);
+                Ok(())
+            }
+        };
+    }
+
+    let keypair_path = match cli.keypair {
+        Some(path) => path,
+        None => default_keypair_path()?,
+    };
+    let ctx = Session { rpc, payer: load_keypair(&keypair_path)? };
+    let me = ctx.signer();
+
+    match cli.command {
+        Command::Mint { usdc_mint, amount, due_date, metadata_hash } => {
+            let invoice = Keypair::new();
+            println!("Invoice {}", invoice.pubkey());
+            let ix = instructions::mint_invoice(invoice.pubkey(), me, usdc_mint, metadata_hash, amount, due_date);
+            ctx.send(&[ix], &[&invoice])
+        }
+        Command::Escrow { invoice } => {
+            let usdc_mint = inspect::fetch_invoice(&ctx.rpc, &invoice)?.usdc_mint;
+            let token_program = inspect::token_program_of(&ctx.rpc, &usdc_mint)?;
+            ctx.send(&[instructions::create_escrow(invoice, me, usdc_mint, token_program)], &[])
+        }
+        Command::Shares { invoice, restricted } => {
+            let shares_mint = Keypair::new();
+            println!("Shares mint {}", shares_mint.pubkey());
+            if restricted {
+                let ixs = [
+                    instructions::init_shares_restricted(invoice, me, shares_mint.pubkey()),
+                    instructions::init_extra_account_meta_list(me, shares_mint.pubkey()),
+                ];
+                return ctx.send(&ixs, &[&shares_mint]);
+            }
+            ctx.send(&[instructions::init_shares(invoice, me, shares_mint.pubkey())], &[&shares_mint])
+        }
+        Command::Fund { invoice, amount, fractional } => {
+            let seller = inspect::fetch_invoice(&ctx.rpc, &invoice)?.seller;
+            let ix = if fractional {
+                let (shares_mint, usdc_mint, shares_token_program) = shares_mint_of(&ctx, &invoice)?;
+                let token_program = inspect::token_program_of(&ctx.rpc, &usdc_mint)?;
+                instructions::fund_invoice_fractional(
+                    invoice,
+                    me,
+                    seller,
+                    usdc_mint,
+                    token_program,
+                    shares_mint,
+                    shares_token_program,
+                    amount,
+                )
+            } else {
+                let usdc_mint = inspect::fetch_invoice(&ctx.rpc, &invoice)?.usdc_mint;
+                let token_program = inspect::token_program_of(&ctx.rpc, &usdc_mint)?;
+                instructions::fund_invoice(invoice, me, seller, usdc_mint, token_program, amount)
+            };
+            ctx.send(&[ix], &[])
+        }
+        Command::List { invoice, qty, price, market } => {
+            let (shares_mint, usdc_mint, shares_token_program) = shares_mint_of(&ctx, &invoice)?;
+            if market.v1 {
+                require_v2_for_restricted(&shares_token_program)?;
+                let ix = instructions::create_listing(invoice, me, shares_mint, usdc_mint, qty, price);
+                return ctx.send(&[ix], &[]);
+            }
+            // V2 leaves shares in the seller's account, delegated to the market authority.
+            let listing = pda::listing(&invoice, &me).0;
+            let approve = spl_token_2022::instruction::approve(
+                &shares_token_program,
+                &pda::ata_with_program(&me, &shares_mint, &shares_token_program),
+                &pda::market_authority(&listing).0,
+                &me,
+                &[],
+                qty,
+            )?;
+            let ix = instructions::create_listing_v2(invoice, me, shares_mint, usdc_mint, qty, price);
+            ctx.send(&[approve, ix], &[])
+        }
+        Command::Fulfill { invoice, seller, qty, market } => {
+            let listing_address = pda::listing(&invoice, &seller).0;
+            let listing = inspect::fetch_listing(&ctx.rpc, &listing_address)?;
+            let (_, _, shares_token_program) = shares_mint_of(&ctx, &invoice)?;
+            let token_program = inspect::token_program_of(&ctx.rpc, &listing.usdc_mint)?;
+            if market.v1 {
+                require_v2_for_restricted(&shares_token_program)?;
+                let ix = instructions::fulfill_listing(
+                    invoice,
+                    seller,
+                    me,
+                    listing.shares_mint,
+                    listing.usdc_mint,
+                    token_program,
+                    qty,
+                );
+                return ctx.send(&[ix], &[]);
+            }
+            // Same rounding as the program: qty and price both carry 6 decimals.
+            let total = qty
+                .checked_mul(listing.price)
+                .map(|raw| raw / 1_000_000)
+                .ok_or_else(|| anyhow!("qty * price overflows"))?;
+            let approve = spl_token_2022::instruction::approve(
+                &token_program,
+                &pda::ata_with_program(&me, &listing.usdc_mint, &token_program),
+                &pda::market_authority(&listing_address).0,
+                &me,
+                &[],
+                total,
+            )?;
+            let ix = instructions::fulfill_listing_v2(
+                invoice,
+                seller,
+                me,
+                listing.shares_mint,
+                shares_token_program,
+                listing.usdc_mint,
+                token_program,
+                qty,
+            );
+            ctx.send(&[approve, ix], &[])
+        }
+        Command::Cancel { invoice, market } => {
+            let (shares_mint, _, shares_token_program) = shares_mint_of(&ctx, &invoice)?;
+            let ix = if market.v1 {
+                require_v2_for_restricted(&shares_token_program)?;
+                instructions::cancel_listing(invoice, me, shares_mint)
+            } else {
+                instructions::cancel_listing_v2(invoice, me, shares_mint, shares_token_program)
+            };
+            ctx.send(&[ix], &[])
+        }
+        Command::Pay { invoice, amount } => {
+            let state = inspect::fetch_invoice(&ctx.rpc, &invoice)?;
+            let token_program = inspect::token_program_of(&ctx.rpc, &state.usdc_mint)?;
+            ctx.send(&[instructions::pay_invoice(invoice, me, state.seller, state.usdc_mint, token_program, amount)], &[])
+        }
+        Command::Schedule { invoice, installments } => {
+            let installments = installments.iter().map(|term| parse_installment(term)).collect::<Result<Vec<_>>>()?;
+            ctx.send(&[instructions::set_repayment_schedule(invoice, me, installments)], &[])
+        }
+        Command::FlagOverdue { invoice } => ctx.send(&[instructions::flag_overdue_installments(invoice)], &[]),
+        Command::Claim { invoice } => {
+            let state = inspect::fetch_invoice(&ctx.rpc, &invoice)?;
+            let token_program = inspect::token_program_of(&ctx.rpc, &state.usdc_mint)?;
+            let shares = held_shares(&ctx, &invoice, &me)?;
+            ctx.send(&[instructions::claim_repayment(invoice, me, state.usdc_mint, token_program, shares)], &[])
+        }
+        Command::Refund { invoice } => {
+            let state = inspect::fetch_invoice(&ctx.rpc, &invoice)?;
+            let token_program = inspect::token_program_of(&ctx.rpc, &state.usdc_mint)?;
+            let shares = held_shares(&ctx, &invoice, &me)?;
+            ctx.send(&[instructions::refund_defaulted(invoice, me, state.usdc_mint, token_program, shares)], &[])
+        }
+        Command::Default { invoice } => {
+            let state = inspect::fetch_invoice(&ctx.rpc, &invoice)?;
+            ctx.send(&[instructions::mark_defaulted(invoice, me, state.seller)], &[])
+        }
+        Command::Settle { invoice, oracle_keypair, payment_ref } => {
+            let state = inspect::fetch_invoice(&ctx.rpc, &invoice)?;
+            let amount = state.funded_amount;
+            let token_program = inspect::token_program_of(&ctx.rpc, &state.usdc_mint)?;
+            match (oracle_keypair, payment_ref) {
+                (Some(path), Some(payment_ref)) => {
+                    let oracle = load_keypair(&path)?;
+                    let attestation = SettlementAttestation {
+                        payment_ref: parse_payment_ref(&payment_ref)?,
+                        timestamp: unix_now()?,
+                    };
+                    let message = attestation.message(&invoice, amount);
+                    let signature: [u8; 64] = oracle.sign_message(&message).into();
+                    let verify = instructions::oracle_attestation(&oracle.pubkey(), &signature, &message);
+                    let ix = instructions::set_settled(
+                        invoice,
+                        me,
+                        state.seller,
+                        state.usdc_mint,
+                        token_program,
+                        amount,
+                        Some(attestation),
+                    );
+                    ctx.send(&[verify, ix], &[])
+                }
+                _ => {
+                    let ix = instructions::set_settled(invoice, me, state.seller, state.usdc_mint, token_program, amount, None);
+                    ctx.send(&[ix], &[])
+                }
+            }
+        }
+        Command::Debtor { invoice, debtor, require_ack } => {
+            ctx.send(&[instructions::set_invoice_debtor(invoice, me, debtor, require_ack)], &[])
+        }
+        Command::Acknowledge { invoice, amount, due_date } => {
+            ctx.send(&[instructions::acknowledge_invoice(invoice, me, amount, due_date)], &[])
+        }
+        Command::Rate { invoice, tier, apr_bps } => ctx.send(&[instructions::set_invoice_risk(invoice, me, tier, apr_bps)], &[]),
+        Command::Migrate { invoice } => ctx.send(&[instructions::migrate_invoice(invoice, me)], &[]),
+        Command::MigrateListing { listing } => ctx.send(&[instructions::migrate_listing(listing, me)], &[]),
+        Command::Config(cmd) => run_config(&ctx, cmd),
+        Command::Inspect(_) => unreachable!("handled above"),
+    }
+}
+
+fn run_config(ctx: &Session, cmd: ConfigCommand) -> Result<()> {
+    let me = ctx.signer();
+    let queue = |change: ConfigChange| {
+        println!("Queueing {}", inspect::describe_change(&change));
+        ctx.send(&[instructions::queue_config_change(me, change)], &[])
+    };
+    match cmd {
+        ConfigCommand::Init { admin } => ctx.send(&[instructions::init_config(me, admin.unwrap_or(me))], &[]),
+        ConfigCommand::QueueAdmin { key } => queue(ConfigChange::Admin(key)),
+        ConfigCommand::QueueCompliance { key } => queue(ConfigChange::Compliance(key)),
+        ConfigCommand::QueueOracle { key } => queue(ConfigChange::SettlementOracle(key)),
+        ConfigCommand::QueueUnderwriter { key } => queue(ConfigChange::Underwriter(key)),
+        ConfigCommand::QueueTimelock { seconds } => queue(ConfigChange::TimelockDelay(seconds)),
+        ConfigCommand::QueueAddMint { mint, decimals, min_invoice_amount } => {
+            queue(ConfigChange::AddSettlementMint(SettlementMint { mint, decimals, min_invoice_amount }))
+        }
+        ConfigCommand::QueueRemoveMint { mint } => queue(ConfigChange::RemoveSettlementMint(mint)),
+        ConfigCommand::QueueLimits { max_position, max_share_bps, max_seller_outstanding } => {
+            queue(ConfigChange::ExposureLimits(ExposureLimits {
+                max_investor_position: max_position,
+                max_investor_share_bps: max_share_bps,
+                max_seller_outstanding,
+            }))
+        }
+        ConfigCommand::Migrate => ctx.send(&[instructions::migrate_config(me)], &[]),
+        ConfigCommand::Execute | ConfigCommand::Cancel => {
+            let data = ctx
+                .rpc
+                .account_data(&pda::config_change().0)?
+                .ok_or_else(|| anyhow!("no pending config change"))?;
+            let pending = invoice_manager_client::accounts::decode_pending_config_change(&data)?;
+            let ix = if matches!(cmd, ConfigCommand::Execute) {
+                instructions::execute_config_change(pending.proposer)
+            } else {
+                instructions::cancel_config_change(me, pending.proposer)
+            };
+            ctx.send(&[ix], &[])
+        }
+        ConfigCommand::AcceptAdmin => ctx.send(&[instructions::accept_admin(me)], &[]),
+        ConfigCommand::CancelAdminProposal => ctx.send(&[instructions::cancel_admin_proposal(me)], &[]),
+        ConfigCommand::Pause { flags } => {
+            let paused = parse_pause_flags(&flags)?;
+            println!("Setting paused {}", inspect::describe_paused(paused));
+            ctx.send(&[instructions::set_paused(me, paused)], &[])
+        }
+        ConfigCommand::Freeze { invoice, unfreeze } => {
+            ctx.send(&[instructions::set_invoice_frozen(invoice, me, !unfreeze)], &[])
+        }
+        ConfigCommand::Attest { investor, jurisdiction, expires_at } => {
+            let jurisdiction = parse_jurisdiction(&jurisdiction)?;
+            ctx.send(&[instructions::issue_attestation(me, investor, jurisdiction, expires_at)], &[])
+        }
+        ConfigCommand::Seller { seller, status, credit_limit } => {
+            ctx.send(&[instructions::register_seller(me, seller, status, credit_limit)], &[])
+        }
+        ConfigCommand::RevokeAttestation { investor } => ctx.send(&[instructions::revoke_attestation(me, investor)], &[]),
+        ConfigCommand::Kyc { invoice: Some(invoice), off } => {
+            ctx.send(&[instructions::set_invoice_kyc_required(invoice, me, !off)], &[])
+        }
+        ConfigCommand::Kyc { invoice: None, off } => ctx.send(&[instructions::set_kyc_required(me, !off)], &[]),
+    }
+}
+
+fn main() {
+    if let Err(err) = run(Cli::parse()) {
+        eprintln!("error: {err:#}");
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,183 @@
+//! Minimal JSON-RPC client covering the handful of calls the CLI needs.
+
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, bail, Context, Result};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use invoice_manager_client::accounts::Memcmp;
+use invoice_manager_client::Pubkey;
+use serde_json::{json, Value};
+use solana_transaction::Transaction;
+
+const COMMITMENT: &str = "confirmed";
+const CONFIRM_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub struct RpcClient {
+    url: String,
+    agent: ureq::Agent,
+}
+
+impl RpcClient {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            agent: ureq::AgentBuilder::new().timeout(Duration::from_secs(30)).build(),
+        }
+    }
+
+    fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let body = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        let resp: Value = self
+            .agent
+            .post(&self.url)
+            .send_json(body)
+            .with_context(|| format!("{method} request to {} failed", self.url))?
+            .into_json()
+            .with_context(|| format!("{method} returned invalid JSON"))?;
+        if let Some(err) = resp.get("error") {
+            bail!("{method} failed: {err}");
+        }
+        resp.get("result")
+            .cloned()
+            .ok_or_else(|| anyhow!("{method} returned no result"))
+    }
+
+    pub fn latest_blockhash(&self) -> Result<solana_hash::Hash> {
+        let result = self.call("getLatestBlockhash", json!([{ "commitment": COMMITMENT }]))?;
+        let hash = result["value"]["blockhash"]
+            .as_str()
+            .ok_or_else(|| anyhow!("getLatestBlockhash: missing blockhash"))?;
+        solana_hash::Hash::from_str(hash).map_err(|e| anyhow!("invalid blockhash {hash}: {e}"))
+    }
+
+    /// Raw account data, or `None` if the account does not exist.
+    pub fn account_data(&self, address: &Pubkey) -> Result<Option<Vec<u8>>> {
+        let result = self.call(
+            "getAccountInfo",
+            json!([address.to_string(), { "encoding": "base64", "commitment": COMMITMENT }]),
+        )?;
+        let value = &result["value"];
+        if value.is_null() {
+            return Ok(None);
+        }
+        let encoded = value["data"][0]
+            .as_str()
+            .ok_or_else(|| anyhow!("getAccountInfo: unexpected data encoding"))?;
+        Ok(Some(STANDARD.decode(encoded)?))
+    }
+
+    /// Owning program of an account, or `None` if the account does not exist.
+    pub fn account_owner(&self, address: &Pubkey) -> Result<Option<Pubkey>> {
+        let result = self.call(
+            "getAccountInfo",
+            json!([address.to_string(), { "encoding": "base64", "dataSlice": { "offset": 0, "length": 0 }, "commitment": COMMITMENT }]),
+        )?;
+        let value = &result["value"];
+        if value.is_null() {
+            return Ok(None);
+        }
+        let owner = value["owner"]
+            .as_str()
+            .ok_or_else(|| anyhow!("getAccountInfo: missing owner"))?;
+        Ok(Some(Pubkey::from_str(owner).map_err(|e| anyhow!("invalid pubkey {owner}: {e}"))?))
+    }
+
+    /// Addresses and data of `program`'s accounts of `data_size` bytes that
+    /// match every memcmp filter.
+    pub fn program_accounts(&self, program: &Pubkey, data_size: usize, filters: &[Memcmp]) -> Result<Vec<(Pubkey, Vec<u8>)>> {
+        let mut json_filters = vec![json!({ "dataSize": data_size })];
+        for filter in filters {
+            json_filters.push(json!({
+                "memcmp": { "offset": filter.offset, "bytes": STANDARD.encode(&filter.bytes), "encoding": "base64" }
+            }));
+        }
+        let result = self.call(
+            "getProgramAccounts",
+            json!([program.to_string(), { "encoding": "base64", "commitment": COMMITMENT, "filters": json_filters }]),
+        )?;
+        let entries = result.as_array().ok_or_else(|| anyhow!("getProgramAccounts: expected an array"))?;
+        entries
+            .iter()
+            .map(|entry| {
+                let address = entry["pubkey"]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("getProgramAccounts: missing pubkey"))?;
+                let encoded = entry["account"]["data"][0]
+                    .as_str()
+                    .ok_or_else(|| anyhow!("getProgramAccounts: unexpected data encoding"))?;
+                let address = Pubkey::from_str(address).map_err(|e| anyhow!("invalid pubkey {address}: {e}"))?;
+                Ok((address, STANDARD.decode(encoded)?))
+            })
+            .collect()
+    }
+
+    /// Sends a signed transaction and waits until it is confirmed.
+    pub fn send_and_confirm(&self, tx: &Transaction) -> Result<String> {
+        let wire = STANDARD.encode(bincode::serialize(tx)?);
+        let result = self.call(
+            "sendTransaction",
+            json!([wire, { "encoding": "base64", "preflightCommitment": COMMITMENT }]),
+        )?;
+        let signature = result
+            .as_str()
+            .ok_or_else(|| anyhow!("sendTransaction: missing signature"))?
+            .to_string();
+
+        let started = Instant::now();
+        loop {
+            let statuses = self.call("getSignatureStatuses", json!([[signature]]))?;
+            let status = &statuses["value"][0];
+            if !status.is_null() {
+                if !status["err"].is_null() {
+                    bail!("transaction {signature} failed: {}", status["err"]);
+                }
+                let level = status["confirmationStatus"].as_str().unwrap_or_default();
+                if level == "confirmed" || level == "finalized" {
+                    return Ok(signature);
+                }
+            }
+            if started.elapsed() > CONFIRM_TIMEOUT {
+                bail!("transaction {signature} not confirmed after {CONFIRM_TIMEOUT:?}");
+            }
+            sleep(Duration::from_millis(500));
+        }
+    }
+
+    /// Simulates an unsigned transaction against the latest state and returns
+    /// its return data. Fails if the simulation does.
+    pub fn simulate_return_data(&self, tx: &Transaction) -> Result<Vec<u8>> {
+        let wire = STANDARD.encode(bincode::serialize(tx)?);
+        let result = self.call(
+            "simulateTransaction",
+            json!([wire, {
+                "encoding": "base64",
+                "commitment": COMMITMENT,
+                "sigVerify": false,
+                "replaceRecentBlockhash": true,
+            }]),
+        )?;
+        let value = &result["value"];
+        if !value["err"].is_null() {
+            bail!("simulation failed: {}\n{:#}", value["err"], value["logs"]);
+        }
+        match value["returnData"]["data"][0].as_str() {
+            Some(encoded) => Ok(STANDARD.decode(encoded)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Log messages of a confirmed transaction.
+    pub fn transaction_logs(&self, signature: &str) -> Result<Vec<String>> {
+        let result = self.call(
+            "getTransaction",
+            json!([signature, { "encoding": "json", "commitment": COMMITMENT, "maxSupportedTransactionVersion": 0 }]),
+        )?;
+        Ok(result["meta"]["logMessages"]
+            .as_array()
+            .map(|logs| logs.iter().filter_map(|l| l.as_str().map(String::from)).collect())
+            .unwrap_or_default())
+    }
+}
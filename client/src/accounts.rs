@@ -0,0 +1,77 @@
+//! Account decoders. Each checks the Anchor discriminator before deserializing.
+
+use anchor_lang::error::ErrorCode;
+use anchor_lang::{AccountDeserialize, Discriminator, Result};
+use anchor_lang::solana_program::pubkey::Pubkey;
+use invoice_manager::{
+    AdminConfig, InvestorAttestation, InvestorPosition, InvestorProfile, Invoice, InvoiceStatus, Listing,
+    PendingConfigChange, RepaymentSchedule, SellerProfile, INVOICE_STATUS_OFFSET, INVOICE_USDC_MINT_OFFSET,
+};
+
+/// Decodes any program account from its raw data, discriminator included.
+pub fn decode<T: AccountDeserialize>(data: &[u8]) -> Result<T> {
+    T::try_deserialize(&mut &data[..])
+}
+
+/// Decodes an `Invoice` in the current fixed-size layout. Accounts still in a
+/// legacy borsh layout are rejected rather than misread; see `migrate_invoice`.
+pub fn decode_invoice(data: &[u8]) -> Result<Invoice> {
+    if data.len() != Invoice::SPACE {
+        return Err(ErrorCode::AccountDidNotDeserialize.into());
+    }
+    decode(data)
+}
+
+pub fn decode_listing(data: &[u8]) -> Result<Listing> {
+    decode(data)
+}
+
+pub fn decode_admin_config(data: &[u8]) -> Result<AdminConfig> {
+    decode(data)
+}
+
+pub fn decode_pending_config_change(data: &[u8]) -> Result<PendingConfigChange> {
+    decode(data)
+}
+
+pub fn decode_investor_attestation(data: &[u8]) -> Result<InvestorAttestation> {
+    decode(data)
+}
+
+pub fn decode_seller_profile(data: &[u8]) -> Result<SellerProfile> {
+    decode(data)
+}
+
+pub fn decode_investor_profile(data: &[u8]) -> Result<InvestorProfile> {
+    decode(data)
+}
+
+pub fn decode_investor_position(data: &[u8]) -> Result<InvestorPosition> {
+    decode(data)
+}
+
+pub fn decode_repayment_schedule(data: &[u8]) -> Result<RepaymentSchedule> {
+    decode(data)
+}
+
+/// A `getProgramAccounts` memcmp filter: `bytes` must appear at `offset` in
+/// the account data.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Memcmp {
+    pub offset: usize,
+    pub bytes: Vec<u8>,
+}
+
+/// Filters selecting invoices, optionally narrowed to one status and one
+/// settlement mint. Pair them with a `dataSize` filter of `Invoice::SPACE` so
+/// accounts not yet migrated to the fixed-size layout are skipped.
+pub fn invoice_filters(status: Option<InvoiceStatus>, usdc_mint: Option<&Pubkey>) -> Vec<Memcmp> {
+    let mut filters = vec![Memcmp { offset: 0, bytes: Invoice::DISCRIMINATOR.to_vec() }];
+    if let Some(status) = status {
+        filters.push(Memcmp { offset: INVOICE_STATUS_OFFSET, bytes: vec![status as u8] });
+    }
+    if let Some(mint) = usdc_mint {
+        filters.push(Memcmp { offset: INVOICE_USDC_MINT_OFFSET, bytes: mint.to_bytes().to_vec() });
+    }
+    filters
+}
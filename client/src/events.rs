@@ -0,0 +1,110 @@
+//! Event decoders.
+//!
+//! Anchor's `emit!` writes each event as a `Program data: <base64>` log line holding
+//! the 8-byte event discriminator followed by the borsh-encoded event.
+
+use anchor_lang::{AnchorDeserialize, Discriminator};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use invoice_manager::ID;
+
+macro_rules! program_events {
+    ($($name:ident),* $(,)?) => {
+        /// Every event emitted by the program.
+        pub enum InvoiceManagerEvent {
+            $($name(invoice_manager::$name),)*
+        }
+
+        /// Decodes one event from `Program data` bytes. Returns `None` for unknown
+        /// discriminators (e.g. events added by a newer program) or malformed data.
+        pub fn decode_event(data: &[u8]) -> Option<InvoiceManagerEvent> {
+            $(
+                if let Some(body) = data.strip_prefix(invoice_manager::$name::DISCRIMINATOR) {
+                    return invoice_manager::$name::deserialize(&mut &body[..])
+                        .ok()
+                        .map(InvoiceManagerEvent::$name);
+                }
+            )*
+            None
+        }
+
+        impl InvoiceManagerEvent {
+            /// Event name as declared in the program
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(InvoiceManagerEvent::$name(_) => stringify!($name),)*
+                }
+            }
+        }
+    };
+}
+
+program_events!(
+    InvoiceMinted,
+    EscrowCreated,
+    SharesInitialized,
+    InvoiceFunded,
+    InvoiceSettled,
+    InvoicePaid,
+    RepaymentScheduleSet,
+    InstallmentOverdue,
+    RepaymentClaimed,
+    InvoiceDefaulted,
+    EscrowRefunded,
+    ConfigInitialized,
+    ListingCreated,
+    ListingFulfilled,
+    ListingCanceled,
+    ListingFulfilledV1,
+    ListingFulfilledV2,
+    ListingCanceledV1,
+    ListingCanceledV2,
+    AdminProposed,
+    AdminAccepted,
+    AdminProposalCanceled,
+    ConfigChangeQueued,
+    ConfigChangeExecuted,
+    ConfigChangeCanceled,
+    PauseUpdated,
+    InvoiceFrozenUpdated,
+    AttestationIssued,
+    AttestationRevoked,
+    KycRequiredUpdated,
+    InvoiceRiskUpdated,
+    InvoiceDebtorSet,
+    InvoiceAcknowledged,
+    SellerProfileUpdated,
+    InvoiceMigrated,
+    ListingMigrated,
+    ConfigMigrated,
+);
+
+/// Extracts the program's events from a transaction's log messages, in order.
+///
+/// Invocation depth is tracked so `Program data` lines written by other programs
+/// (including ones invoked via CPI) are ignored.
+pub fn parse_logs<S: AsRef<str>>(logs: &[S]) -> Vec<InvoiceManagerEvent> {
+    let program_id = ID.to_string();
+    let mut stack: Vec<&str> = Vec::new();
+    let mut events = Vec::new();
+    for line in logs {
+        let line = line.as_ref();
+        if let Some(rest) = line.strip_prefix("Program data: ") {
+            if stack.last() == Some(&program_id.as_str()) {
+                if let Some(event) = STANDARD.decode(rest).ok().and_then(|d| decode_event(&d)) {
+                    events.push(event);
+                }
+            }
+        } else if let Some(rest) = line.strip_prefix("Program ") {
+            let mut parts = rest.split_whitespace();
+            match (parts.next(), parts.next()) {
+                (Some(id), Some("invoke")) => stack.push(id),
+                (Some(_), Some("success")) | (Some(_), Some("failed:")) => {
+                    stack.pop();
+                }
+                _ => {}
+            }
+        }
+    }
+    events
+}
@@ -0,0 +1,790 @@
+//! Instruction builders, one per program instruction.
+//!
+//! PDAs and associated token accounts are derived from the keys passed in, the
+//! same way `backend/src/anchor.ts` does, so callers only supply the "real" keys.
+
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::pubkey::Pubkey;
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::{associated_token, token, token_2022};
+use invoice_manager::{
+    accounts, instruction, ConfigChange, InstallmentTerms, SellerStatus, SettlementAttestation, ID, SHARES_TRANSFER_HOOK_ID,
+};
+use solana_sdk_ids::{ed25519_program, system_program, sysvar};
+
+use crate::pda;
+
+fn build(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+/// `invoice` is a fresh keypair that must also sign.
+pub fn mint_invoice(
+    invoice: Pubkey,
+    seller: Pubkey,
+    usdc_mint: Pubkey,
+    metadata_hash: String,
+    amount: u64,
+    due_date: i64,
+) -> Instruction {
+    build(
+        accounts::MintInvoice {
+            config: pda::config().0,
+            invoice,
+            seller,
+            usdc_mint,
+            system_program: system_program::ID,
+            seller_profile: pda::seller_profile(&seller).0,
+        },
+        instruction::MintInvoice { metadata_hash, amount, due_date },
+    )
+}
+
+/// `token_program` owns `usdc_mint`: spl-token, or Token-2022 for extension mints.
+pub fn create_escrow(invoice: Pubkey, seller: Pubkey, usdc_mint: Pubkey, token_program: Pubkey) -> Instruction {
+    build(
+        accounts::CreateEscrow {
+            config: pda::config().0,
+            invoice,
+            seller,
+            usdc_mint,
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            escrow_token: pda::escrow_token_with_program(&invoice, &usdc_mint, &token_program),
+            token_program,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::CreateEscrow {},
+    )
+}
+
+/// `shares_mint` is a fresh keypair that must also sign.
+pub fn init_shares(invoice: Pubkey, payer: Pubkey, shares_mint: Pubkey) -> Instruction {
+    build(
+        accounts::InitShares {
+            config: pda::config().0,
+            invoice,
+            payer,
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            shares_mint,
+            token_program: token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::InitShares {},
+    )
+}
+
+/// Restricted variant of [`init_shares`]: a Token-2022 mint with the attestation
+/// transfer hook. Send [`init_extra_account_meta_list`] for the same mint before
+/// its shares are traded. `shares_mint` is a fresh keypair that must also sign.
+pub fn init_shares_restricted(invoice: Pubkey, payer: Pubkey, shares_mint: Pubkey) -> Instruction {
+    build(
+        accounts::InitSharesRestricted {
+            config: pda::config().0,
+            invoice,
+            payer,
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            shares_mint,
+            transfer_hook_program: SHARES_TRANSFER_HOOK_ID,
+            token_program: token_2022::ID,
+            system_program: system_program::ID,
+        },
+        instruction::InitSharesRestricted {},
+    )
+}
+
+/// Creates the transfer hook's extra-account-meta list for a restricted shares
+/// mint. Permissionless; `payer` funds the account.
+pub fn init_extra_account_meta_list(payer: Pubkey, shares_mint: Pubkey) -> Instruction {
+    Instruction {
+        program_id: SHARES_TRANSFER_HOOK_ID,
+        accounts: shares_transfer_hook::accounts::InitializeExtraAccountMetaList {
+            payer,
+            extra_account_meta_list: pda::extra_account_metas(&shares_mint).0,
+            mint: shares_mint,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: shares_transfer_hook::instruction::InitializeExtraAccountMetaList {}.data(),
+    }
+}
+
+pub fn fund_invoice(
+    invoice: Pubkey,
+    investor: Pubkey,
+    seller: Pubkey,
+    usdc_mint: Pubkey,
+    token_program: Pubkey,
+    amount: u64,
+) -> Instruction {
+    build(
+        accounts::FundInvoice {
+            config: pda::config().0,
+            invoice,
+            investor,
+            investor_ata: pda::ata_with_program(&investor, &usdc_mint, &token_program),
+            escrow_token: pda::escrow_token_with_program(&invoice, &usdc_mint, &token_program),
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            token_program,
+            attestation: pda::attestation(&investor).0,
+            usdc_mint,
+            seller_profile: pda::seller_profile(&seller).0,
+            investor_profile: pda::investor_profile(&investor).0,
+            position: pda::position(&invoice, &investor).0,
+            system_program: system_program::ID,
+        },
+        instruction::FundInvoice { amount },
+    )
+}
+
+/// `token_program` owns `usdc_mint` and `shares_token_program` owns `shares_mint`:
+/// spl-token, or Token-2022 for extension mints and restricted shares.
+#[allow(clippy::too_many_arguments)]
+pub fn fund_invoice_fractional(
+    invoice: Pubkey,
+    investor: Pubkey,
+    seller: Pubkey,
+    usdc_mint: Pubkey,
+    token_program: Pubkey,
+    shares_mint: Pubkey,
+    shares_token_program: Pubkey,
+    amount: u64,
+) -> Instruction {
+    build(
+        accounts::FundInvoiceFractional {
+            config: pda::config().0,
+            invoice,
+            investor,
+            investor_ata: pda::ata_with_program(&investor, &usdc_mint, &token_program),
+            escrow_token: pda::escrow_token_with_program(&invoice, &usdc_mint, &token_program),
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            shares_mint,
+            investor_shares_ata: pda::ata_with_program(&investor, &shares_mint, &shares_token_program),
+            token_program,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+            attestation: pda::attestation(&investor).0,
+            shares_token_program,
+            usdc_mint,
+            seller_profile: pda::seller_profile(&seller).0,
+            investor_profile: pda::investor_profile(&investor).0,
+            position: pda::position(&invoice, &investor).0,
+        },
+        instruction::FundInvoiceFractional { amount },
+    )
+}
+
+/// Settles a funded invoice. With `attestation: None` the operator must be the
+/// admin; otherwise the transaction must carry [`oracle_attestation`] right before it.
+pub fn set_settled(
+    invoice: Pubkey,
+    operator: Pubkey,
+    seller: Pubkey,
+    usdc_mint: Pubkey,
+    token_program: Pubkey,
+    amount: u64,
+    attestation: Option<SettlementAttestation>,
+) -> Instruction {
+    build(
+        accounts::SetSettled {
+            invoice,
+            config: pda::config().0,
+            operator,
+            seller_ata: pda::ata_with_program(&seller, &usdc_mint, &token_program),
+            escrow_token: pda::escrow_token_with_program(&invoice, &usdc_mint, &token_program),
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            instructions: sysvar::instructions::ID,
+            token_program,
+            usdc_mint,
+            seller_profile: pda::seller_profile(&seller).0,
+        },
+        instruction::SetSettled { amount, attestation },
+    )
+}
+
+/// Deposits `amount` of the invoice's settlement mint from `payer`'s account into
+/// the repayment vault; `seller` receives the escrow if this completes the face value.
+pub fn pay_invoice(invoice: Pubkey, payer: Pubkey, seller: Pubkey, usdc_mint: Pubkey, token_program: Pubkey, amount: u64) -> Instruction {
+    build(
+        accounts::PayInvoice {
+            config: pda::config().0,
+            invoice,
+            payer,
+            payer_ata: pda::ata_with_program(&payer, &usdc_mint, &token_program),
+            repayment_vault: pda::repayment_vault(&invoice).0,
+            seller_ata: pda::ata_with_program(&seller, &usdc_mint, &token_program),
+            escrow_token: pda::escrow_token_with_program(&invoice, &usdc_mint, &token_program),
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            usdc_mint,
+            token_program,
+            seller_profile: pda::seller_profile(&seller).0,
+            repayment_schedule: pda::repayment_schedule(&invoice).0,
+            system_program: system_program::ID,
+        },
+        instruction::PayInvoice { amount },
+    )
+}
+
+/// Creates or replaces the invoice's repayment schedule; signed by its seller
+/// before funding starts.
+pub fn set_repayment_schedule(invoice: Pubkey, seller: Pubkey, installments: Vec<InstallmentTerms>) -> Instruction {
+    build(
+        accounts::SetRepaymentSchedule {
+            config: pda::config().0,
+            invoice,
+            seller,
+            repayment_schedule: pda::repayment_schedule(&invoice).0,
+            system_program: system_program::ID,
+        },
+        instruction::SetRepaymentSchedule { installments },
+    )
+}
+
+/// Flags the invoice's overdue installments; anyone may send it.
+pub fn flag_overdue_installments(invoice: Pubkey) -> Instruction {
+    build(
+        accounts::FlagOverdueInstallments {
+            config: pda::config().0,
+            invoice,
+            repayment_schedule: pda::repayment_schedule(&invoice).0,
+        },
+        instruction::FlagOverdueInstallments {},
+    )
+}
+
+/// Pays `claimant` its share of the repayments received so far into its
+/// settlement mint ATA. `shares` is the invoice's `(shares_mint, shares token
+/// program)`; the claimant's whole shares balance is burned and claimed on,
+/// so after the first claim the position can no longer be traded.
+/// `None` claims only on what was funded directly or redeemed before.
+pub fn claim_repayment(
+    invoice: Pubkey,
+    claimant: Pubkey,
+    usdc_mint: Pubkey,
+    token_program: Pubkey,
+    shares: Option<(Pubkey, Pubkey)>,
+) -> Instruction {
+    build(
+        accounts::ClaimRepayment {
+            config: pda::config().0,
+            invoice,
+            claimant,
+            claimant_ata: pda::ata_with_program(&claimant, &usdc_mint, &token_program),
+            repayment_vault: pda::repayment_vault(&invoice).0,
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            usdc_mint,
+            token_program,
+            position: pda::position(&invoice, &claimant).0,
+            shares_mint: shares.map(|(mint, _)| mint),
+            claimant_shares: shares.map(|(mint, program)| pda::ata_with_program(&claimant, &mint, &program)),
+            shares_token_program: shares.map(|(_, program)| program),
+            system_program: system_program::ID,
+        },
+        instruction::ClaimRepayment {},
+    )
+}
+
+/// Ed25519 program instruction carrying the oracle's signature over
+/// [`SettlementAttestation::message`]; place it immediately before `set_settled`.
+pub fn oracle_attestation(oracle: &Pubkey, signature: &[u8; 64], message: &[u8]) -> Instruction {
+    // num_signatures, padding, offsets (7 x u16), pubkey, signature, message
+    const HEADER_LEN: u16 = 2 + 14;
+    const PUBKEY_OFFSET: u16 = HEADER_LEN;
+    const SIGNATURE_OFFSET: u16 = PUBKEY_OFFSET + 32;
+    const MESSAGE_OFFSET: u16 = SIGNATURE_OFFSET + 64;
+    const THIS_IX: u16 = u16::MAX;
+
+    let mut data = Vec::with_capacity(MESSAGE_OFFSET as usize + message.len());
+    data.extend_from_slice(&[1, 0]);
+    for field in [
+        SIGNATURE_OFFSET,
+        THIS_IX,
+        PUBKEY_OFFSET,
+        THIS_IX,
+        MESSAGE_OFFSET,
+        message.len() as u16,
+        THIS_IX,
+    ] {
+        data.extend_from_slice(&field.to_le_bytes());
+    }
+    data.extend_from_slice(oracle.as_ref());
+    data.extend_from_slice(signature);
+    data.extend_from_slice(message);
+    Instruction {
+        program_id: ed25519_program::ID,
+        accounts: vec![],
+        data,
+    }
+}
+
+/// V1 listing: moves `qty` shares from the seller into the marketplace escrow.
+pub fn create_listing(
+    invoice: Pubkey,
+    seller: Pubkey,
+    shares_mint: Pubkey,
+    usdc_mint: Pubkey,
+    qty: u64,
+    price: u64,
+) -> Instruction {
+    let listing = pda::listing(&invoice, &seller).0;
+    build(
+        accounts::CreateListing {
+            config: pda::config().0,
+            invoice,
+            seller,
+            shares_mint,
+            usdc_mint,
+            listing,
+            market_authority: pda::market_authority(&listing).0,
+            seller_shares_ata: pda::ata(&seller, &shares_mint),
+            escrow_shares_ata: pda::escrow_shares(&listing, &shares_mint),
+            token_program: token::ID,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+        },
+        instruction::CreateListing { qty, price },
+    )
+}
+
+/// V2 listing: no escrow; the seller approves shares to the market authority separately.
+pub fn create_listing_v2(
+    invoice: Pubkey,
+    seller: Pubkey,
+    shares_mint: Pubkey,
+    usdc_mint: Pubkey,
+    qty: u64,
+    price: u64,
+) -> Instruction {
+    let listing = pda::listing(&invoice, &seller).0;
+    build(
+        accounts::CreateListingV2 {
+            config: pda::config().0,
+            invoice,
+            seller,
+            shares_mint,
+            usdc_mint,
+            listing,
+            market_authority: pda::market_authority(&listing).0,
+            system_program: system_program::ID,
+        },
+        instruction::CreateListingV2 { qty, price },
+    )
+}
+
+pub fn fulfill_listing(
+    invoice: Pubkey,
+    seller: Pubkey,
+    buyer: Pubkey,
+    shares_mint: Pubkey,
+    usdc_mint: Pubkey,
+    token_program: Pubkey,
+    qty: u64,
+) -> Instruction {
+    let listing = pda::listing(&invoice, &seller).0;
+    build(
+        accounts::FulfillListing {
+            config: pda::config().0,
+            invoice,
+            buyer,
+            listing,
+            market_authority: pda::market_authority(&listing).0,
+            buyer_usdc_ata: pda::ata_with_program(&buyer, &usdc_mint, &token_program),
+            seller_usdc_ata: pda::ata_with_program(&seller, &usdc_mint, &token_program),
+            escrow_shares_ata: pda::escrow_shares(&listing, &shares_mint),
+            buyer_shares_ata: pda::ata(&buyer, &shares_mint),
+            shares_mint,
+            token_program,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+            attestation: pda::attestation(&buyer).0,
+            usdc_mint,
+            shares_token_program: token::ID,
+            buyer_profile: pda::investor_profile(&buyer).0,
+            buyer_position: pda::position(&invoice, &buyer).0,
+            seller_position: pda::position(&invoice, &seller).0,
+        },
+        instruction::FulfillListing { qty },
+    )
+}
+
+pub fn cancel_listing(invoice: Pubkey, seller: Pubkey, shares_mint: Pubkey) -> Instruction {
+    let listing = pda::listing(&invoice, &seller).0;
+    build(
+        accounts::CancelListing {
+            config: pda::config().0,
+            invoice,
+            seller,
+            listing,
+            market_authority: pda::market_authority(&listing).0,
+            escrow_shares_ata: pda::escrow_shares(&listing, &shares_mint),
+            seller_shares_ata: pda::ata(&seller, &shares_mint),
+            token_program: token::ID,
+            shares_mint,
+        },
+        instruction::CancelListing {},
+    )
+}
+
+/// Token-2022 (restricted) shares get the transfer hook's extra accounts appended.
+#[allow(clippy::too_many_arguments)]
+pub fn fulfill_listing_v2(
+    invoice: Pubkey,
+    seller: Pubkey,
+    buyer: Pubkey,
+    shares_mint: Pubkey,
+    shares_token_program: Pubkey,
+    usdc_mint: Pubkey,
+    token_program: Pubkey,
+    qty: u64,
+) -> Instruction {
+    let listing = pda::listing(&invoice, &seller).0;
+    let mut ix = build(
+        accounts::FulfillListingV2 {
+            config: pda::config().0,
+            invoice,
+            buyer,
+            listing,
+            market_authority: pda::market_authority(&listing).0,
+            buyer_usdc_ata: pda::ata_with_program(&buyer, &usdc_mint, &token_program),
+            seller_usdc_ata: pda::ata_with_program(&seller, &usdc_mint, &token_program),
+            seller_shares_ata: pda::ata_with_program(&seller, &shares_mint, &shares_token_program),
+            buyer_shares_ata: pda::ata_with_program(&buyer, &shares_mint, &shares_token_program),
+            shares_mint,
+            usdc_mint,
+            token_program,
+            associated_token_program: associated_token::ID,
+            system_program: system_program::ID,
+            attestation: pda::attestation(&buyer).0,
+            shares_token_program,
+            buyer_profile: pda::investor_profile(&buyer).0,
+            buyer_position: pda::position(&invoice, &buyer).0,
+            seller_position: pda::position(&invoice, &seller).0,
+        },
+        instruction::FulfillListingV2 { qty },
+    );
+    if shares_token_program == token_2022::ID {
+        ix.accounts.extend([
+            AccountMeta::new_readonly(pda::extra_account_metas(&shares_mint).0, false),
+            AccountMeta::new_readonly(ID, false),
+            AccountMeta::new_readonly(pda::attestation(&buyer).0, false),
+            AccountMeta::new_readonly(SHARES_TRANSFER_HOOK_ID, false),
+        ]);
+    }
+    ix
+}
+
+pub fn cancel_listing_v2(
+    invoice: Pubkey,
+    seller: Pubkey,
+    shares_mint: Pubkey,
+    shares_token_program: Pubkey,
+) -> Instruction {
+    let listing = pda::listing(&invoice, &seller).0;
+    build(
+        accounts::CancelListingV2 {
+            config: pda::config().0,
+            invoice,
+            seller,
+            listing,
+            market_authority: pda::market_authority(&listing).0,
+            seller_shares_ata: pda::ata_with_program(&seller, &shares_mint, &shares_token_program),
+            token_program: shares_token_program,
+        },
+        instruction::CancelListingV2 {},
+    )
+}
+
+/// `payer` must be the program's upgrade authority.
+pub fn init_config(payer: Pubkey, admin: Pubkey) -> Instruction {
+    build(
+        accounts::InitConfig {
+            config: pda::config().0,
+            payer,
+            program: ID,
+            program_data: pda::program_data(),
+            system_program: system_program::ID,
+        },
+        instruction::InitConfig { admin },
+    )
+}
+
+pub fn queue_config_change(admin: Pubkey, change: ConfigChange) -> Instruction {
+    build(
+        accounts::QueueConfigChange {
+            config: pda::config().0,
+            admin,
+            pending_change: pda::config_change().0,
+            system_program: system_program::ID,
+        },
+        instruction::QueueConfigChange { change },
+    )
+}
+
+/// `proposer` is the admin that queued the change; it receives the account's rent.
+pub fn execute_config_change(proposer: Pubkey) -> Instruction {
+    build(
+        accounts::ExecuteConfigChange {
+            config: pda::config().0,
+            pending_change: pda::config_change().0,
+            proposer,
+        },
+        instruction::ExecuteConfigChange {},
+    )
+}
+
+pub fn cancel_config_change(admin: Pubkey, proposer: Pubkey) -> Instruction {
+    build(
+        accounts::CancelConfigChange {
+            config: pda::config().0,
+            admin,
+            pending_change: pda::config_change().0,
+            proposer,
+        },
+        instruction::CancelConfigChange {},
+    )
+}
+
+pub fn accept_admin(pending_admin: Pubkey) -> Instruction {
+    build(
+        accounts::AcceptAdmin {
+            config: pda::config().0,
+            pending_admin,
+        },
+        instruction::AcceptAdmin {},
+    )
+}
+
+pub fn cancel_admin_proposal(admin: Pubkey) -> Instruction {
+    build(
+        accounts::UpdateConfig {
+            config: pda::config().0,
+            admin,
+        },
+        instruction::CancelAdminProposal {},
+    )
+}
+
+/// `paused` is a combination of the program's `PAUSE_*` bits.
+pub fn set_paused(admin: Pubkey, paused: u8) -> Instruction {
+    build(
+        accounts::UpdateConfig {
+            config: pda::config().0,
+            admin,
+        },
+        instruction::SetPaused { paused },
+    )
+}
+
+pub fn set_invoice_frozen(invoice: Pubkey, compliance: Pubkey, frozen: bool) -> Instruction {
+    build(
+        accounts::SetInvoiceFrozen {
+            invoice,
+            config: pda::config().0,
+            compliance,
+        },
+        instruction::SetInvoiceFrozen { frozen },
+    )
+}
+
+/// Issues or renews `investor`'s KYC attestation; `jurisdiction` is an ISO 3166-1 alpha-2 code.
+pub fn issue_attestation(compliance: Pubkey, investor: Pubkey, jurisdiction: [u8; 2], expires_at: i64) -> Instruction {
+    build(
+        accounts::IssueAttestation {
+            config: pda::config().0,
+            compliance,
+            investor,
+            attestation: pda::attestation(&investor).0,
+            system_program: system_program::ID,
+        },
+        instruction::IssueAttestation { jurisdiction, expires_at },
+    )
+}
+
+/// Closes `investor`'s attestation, refunding its rent to `compliance`.
+pub fn revoke_attestation(compliance: Pubkey, investor: Pubkey) -> Instruction {
+    build(
+        accounts::RevokeAttestation {
+            config: pda::config().0,
+            compliance,
+            attestation: pda::attestation(&investor).0,
+        },
+        instruction::RevokeAttestation {},
+    )
+}
+
+pub fn set_kyc_required(compliance: Pubkey, required: bool) -> Instruction {
+    build(
+        accounts::SetKycRequired {
+            config: pda::config().0,
+            compliance,
+        },
+        instruction::SetKycRequired { required },
+    )
+}
+
+pub fn set_invoice_kyc_required(invoice: Pubkey, compliance: Pubkey, required: bool) -> Instruction {
+    build(
+        accounts::SetInvoiceKycRequired {
+            invoice,
+            config: pda::config().0,
+            compliance,
+        },
+        instruction::SetInvoiceKycRequired { required },
+    )
+}
+
+/// Rates an invoice that hasn't been funded yet; `underwriter` is the config's
+/// underwriter role.
+pub fn set_invoice_risk(invoice: Pubkey, underwriter: Pubkey, risk_tier: u8, target_apr_bps: u16) -> Instruction {
+    build(
+        accounts::SetInvoiceRisk {
+            invoice,
+            config: pda::config().0,
+            underwriter,
+        },
+        instruction::SetInvoiceRisk { risk_tier, target_apr_bps },
+    )
+}
+
+/// Names the invoice's debtor (Pubkey::default() clears it) and whether
+/// funding waits for its acknowledgement; signed by the invoice's seller.
+pub fn set_invoice_debtor(invoice: Pubkey, seller: Pubkey, debtor: Pubkey, acknowledgement_required: bool) -> Instruction {
+    build(
+        accounts::SetInvoiceDebtor { config: pda::config().0, invoice, seller },
+        instruction::SetInvoiceDebtor { debtor, acknowledgement_required },
+    )
+}
+
+/// Confirms the invoice's `amount` and `due_date`, which must match the account.
+pub fn acknowledge_invoice(invoice: Pubkey, debtor: Pubkey, amount: u64, due_date: i64) -> Instruction {
+    build(
+        accounts::AcknowledgeInvoice { config: pda::config().0, invoice, debtor },
+        instruction::AcknowledgeInvoice { amount, due_date },
+    )
+}
+
+/// Creates or updates `seller`'s profile; `authority` is the admin or compliance
+/// role and pays the rent on creation.
+pub fn register_seller(authority: Pubkey, seller: Pubkey, status: SellerStatus, credit_limit: u64) -> Instruction {
+    build(
+        accounts::RegisterSeller {
+            config: pda::config().0,
+            authority,
+            seller,
+            seller_profile: pda::seller_profile(&seller).0,
+            system_program: system_program::ID,
+        },
+        instruction::RegisterSeller { status, credit_limit },
+    )
+}
+
+/// Writes off a funded invoice of `seller` past its due date (admin only).
+pub fn mark_defaulted(invoice: Pubkey, admin: Pubkey, seller: Pubkey) -> Instruction {
+    build(
+        accounts::MarkDefaulted {
+            invoice,
+            config: pda::config().0,
+            admin,
+            seller_profile: pda::seller_profile(&seller).0,
+        },
+        instruction::MarkDefaulted {},
+    )
+}
+
+/// Refunds `holder` its funding out of a defaulted invoice's escrow. `shares`
+/// works as in [`claim_repayment`]: the holder's shares are burned and refunded.
+pub fn refund_defaulted(
+    invoice: Pubkey,
+    holder: Pubkey,
+    usdc_mint: Pubkey,
+    token_program: Pubkey,
+    shares: Option<(Pubkey, Pubkey)>,
+) -> Instruction {
+    build(
+        accounts::RefundDefaulted {
+            config: pda::config().0,
+            invoice,
+            holder,
+            holder_ata: pda::ata_with_program(&holder, &usdc_mint, &token_program),
+            escrow_token: pda::escrow_token_with_program(&invoice, &usdc_mint, &token_program),
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            usdc_mint,
+            token_program,
+            position: pda::position(&invoice, &holder).0,
+            shares_mint: shares.map(|(mint, _)| mint),
+            holder_shares: shares.map(|(mint, program)| pda::ata_with_program(&holder, &mint, &program)),
+            shares_token_program: shares.map(|(_, program)| program),
+            system_program: system_program::ID,
+        },
+        instruction::RefundDefaulted {},
+    )
+}
+
+/// Upgrades an invoice written by an older program version; `payer` covers the extra rent.
+pub fn migrate_invoice(invoice: Pubkey, payer: Pubkey) -> Instruction {
+    build(
+        accounts::MigrateInvoice {
+            invoice,
+            payer,
+            system_program: system_program::ID,
+        },
+        instruction::MigrateInvoice {},
+    )
+}
+
+/// Upgrades a listing written before `LISTING_VERSION` 1; `payer` covers the extra rent.
+pub fn migrate_listing(listing: Pubkey, payer: Pubkey) -> Instruction {
+    build(
+        accounts::MigrateListing {
+            listing,
+            payer,
+            system_program: system_program::ID,
+        },
+        instruction::MigrateListing {},
+    )
+}
+
+/// Grows a config written before the settlement mint allow-list; `payer` tops up the rent.
+pub fn migrate_config(payer: Pubkey) -> Instruction {
+    build(
+        accounts::MigrateConfig {
+            config: pda::config().0,
+            payer,
+            program: ID,
+            program_data: pda::program_data(),
+            system_program: system_program::ID,
+        },
+        instruction::MigrateConfig {},
+    )
+}
+
+/// Read-only; simulate it and decode the return data with [`crate::decode_invariant_report`].
+/// `shares_mint` is `None` for invoices that never initialized one. `listings` pairs
+/// each listing with the token account backing it: [`pda::escrow_shares`] for V1
+/// listings, the seller's shares ATA for V2.
+pub fn verify_invariants(
+    invoice: Pubkey,
+    usdc_mint: Pubkey,
+    token_program: Pubkey,
+    shares_mint: Option<Pubkey>,
+    listings: &[(Pubkey, Pubkey)],
+) -> Instruction {
+    let mut ix = build(
+        accounts::VerifyInvariants {
+            invoice,
+            escrow_token: pda::escrow_token_with_program(&invoice, &usdc_mint, &token_program),
+            escrow_authority: pda::escrow_authority(&invoice).0,
+            shares_mint,
+        },
+        instruction::VerifyInvariants {},
+    );
+    for (listing, backing) in listings {
+        ix.accounts.push(AccountMeta::new_readonly(*listing, false));
+        ix.accounts.push(AccountMeta::new_readonly(*backing, false));
+    }
+    ix
+}
@@ -0,0 +1,27 @@
+//! Rust client for the `invoice_manager` program.
+//!
+//! - [`pda`]: address derivation for the program's PDAs and the token accounts it uses
+//! - [`instructions`]: typed builders for every program instruction
+//! - [`accounts`]: decoders for `Invoice`, `Listing`, `AdminConfig`, `PendingConfigChange`, the profile accounts and `RepaymentSchedule`
+//! - [`events`]: decoders for events found in transaction logs
+//!
+//! Builders return plain [`Instruction`]s; signing and sending is left to the caller.
+
+pub mod accounts;
+pub mod events;
+pub mod instructions;
+pub mod pda;
+
+pub use anchor_lang::solana_program::instruction::Instruction;
+pub use anchor_lang::solana_program::pubkey::Pubkey;
+pub use invoice_manager::{
+    self, AdminConfig, ConfigChange, ExposureLimits, Installment, InstallmentTerms, InvariantReport, InvestorAttestation,
+    InvestorPosition, InvestorProfile, Invoice, InvoiceError, InvoiceStatus, Listing, PendingConfigChange,
+    RepaymentSchedule, SellerProfile, SellerStatus, SettlementAttestation, SettlementMint, ID as PROGRAM_ID,
+    MAX_INSTALLMENTS, MAX_RISK_TIER, SHARES_TRANSFER_HOOK_ID,
+};
+
+/// Decodes the return data of a simulated `verify_invariants`.
+pub fn decode_invariant_report(data: &[u8]) -> std::io::Result<InvariantReport> {
+    anchor_lang::AnchorDeserialize::try_from_slice(data)
+}
@@ -0,0 +1,102 @@
+//! Address derivation. Seeds mirror the `seeds = [...]` constraints in the program.
+
+use anchor_lang::solana_program::pubkey::Pubkey;
+use anchor_spl::associated_token::{get_associated_token_address, get_associated_token_address_with_program_id};
+use invoice_manager::{ID, SHARES_TRANSFER_HOOK_ID};
+use solana_sdk_ids::bpf_loader_upgradeable;
+
+/// Global `AdminConfig`: `["config"]`
+pub fn config() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], &ID)
+}
+
+/// Pending timelocked config change: `["config_change"]`
+pub fn config_change() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config_change"], &ID)
+}
+
+/// Escrow authority of an invoice, owner of its USDC escrow and shares mint authority:
+/// `["escrow", invoice]`
+pub fn escrow_authority(invoice: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"escrow", invoice.as_ref()], &ID)
+}
+
+/// Listing of `seller` for `invoice`: `["listing", invoice, seller]`
+pub fn listing(invoice: &Pubkey, seller: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"listing", invoice.as_ref(), seller.as_ref()], &ID)
+}
+
+/// Marketplace authority of a listing (escrow owner in V1, delegate in V2):
+/// `["market", listing]`
+pub fn market_authority(listing: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"market", listing.as_ref()], &ID)
+}
+
+/// KYC attestation of an investor or buyer: `["attestation", investor]`
+pub fn attestation(investor: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"attestation", investor.as_ref()], &ID)
+}
+
+/// Onboarding state and counters of a seller: `["seller_profile", seller]`
+pub fn seller_profile(seller: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"seller_profile", seller.as_ref()], &ID)
+}
+
+/// Lifetime counters of an investor: `["investor_profile", investor]`
+pub fn investor_profile(investor: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"investor_profile", investor.as_ref()], &ID)
+}
+
+/// What `investor` holds in `invoice`: `["position", invoice, investor]`
+pub fn position(invoice: &Pubkey, investor: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"position", invoice.as_ref(), investor.as_ref()], &ID)
+}
+
+/// USDC token account collecting `pay_invoice` repayments, owned by the escrow
+/// authority: `["repayment", invoice]`
+pub fn repayment_vault(invoice: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"repayment", invoice.as_ref()], &ID)
+}
+
+/// Installments an invoice is repaid in: `["schedule", invoice]`
+pub fn repayment_schedule(invoice: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"schedule", invoice.as_ref()], &ID)
+}
+
+/// Transfer hook validation account of a restricted shares mint, owned by the
+/// hook program: `["extra-account-metas", mint]`
+pub fn extra_account_metas(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"extra-account-metas", mint.as_ref()], &SHARES_TRANSFER_HOOK_ID)
+}
+
+/// ProgramData account of the deployed program, required by `init_config`
+pub fn program_data() -> Pubkey {
+    Pubkey::find_program_address(&[ID.as_ref()], &bpf_loader_upgradeable::ID).0
+}
+
+/// USDC escrow token account of an invoice (ATA of the escrow authority)
+pub fn escrow_token(invoice: &Pubkey, usdc_mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(&escrow_authority(invoice).0, usdc_mint)
+}
+
+/// [`escrow_token`] for a settlement mint owned by `token_program` (Token-2022
+/// for extension mints)
+pub fn escrow_token_with_program(invoice: &Pubkey, usdc_mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    get_associated_token_address_with_program_id(&escrow_authority(invoice).0, usdc_mint, token_program)
+}
+
+/// V1 marketplace escrow holding a listing's shares (ATA of the market authority)
+pub fn escrow_shares(listing: &Pubkey, shares_mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(&market_authority(listing).0, shares_mint)
+}
+
+/// Associated token account of `owner` for `mint`
+pub fn ata(owner: &Pubkey, mint: &Pubkey) -> Pubkey {
+    get_associated_token_address(owner, mint)
+}
+
+/// Associated token account of `owner` for a `mint` owned by `token_program`
+/// (Token-2022 for restricted shares)
+pub fn ata_with_program(owner: &Pubkey, mint: &Pubkey, token_program: &Pubkey) -> Pubkey {
+    get_associated_token_address_with_program_id(owner, mint, token_program)
+}
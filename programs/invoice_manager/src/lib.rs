@@ -1,9 +1,71 @@
+// Anchor 0.31's generated IDL handlers still call `AccountInfo::realloc`.
+#![allow(deprecated)]
+
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Mint, Token, TokenAccount, Transfer, TransferChecked, MintTo, Revoke};
+use anchor_spl::token::Token;
+use anchor_spl::token_interface::{self, Mint, TokenAccount, TokenInterface, TransferChecked};
 use anchor_spl::associated_token::AssociatedToken;
+use anchor_spl::token_2022::{spl_token_2022, Token2022};
+use anchor_lang::solana_program::{ed25519_program, sysvar::instructions as ix_sysvar};
+use anchor_lang::system_program;
 
 const METADATA_MAX_LEN: usize = 128;
 
+// Layout versions stamped into each account. Bump on layout changes; new fields
+// come out of the reserved padding so existing accounts keep their size.
+pub const INVOICE_VERSION: u8 = 2;
+pub const LISTING_VERSION: u8 = 1;
+pub const CONFIG_VERSION: u8 = 2;
+pub const ATTESTATION_VERSION: u8 = 1;
+pub const PROFILE_VERSION: u8 = 2;
+pub const SCHEDULE_VERSION: u8 = 1;
+
+// AdminConfig.paused bits
+pub const PAUSE_FUNDING: u8 = 1 << 0;
+pub const PAUSE_TRADING: u8 = 1 << 1;
+pub const PAUSE_SETTLEMENT: u8 = 1 << 2;
+const PAUSE_ALL: u8 = PAUSE_FUNDING | PAUSE_TRADING | PAUSE_SETTLEMENT;
+
+// Timelock applied to queued config changes (seconds)
+pub const DEFAULT_TIMELOCK_DELAY: i64 = 2 * 24 * 60 * 60;
+pub const MAX_TIMELOCK_DELAY: i64 = 30 * 24 * 60 * 60;
+
+// Versioning carried by the unified marketplace events, one version per event so
+// it only moves when that event's fields change. Decoders should check
+// schema_version before reading further fields.
+// ListingCreated 2 appended risk_tier and target_apr_bps.
+pub const LISTING_CREATED_SCHEMA_VERSION: u8 = 2;
+pub const LISTING_FULFILLED_SCHEMA_VERSION: u8 = 1;
+pub const LISTING_CANCELED_SCHEMA_VERSION: u8 = 1;
+pub const MARKET_V1: u8 = 1;
+pub const MARKET_V2: u8 = 2;
+
+// Token-2022 transfer hook (programs/shares_transfer_hook) set on the mints created
+// by init_shares_restricted; it only lets shares move to attested investors
+pub const SHARES_TRANSFER_HOOK_ID: Pubkey = pubkey!("3sr3Q2ukAuj4CaS3LzSY8FjRVfYZCBMYMNrYhPuQpXfh");
+
+// Capacity of AdminConfig.settlement_mints
+pub const MAX_SETTLEMENT_MINTS: usize = 4;
+
+// Capacity of RepaymentSchedule.installments
+pub const MAX_INSTALLMENTS: usize = 12;
+
+// Denominator of ExposureLimits.max_investor_share_bps
+pub const BPS_DENOMINATOR: u64 = 10_000;
+
+// Invoice.risk_tier runs from 1 (lowest risk) to MAX_RISK_TIER; 0 means not yet rated
+pub const MAX_RISK_TIER: u8 = 5;
+
+// Oracle settlement attestations older (or further in the future) than this are rejected (seconds)
+pub const MAX_ATTESTATION_AGE: i64 = 60 * 60;
+
+// InvariantReport.violations bits
+pub const INVARIANT_OVERFUNDED: u8 = 1 << 0;
+pub const INVARIANT_STATUS: u8 = 1 << 1;
+pub const INVARIANT_ESCROW_SHORTFALL: u8 = 1 << 2;
+pub const INVARIANT_SHARES_EXCEED_FUNDING: u8 = 1 << 3;
+pub const INVARIANT_LISTING_UNBACKED: u8 = 1 << 4;
+
 declare_id!("F9X1Wm9yMvssSqm7Svv1UH7ZRe9YVdsffzW6krTemMDm");
 
 #[program]
@@ -15,218 +77,511 @@ pub mod invoice_manager {
         amount: u64,
         due_date: i64,
     ) -> Result<()> {
-        let invoice = &mut ctx.accounts.invoice;
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        require!(metadata_hash.len() <= METADATA_MAX_LEN, InvoiceError::InvalidParameter);
+        let listed = ctx
+            .accounts
+            .config
+            .settlement_mint(&ctx.accounts.usdc_mint.key())
+            .ok_or(InvoiceError::SettlementMintNotAllowed)?;
+        require!(ctx.accounts.usdc_mint.decimals == listed.decimals, InvoiceError::MintMismatch);
+        require!(amount >= listed.min_invoice_amount, InvoiceError::InvoiceBelowMinimum);
+        let profile = &mut ctx.accounts.seller_profile;
+        require!(profile.status == SellerStatus::Approved, InvoiceError::SellerNotApproved);
+        let exposure = profile.outstanding.checked_add(amount).ok_or(InvoiceError::MathOverflow)?;
+        require!(exposure <= profile.credit_limit, InvoiceError::CreditLimitExceeded);
+        profile.total_issued = profile.total_issued.checked_add(amount).ok_or(InvoiceError::MathOverflow)?;
+        let invoice_key = ctx.accounts.invoice.key();
+        let mut invoice = ctx.accounts.invoice.load_init()?;
         invoice.seller = *ctx.accounts.seller.key;
         invoice.amount = amount;
-        invoice.metadata_hash = metadata_hash;
+        invoice.metadata_hash[..metadata_hash.len()].copy_from_slice(metadata_hash.as_bytes());
+        invoice.metadata_len = metadata_hash.len() as u8;
         invoice.due_date = due_date;
-        invoice.status = InvoiceStatus::Open;
+        invoice.set_status(InvoiceStatus::Open);
         invoice.usdc_mint = ctx.accounts.usdc_mint.key();
         invoice.funded_amount = 0;
         invoice.investor = Pubkey::default();
         invoice.escrow_bump = 0; // set on create_escrow
         invoice.shares_mint = Pubkey::default();
+        invoice.frozen = 0;
+        invoice.seq = 0;
+        invoice.version = INVOICE_VERSION;
+
+        let clock = Clock::get()?;
+        emit!(InvoiceMinted {
+            invoice: invoice_key,
+            seller: invoice.seller,
+            usdc_mint: invoice.usdc_mint,
+            amount,
+            due_date,
+            status: invoice.status()?,
+            seq: invoice.seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
-// Events
-#[event]
-pub struct ListingFulfilledV1 {
-    pub invoice: Pubkey,
-    pub seller: Pubkey,
-    pub buyer: Pubkey,
-    pub qty: u64,
-    pub total: u64,
-}
-
-#[event]
-pub struct ListingFulfilledV2 {
-    pub invoice: Pubkey,
-    pub seller: Pubkey,
-    pub buyer: Pubkey,
-    pub qty: u64,
-    pub total: u64,
-}
-
-#[event]
-pub struct ListingCanceledV1 {
-    pub invoice: Pubkey,
-    pub seller: Pubkey,
-    pub qty: u64,
-}
-
-#[event]
-pub struct ListingCanceledV2 {
-    pub invoice: Pubkey,
-    pub seller: Pubkey,
-    pub qty: u64,
-}
-
-#[derive(Accounts)]
-pub struct CancelListingV2<'info> {
-    pub invoice: Account<'info, Invoice>,
-    #[account(mut)]
-    pub seller: Signer<'info>,
-    #[account(
-        mut,
-        seeds = [b"listing", invoice.key().as_ref(), seller.key().as_ref()],
-        bump = listing.bump,
-        constraint = listing.seller == seller.key(),
-    )]
-    pub listing: Account<'info, Listing>,
-    /// CHECK: PDA authority used as delegate in V2
-    #[account(seeds = [b"market", listing.key().as_ref()], bump = listing.market_bump)]
-    pub market_authority: UncheckedAccount<'info>,
-    #[account(
-        mut,
-        constraint = seller_shares_ata.owner == seller.key(),
-        constraint = seller_shares_ata.mint == listing.shares_mint,
-    )]
-    pub seller_shares_ata: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-}
-
- 
-
-#[derive(Accounts)]
-pub struct CreateListingV2<'info> {
-    /// Invoice for which the listing is created
-    pub invoice: Account<'info, Invoice>,
-    #[account(mut)]
-    pub seller: Signer<'info>,
-    pub shares_mint: Account<'info, Mint>,
-    pub usdc_mint: Account<'info, Mint>,
-    #[account(
-        init,
-        payer = seller,
-        seeds = [b"listing", invoice.key().as_ref(), seller.key().as_ref()],
-        bump,
-        space = 8  // disc
-            + 32  // invoice
-            + 32  // seller
-            + 32  // shares_mint
-            + 32  // usdc_mint
-            + 8   // price
-            + 8   // remaining_qty
-            + 1   // bump
-            + 1   // market_bump
-    )]
-    pub listing: Account<'info, Listing>,
-    /// CHECK: PDA authority used as delegate for allowance-based flow
-    #[account(seeds = [b"market", listing.key().as_ref()], bump)]
-    pub market_authority: UncheckedAccount<'info>,
-    pub system_program: Program<'info, System>,
-}
-
- 
-
- 
-
- 
-
     pub fn create_escrow(ctx: Context<CreateEscrow>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
         // Record bump so we can sign with PDA later
         let bump = ctx.bumps.escrow_authority;
-        ctx.accounts.invoice.escrow_bump = bump;
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        invoice.escrow_bump = bump;
+        let seq = invoice.next_seq()?;
+
+        let clock = Clock::get()?;
+        emit!(EscrowCreated {
+            invoice: ctx.accounts.invoice.key(),
+            payer: ctx.accounts.seller.key(),
+            escrow_token: ctx.accounts.escrow_token.key(),
+            status: invoice.status()?,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
     pub fn fund_invoice(ctx: Context<FundInvoice>, amount: u64) -> Result<()> {
-        let invoice = &mut ctx.accounts.invoice;
-        require!(invoice.status == InvoiceStatus::Open || invoice.status == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
+        require_attested(&ctx.accounts.config, &*ctx.accounts.invoice.load()?, &ctx.accounts.attestation, &ctx.accounts.investor.key())?;
+        let invoice_key = ctx.accounts.invoice.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(invoice.status()? == InvoiceStatus::Open || invoice.status()? == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        require!(!invoice.awaits_acknowledgement(), InvoiceError::AcknowledgementRequired);
+        // Funding closes once the debtor starts repaying
+        require!(invoice.repaid_amount == 0, InvoiceError::WrongStatus);
         require!(ctx.accounts.investor_ata.mint == invoice.usdc_mint, InvoiceError::MintMismatch);
         require!(ctx.accounts.escrow_token.mint == invoice.usdc_mint, InvoiceError::MintMismatch);
-        require!(invoice.funded_amount.saturating_add(amount) <= invoice.amount, InvoiceError::Overfund);
-
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.investor_ata.to_account_info(),
-            to: ctx.accounts.escrow_token.to_account_info(),
-            authority: ctx.accounts.investor.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
 
-        invoice.funded_amount = invoice.funded_amount.saturating_add(amount);
-        invoice.status = InvoiceStatus::Funded;
-        invoice.investor = ctx.accounts.investor.key();
+        // Credit what the escrow received, which is less than `amount` for mints
+        // with a transfer fee
+        let received = deposit_to_escrow(
+            &ctx.accounts.investor_ata,
+            &mut ctx.accounts.escrow_token,
+            &ctx.accounts.usdc_mint,
+            &ctx.accounts.investor,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        require!(invoice.funded_amount.saturating_add(received) <= invoice.amount, InvoiceError::Overfund);
+
+        let investor = ctx.accounts.investor.key();
+        let limits = ctx.accounts.config.exposure_limits;
+        ctx.accounts.position.open(invoice_key, investor, ctx.bumps.position);
+        ctx.accounts.position.acquire(&limits, &invoice, received)?;
+        // No shares are minted, so the funding is claimed on directly
+        let position = &mut ctx.accounts.position;
+        position.claim_basis = position.claim_basis.checked_add(received).ok_or(InvoiceError::MathOverflow)?;
+        ctx.accounts.seller_profile.record_funding(&limits, received)?;
+        let profile = &mut ctx.accounts.investor_profile;
+        profile.open(investor, ctx.bumps.investor_profile);
+        profile.total_funded = profile.total_funded.checked_add(received).ok_or(InvoiceError::MathOverflow)?;
+
+        invoice.funded_amount = invoice.funded_amount.saturating_add(received);
+        invoice.set_status(InvoiceStatus::Funded);
+        invoice.investor = investor;
+        let seq = invoice.next_seq()?;
+
+        let clock = Clock::get()?;
+        emit!(InvoiceFunded {
+            invoice: invoice_key,
+            investor: invoice.investor,
+            amount: received,
+            funded_amount: invoice.funded_amount,
+            shares_minted: 0,
+            status: invoice.status()?,
+            risk_tier: invoice.risk_tier,
+            target_apr_bps: invoice.target_apr_bps,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
-    pub fn set_settled(ctx: Context<SetSettled>, amount: u64) -> Result<()> {
-        let invoice = &mut ctx.accounts.invoice;
+    // Settlement is authorized either by the admin signing as operator, or by a
+    // registered payment oracle whose Ed25519 signature over the attestation
+    // message is verified by the instruction immediately preceding this one.
+    // In the oracle mode any relayer may submit the transaction. Once the
+    // debtor has paid anything through pay_invoice, only pay_invoice can
+    // settle, so every installment is shared out by the same rules.
+    pub fn set_settled(
+        ctx: Context<SetSettled>,
+        amount: u64,
+        attestation: Option<SettlementAttestation>,
+    ) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_SETTLEMENT)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
+        let invoice_key = ctx.accounts.invoice.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
         require!(amount > 0, InvoiceError::InvalidParameter);
-        require!(invoice.status == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        require!(invoice.status()? == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        require!(invoice.repaid_amount == 0, InvoiceError::WrongStatus);
         require!(invoice.funded_amount == amount, InvoiceError::Overfund);
-        require_keys_eq!(ctx.accounts.operator.key(), ctx.accounts.config.admin, InvoiceError::Unauthorized);
+        let oracle_attested = attestation.is_some();
+        match attestation {
+            None => require_keys_eq!(ctx.accounts.operator.key(), ctx.accounts.config.admin, InvoiceError::Unauthorized),
+            Some(attestation) => {
+                let oracle = ctx.accounts.config.settlement_oracle;
+                require!(oracle != Pubkey::default(), InvoiceError::OracleNotConfigured);
+                let now = Clock::get()?.unix_timestamp;
+                let age = now.checked_sub(attestation.timestamp).ok_or(InvoiceError::MathOverflow)?;
+                require!(age.abs() <= MAX_ATTESTATION_AGE, InvoiceError::StaleAttestation);
+                let message = attestation.message(&invoice_key, amount);
+                verify_ed25519_ix(&ctx.accounts.instructions.to_account_info(), &oracle, &message)?;
+            }
+        }
         require!(ctx.accounts.seller_ata.mint == invoice.usdc_mint, InvoiceError::MintMismatch);
         require!(ctx.accounts.escrow_token.mint == invoice.usdc_mint, InvoiceError::MintMismatch);
 
-        let bump = invoice.escrow_bump;
-        let invoice_key = invoice.key();
-        let signer_seeds: &[&[u8]] = &[b"escrow", invoice_key.as_ref(), &[bump]];
-        let signer: &[&[&[u8]]] = &[signer_seeds];
+        release_escrow(
+            &invoice_key,
+            invoice.escrow_bump,
+            &ctx.accounts.escrow_token,
+            &ctx.accounts.usdc_mint,
+            &ctx.accounts.seller_ata,
+            &ctx.accounts.escrow_authority,
+            &ctx.accounts.token_program,
+            &ctx.accounts.seller_profile,
+            amount,
+        )?;
+
+        invoice.set_status(InvoiceStatus::Settled);
+        let seq = invoice.next_seq()?;
+
+        let clock = Clock::get()?;
+        emit!(InvoiceSettled {
+            invoice: invoice_key,
+            operator: ctx.accounts.operator.key(),
+            seller: invoice.seller,
+            amount,
+            oracle_attested,
+            status: invoice.status()?,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
 
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.escrow_token.to_account_info(),
-            to: ctx.accounts.seller_ata.to_account_info(),
-            authority: ctx.accounts.escrow_authority.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            cpi_accounts,
-            signer,
-        );
-        token::transfer(cpi_ctx, amount)?;
+    // Direct repayment: anyone, usually the debtor, deposits USDC into the
+    // invoice's repayment vault, in as many parts as needed. The payment that
+    // brings repaid_amount to face value settles the invoice like set_settled.
+    pub fn pay_invoice(ctx: Context<PayInvoice>, amount: u64) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_SETTLEMENT)?;
+        let invoice_key = ctx.accounts.invoice.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        require!(invoice.status()? == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        require!(amount > 0, InvoiceError::InvalidParameter);
+
+        let received = deposit_to_escrow(
+            &ctx.accounts.payer_ata,
+            &mut ctx.accounts.repayment_vault,
+            &ctx.accounts.usdc_mint,
+            &ctx.accounts.payer,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        let repaid = invoice.repaid_amount.checked_add(received).ok_or(InvoiceError::MathOverflow)?;
+        require!(repaid <= invoice.amount, InvoiceError::Overpayment);
+        invoice.repaid_amount = repaid;
+        invoice.repayment_bump = ctx.bumps.repayment_vault;
+        update_if_exists(&ctx.accounts.repayment_schedule, |schedule: &mut RepaymentSchedule| {
+            schedule.apply_payment(received)
+        })?;
+        let seq = invoice.next_seq()?;
+
+        let clock = Clock::get()?;
+        emit!(InvoicePaid {
+            invoice: invoice_key,
+            payer: ctx.accounts.payer.key(),
+            amount: received,
+            repaid_amount: repaid,
+            status: invoice.status()?,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        if repaid < invoice.amount {
+            return Ok(());
+        }
+
+        let funded = invoice.funded_amount;
+        release_escrow(
+            &invoice_key,
+            invoice.escrow_bump,
+            &ctx.accounts.escrow_token,
+            &ctx.accounts.usdc_mint,
+            &ctx.accounts.seller_ata,
+            &ctx.accounts.escrow_authority,
+            &ctx.accounts.token_program,
+            &ctx.accounts.seller_profile,
+            funded,
+        )?;
+        invoice.set_status(InvoiceStatus::Settled);
+        let seq = invoice.next_seq()?;
+        emit!(InvoiceSettled {
+            invoice: invoice_key,
+            operator: ctx.accounts.payer.key(),
+            seller: invoice.seller,
+            amount: funded,
+            oracle_attested: false,
+            status: invoice.status()?,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
 
-        invoice.status = InvoiceStatus::Settled;
+    // Splits repayment into installments. Set by the seller before funding
+    // starts; calling it again replaces the schedule.
+    pub fn set_repayment_schedule(ctx: Context<SetRepaymentSchedule>, installments: Vec<InstallmentTerms>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        let invoice_key = ctx.accounts.invoice.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require_keys_eq!(ctx.accounts.seller.key(), invoice.seller, InvoiceError::Unauthorized);
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        require!(invoice.status()? == InvoiceStatus::Open && invoice.funded_amount == 0, InvoiceError::WrongStatus);
+        let schedule = &mut ctx.accounts.repayment_schedule;
+        schedule.open(invoice_key, ctx.bumps.repayment_schedule);
+        schedule.set(&invoice, &installments)?;
+        let seq = invoice.next_seq()?;
+        let clock = Clock::get()?;
+        emit!(RepaymentScheduleSet {
+            invoice: invoice_key,
+            seller: invoice.seller,
+            installment_count: schedule.installment_count,
+            first_due_date: installments[0].due_date,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Permissionless: flags every installment that is past its due date and not
+    // fully repaid, emitting InstallmentOverdue once per installment.
+    pub fn flag_overdue_installments(ctx: Context<FlagOverdueInstallments>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_SETTLEMENT)?;
+        let invoice_key = ctx.accounts.invoice.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        require!(invoice.status()? == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        let clock = Clock::get()?;
+        let schedule = &mut ctx.accounts.repayment_schedule;
+        let count = schedule.installment_count as usize;
+        let mut flagged = 0;
+        for (index, installment) in schedule.installments[..count].iter_mut().enumerate() {
+            if installment.overdue != 0 || !installment.is_overdue(clock.unix_timestamp) {
+                continue;
+            }
+            installment.overdue = 1;
+            flagged += 1;
+            let seq = invoice.next_seq()?;
+            emit!(InstallmentOverdue {
+                invoice: invoice_key,
+                index: index as u8,
+                due_date: installment.due_date,
+                amount: installment.amount,
+                repaid: installment.repaid,
+                seq,
+                slot: clock.slot,
+                timestamp: clock.unix_timestamp,
+            });
+        }
+        require!(flagged > 0, InvoiceError::InvoiceNotDue);
+        Ok(())
+    }
+
+    // Pays out the claimant's pro-rata share of the repayments received so far.
+    // Claims follow the shares themselves: every share the claimant holds is
+    // burned into its position's claim_basis, which can't change hands, and the
+    // basis earns claim_basis / face value of every payment. The seller also
+    // claims the share of the face value that nobody funded. Burning takes the
+    // whole balance, so the first claim takes the position off the market for
+    // good, however much of the face value is still unpaid.
+    pub fn claim_repayment(ctx: Context<ClaimRepayment>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_SETTLEMENT)?;
+        let invoice_key = ctx.accounts.invoice.key();
+        let claimant = ctx.accounts.claimant.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        let status = invoice.status()?;
+        require!(status == InvoiceStatus::Funded || status == InvoiceStatus::Settled, InvoiceError::WrongStatus);
+
+        let redeemed = burn_held_shares(
+            &ctx.accounts.shares_mint,
+            &ctx.accounts.claimant_shares,
+            &ctx.accounts.shares_token_program,
+            &ctx.accounts.claimant,
+        )?;
+        let position = &mut ctx.accounts.position;
+        position.open(invoice_key, claimant, ctx.bumps.position);
+        position.claim_basis = position.claim_basis.checked_add(redeemed).ok_or(InvoiceError::MathOverflow)?;
+        let from_position = pro_rata(position.claim_basis, invoice.repaid_amount, invoice.amount)
+            .saturating_sub(position.claimed_repayment);
+        position.claimed_repayment = position.claimed_repayment.checked_add(from_position).ok_or(InvoiceError::MathOverflow)?;
+        let from_seller_share = if claimant == invoice.seller {
+            invoice.seller_repayment().saturating_sub(invoice.seller_claimed)
+        } else {
+            0
+        };
+        let amount = from_position.checked_add(from_seller_share).ok_or(InvoiceError::MathOverflow)?;
+        require!(amount > 0, InvoiceError::NothingToClaim);
+        invoice.investors_claimed = invoice.investors_claimed.checked_add(from_position).ok_or(InvoiceError::MathOverflow)?;
+        invoice.seller_claimed = invoice.seller_claimed.checked_add(from_seller_share).ok_or(InvoiceError::MathOverflow)?;
+
+        transfer_as_escrow_authority(
+            &invoice_key,
+            invoice.escrow_bump,
+            &ctx.accounts.repayment_vault,
+            &ctx.accounts.usdc_mint,
+            &ctx.accounts.claimant_ata,
+            &ctx.accounts.escrow_authority,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        let seq = invoice.next_seq()?;
+        let clock = Clock::get()?;
+        emit!(RepaymentClaimed {
+            invoice: invoice_key,
+            claimant,
+            amount,
+            repaid_amount: invoice.repaid_amount,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
     pub fn init_shares(ctx: Context<InitShares>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
         // Initialize per-invoice shares mint; authority is escrow PDA
-        ctx.accounts.invoice.shares_mint = ctx.accounts.shares_mint.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(invoice.shares_mint == Pubkey::default(), InvoiceError::SharesAlreadyInitialized);
+        invoice.shares_mint = ctx.accounts.shares_mint.key();
+        let seq = invoice.next_seq()?;
+
+        let clock = Clock::get()?;
+        emit!(SharesInitialized {
+            invoice: ctx.accounts.invoice.key(),
+            payer: ctx.accounts.payer.key(),
+            shares_mint: ctx.accounts.shares_mint.key(),
+            status: invoice.status()?,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Like init_shares, but the mint is a Token-2022 mint whose transfer hook only
+    // lets shares reach attested investors, and funding requires an attestation
+    // even when KYC is off. The hook's extra-account-meta list must be created
+    // before the first marketplace transfer.
+    pub fn init_shares_restricted(ctx: Context<InitSharesRestricted>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(invoice.shares_mint == Pubkey::default(), InvoiceError::SharesAlreadyInitialized);
+        invoice.shares_mint = ctx.accounts.shares_mint.key();
+        invoice.shares_restricted = 1;
+        let seq = invoice.next_seq()?;
+
+        let clock = Clock::get()?;
+        emit!(SharesInitialized {
+            invoice: ctx.accounts.invoice.key(),
+            payer: ctx.accounts.payer.key(),
+            shares_mint: ctx.accounts.shares_mint.key(),
+            status: invoice.status()?,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
     pub fn fund_invoice_fractional(ctx: Context<FundInvoiceFractional>, amount: u64) -> Result<()> {
-        let invoice = &mut ctx.accounts.invoice;
-        require!(invoice.status == InvoiceStatus::Open || invoice.status == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
+        require_attested(&ctx.accounts.config, &*ctx.accounts.invoice.load()?, &ctx.accounts.attestation, &ctx.accounts.investor.key())?;
+        let invoice_key = ctx.accounts.invoice.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(invoice.status()? == InvoiceStatus::Open || invoice.status()? == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        require!(!invoice.awaits_acknowledgement(), InvoiceError::AcknowledgementRequired);
+        // Funding closes once the debtor starts repaying
+        require!(invoice.repaid_amount == 0, InvoiceError::WrongStatus);
         require!(invoice.escrow_bump != 0, InvoiceError::BumpNotFound);
         require!(ctx.accounts.investor_ata.mint == invoice.usdc_mint, InvoiceError::MintMismatch);
         require!(ctx.accounts.escrow_token.mint == invoice.usdc_mint, InvoiceError::MintMismatch);
         // shares mint must be set and match
         require!(ctx.accounts.shares_mint.key() == invoice.shares_mint, InvoiceError::SharesMintMissing);
-        require!(invoice.funded_amount.saturating_add(amount) <= invoice.amount, InvoiceError::Overfund);
 
-        // Transfer USDC from investor to escrow
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.investor_ata.to_account_info(),
-            to: ctx.accounts.escrow_token.to_account_info(),
-            authority: ctx.accounts.investor.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_ctx, amount)?;
+        // Transfer USDC from investor to escrow; shares and funding follow what
+        // the escrow received, net of any transfer fee
+        let received = deposit_to_escrow(
+            &ctx.accounts.investor_ata,
+            &mut ctx.accounts.escrow_token,
+            &ctx.accounts.usdc_mint,
+            &ctx.accounts.investor,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        require!(invoice.funded_amount.saturating_add(received) <= invoice.amount, InvoiceError::Overfund);
 
         // Mint fractional shares to investor using escrow PDA as authority
         let bump = invoice.escrow_bump;
-        let invoice_key = invoice.key();
         let signer_seeds: &[&[u8]] = &[b"escrow", invoice_key.as_ref(), &[bump]];
         let signer: &[&[&[u8]]] = &[signer_seeds];
-        let mint_accounts = MintTo {
+        let mint_accounts = token_interface::MintTo {
             mint: ctx.accounts.shares_mint.to_account_info(),
             to: ctx.accounts.investor_shares_ata.to_account_info(),
             authority: ctx.accounts.escrow_authority.to_account_info(),
         };
         let mint_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.shares_token_program.to_account_info(),
             mint_accounts,
             signer,
         );
-        token::mint_to(mint_ctx, amount)?;
-
-        invoice.funded_amount = invoice.funded_amount.saturating_add(amount);
-        invoice.status = InvoiceStatus::Funded;
-        invoice.investor = ctx.accounts.investor.key();
+        token_interface::mint_to(mint_ctx, received)?;
+
+        let investor = ctx.accounts.investor.key();
+        let limits = ctx.accounts.config.exposure_limits;
+        ctx.accounts.position.open(invoice_key, investor, ctx.bumps.position);
+        ctx.accounts.position.acquire(&limits, &invoice, received)?;
+        ctx.accounts.seller_profile.record_funding(&limits, received)?;
+        let profile = &mut ctx.accounts.investor_profile;
+        profile.open(investor, ctx.bumps.investor_profile);
+        profile.total_funded = profile.total_funded.checked_add(received).ok_or(InvoiceError::MathOverflow)?;
+
+        invoice.funded_amount = invoice.funded_amount.saturating_add(received);
+        invoice.set_status(InvoiceStatus::Funded);
+        invoice.investor = investor;
+        let seq = invoice.next_seq()?;
+
+        let clock = Clock::get()?;
+        emit!(InvoiceFunded {
+            invoice: invoice_key,
+            investor: invoice.investor,
+            amount: received,
+            funded_amount: invoice.funded_amount,
+            shares_minted: received,
+            status: invoice.status()?,
+            risk_tier: invoice.risk_tier,
+            target_apr_bps: invoice.target_apr_bps,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
@@ -236,22 +591,26 @@ pub struct CreateListingV2<'info> {
     // - cancel_listing: seller retrieves remaining shares from escrow
 
     pub fn create_listing(ctx: Context<CreateListing>, qty: u64, price: u64) -> Result<()> {
-        let invoice = &ctx.accounts.invoice;
+        ctx.accounts.config.require_not_paused(PAUSE_TRADING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
+        let invoice_key = ctx.accounts.invoice.key();
+        let invoice = ctx.accounts.invoice.load()?;
         require!(ctx.accounts.shares_mint.key() == invoice.shares_mint, InvoiceError::SharesMintMissing);
         require!(ctx.accounts.usdc_mint.key() == invoice.usdc_mint, InvoiceError::MintMismatch);
         require!(qty > 0 && price > 0, InvoiceError::InvalidParameter);
 
-        // Transfer shares from seller to escrow
-        let cpi_accounts = Transfer {
-            from: ctx.accounts.seller_shares_ata.to_account_info(),
-            to: ctx.accounts.escrow_shares_ata.to_account_info(),
-            authority: ctx.accounts.seller.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), cpi_accounts);
-        token::transfer(cpi_ctx, qty)?;
+        // Transfer shares from seller to escrow; list what the escrow received
+        let qty = deposit_to_escrow(
+            &ctx.accounts.seller_shares_ata,
+            &mut ctx.accounts.escrow_shares_ata,
+            &ctx.accounts.shares_mint,
+            &ctx.accounts.seller,
+            &ctx.accounts.token_program,
+            qty,
+        )?;
 
         let listing = &mut ctx.accounts.listing;
-        listing.invoice = invoice.key();
+        listing.invoice = invoice_key;
         listing.seller = ctx.accounts.seller.key();
         listing.shares_mint = ctx.accounts.shares_mint.key();
         listing.usdc_mint = ctx.accounts.usdc_mint.key();
@@ -259,17 +618,38 @@ pub struct CreateListingV2<'info> {
         listing.remaining_qty = qty;
         listing.bump = ctx.bumps.listing;
         listing.market_bump = ctx.bumps.market_authority;
+        listing.seq = 0;
+        listing.version = LISTING_VERSION;
+
+        let clock = Clock::get()?;
+        emit!(ListingCreated {
+            schema_version: LISTING_CREATED_SCHEMA_VERSION,
+            market_version: MARKET_V1,
+            invoice: invoice_key,
+            listing: listing.key(),
+            seller: listing.seller,
+            price,
+            qty,
+            seq: listing.seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+            risk_tier: invoice.risk_tier,
+            target_apr_bps: invoice.target_apr_bps,
+        });
         Ok(())
     }
 
     pub fn create_listing_v2(ctx: Context<CreateListingV2>, qty: u64, price: u64) -> Result<()> {
-        let invoice = &ctx.accounts.invoice;
+        ctx.accounts.config.require_not_paused(PAUSE_TRADING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
+        let invoice_key = ctx.accounts.invoice.key();
+        let invoice = ctx.accounts.invoice.load()?;
         require!(ctx.accounts.shares_mint.key() == invoice.shares_mint, InvoiceError::SharesMintMissing);
         require!(ctx.accounts.usdc_mint.key() == invoice.usdc_mint, InvoiceError::MintMismatch);
         require!(qty > 0 && price > 0, InvoiceError::InvalidParameter);
 
         let listing = &mut ctx.accounts.listing;
-        listing.invoice = invoice.key();
+        listing.invoice = invoice_key;
         listing.seller = ctx.accounts.seller.key();
         listing.shares_mint = ctx.accounts.shares_mint.key();
         listing.usdc_mint = ctx.accounts.usdc_mint.key();
@@ -277,15 +657,37 @@ pub struct CreateListingV2<'info> {
         listing.remaining_qty = qty;
         listing.bump = ctx.bumps.listing;
         listing.market_bump = ctx.bumps.market_authority;
+        listing.seq = 0;
+        listing.version = LISTING_VERSION;
+
+        let clock = Clock::get()?;
+        emit!(ListingCreated {
+            schema_version: LISTING_CREATED_SCHEMA_VERSION,
+            market_version: MARKET_V2,
+            invoice: invoice_key,
+            listing: listing.key(),
+            seller: listing.seller,
+            price,
+            qty,
+            seq: listing.seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+            risk_tier: invoice.risk_tier,
+            target_apr_bps: invoice.target_apr_bps,
+        });
         Ok(())
     }
 
     pub fn fulfill_listing(ctx: Context<FulfillListing>, qty: u64) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_TRADING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
+        require_attested(&ctx.accounts.config, &*ctx.accounts.invoice.load()?, &ctx.accounts.attestation, &ctx.accounts.buyer.key())?;
         let listing_key = ctx.accounts.listing.key();
         let market_bump = ctx.accounts.listing.market_bump;
         let listing = &mut ctx.accounts.listing;
-        let invoice = &ctx.accounts.invoice;
-        require!(listing.invoice == invoice.key(), InvoiceError::ListingMismatch);
+        let invoice_key = ctx.accounts.invoice.key();
+        let invoice = ctx.accounts.invoice.load()?;
+        require!(listing.invoice == invoice_key, InvoiceError::ListingMismatch);
         require!(listing.shares_mint == invoice.shares_mint, InvoiceError::SharesMintMissing);
         require!(listing.usdc_mint == invoice.usdc_mint, InvoiceError::MintMismatch);
         require!(qty > 0 && qty <= listing.remaining_qty, InvoiceError::InsufficientEscrow);
@@ -295,51 +697,84 @@ pub struct CreateListingV2<'info> {
             .checked_div(1_000_000)
             .ok_or(InvoiceError::MathOverflow)?;
 
-        // Transfer USDC from buyer to seller
-        let usdc_transfer = Transfer {
+        // Transfer USDC from buyer to seller; a transfer fee comes out of the seller's side
+        let usdc_transfer = TransferChecked {
             from: ctx.accounts.buyer_usdc_ata.to_account_info(),
+            mint: ctx.accounts.usdc_mint.to_account_info(),
             to: ctx.accounts.seller_usdc_ata.to_account_info(),
             authority: ctx.accounts.buyer.to_account_info(),
         };
         let usdc_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), usdc_transfer);
-        token::transfer(usdc_ctx, total)?;
+        token_interface::transfer_checked(usdc_ctx, total, ctx.accounts.usdc_mint.decimals)?;
 
         // Transfer shares from escrow to buyer using market authority PDA signer
         let seeds: &[&[u8]] = &[b"market", listing_key.as_ref(), &[market_bump]];
         let signer: &[&[&[u8]]] = &[seeds];
-        let share_transfer = Transfer {
+        let share_transfer = TransferChecked {
             from: ctx.accounts.escrow_shares_ata.to_account_info(),
+            mint: ctx.accounts.shares_mint.to_account_info(),
             to: ctx.accounts.buyer_shares_ata.to_account_info(),
             authority: ctx.accounts.market_authority.to_account_info(),
         };
         let share_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
+            ctx.accounts.shares_token_program.to_account_info(),
             share_transfer,
             signer,
         );
-        token::transfer(share_ctx, qty)?;
+        token_interface::transfer_checked(share_ctx, qty, ctx.accounts.shares_mint.decimals)?;
+        let buyer = ctx.accounts.buyer.key();
+        ctx.accounts.buyer_profile.open(buyer, ctx.bumps.buyer_profile);
+        ctx.accounts.buyer_position.open(invoice_key, buyer, ctx.bumps.buyer_position);
+        record_purchase(
+            &ctx.accounts.config.exposure_limits,
+            &invoice,
+            listing.seller,
+            &mut ctx.accounts.buyer_profile,
+            &mut ctx.accounts.buyer_position,
+            &ctx.accounts.seller_position,
+            qty,
+        )?;
 
         listing.remaining_qty = listing.remaining_qty.saturating_sub(qty);
-        // Emit event
+        let seq = listing.next_seq()?;
+        // Emit the unified event, then the deprecated per-version one
+        let clock = Clock::get()?;
+        emit!(ListingFulfilled {
+            schema_version: LISTING_FULFILLED_SCHEMA_VERSION,
+            market_version: MARKET_V1,
+            invoice: invoice_key,
+            listing: listing_key,
+            seller: listing.seller,
+            buyer: ctx.accounts.buyer.key(),
+            qty,
+            total,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         emit!(ListingFulfilledV1 {
-            invoice: invoice.key(),
+            invoice: invoice_key,
             seller: listing.seller,
             buyer: ctx.accounts.buyer.key(),
             qty,
             total,
+            seq,
         });
         Ok(())
     }
 
     pub fn cancel_listing(ctx: Context<CancelListing>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_TRADING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
         let listing_key = ctx.accounts.listing.key();
         let market_bump = ctx.accounts.listing.market_bump;
         let remaining = ctx.accounts.listing.remaining_qty;
         if remaining > 0 {
             let seeds: &[&[u8]] = &[b"market", listing_key.as_ref(), &[market_bump]];
             let signer: &[&[&[u8]]] = &[seeds];
-            let share_transfer = Transfer {
+            let share_transfer = TransferChecked {
                 from: ctx.accounts.escrow_shares_ata.to_account_info(),
+                mint: ctx.accounts.shares_mint.to_account_info(),
                 to: ctx.accounts.seller_shares_ata.to_account_info(),
                 authority: ctx.accounts.market_authority.to_account_info(),
             };
@@ -348,14 +783,28 @@ pub struct CreateListingV2<'info> {
                 share_transfer,
                 signer,
             );
-            token::transfer(share_ctx, remaining)?;
+            token_interface::transfer_checked(share_ctx, remaining, ctx.accounts.shares_mint.decimals)?;
             ctx.accounts.listing.remaining_qty = 0;
         }
-        // Emit event for V1 cancel
+        let seq = ctx.accounts.listing.next_seq()?;
+        // Emit the unified event, then the deprecated per-version one
+        let clock = Clock::get()?;
+        emit!(ListingCanceled {
+            schema_version: LISTING_CANCELED_SCHEMA_VERSION,
+            market_version: MARKET_V1,
+            invoice: ctx.accounts.invoice.key(),
+            listing: listing_key,
+            seller: ctx.accounts.listing.seller,
+            qty: remaining,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         emit!(ListingCanceledV1 {
             invoice: ctx.accounts.invoice.key(),
             seller: ctx.accounts.listing.seller,
             qty: remaining,
+            seq,
         });
         Ok(())
     }
@@ -364,13 +813,24 @@ pub struct CreateListingV2<'info> {
     // - Seller approves shares to marketplace_authority (delegate)
     // - Buyer approves USDC to marketplace_authority (delegate)
     // - Program atomically swaps via transfer_checked using PDA signer as delegate
-    pub fn fulfill_listing_v2(ctx: Context<FulfillListingV2>, qty: u64) -> Result<()> {
+    //
+    // Restricted (Token-2022) shares pass the transfer hook's extra accounts as
+    // remaining accounts: the extra-account-meta list, the invoice_manager
+    // program, the buyer's attestation PDA and the hook program.
+    pub fn fulfill_listing_v2<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FulfillListingV2<'info>>,
+        qty: u64,
+    ) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_TRADING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
+        require_attested(&ctx.accounts.config, &*ctx.accounts.invoice.load()?, &ctx.accounts.attestation, &ctx.accounts.buyer.key())?;
         let listing_key = ctx.accounts.listing.key();
         let market_bump = ctx.accounts.listing.market_bump;
         let listing = &mut ctx.accounts.listing;
-        let invoice = &ctx.accounts.invoice;
+        let invoice_key = ctx.accounts.invoice.key();
+        let invoice = ctx.accounts.invoice.load()?;
 
-        require!(listing.invoice == invoice.key(), InvoiceError::ListingMismatch);
+        require!(listing.invoice == invoice_key, InvoiceError::ListingMismatch);
         require!(listing.shares_mint == invoice.shares_mint, InvoiceError::SharesMintMissing);
         require!(listing.usdc_mint == invoice.usdc_mint, InvoiceError::MintMismatch);
         require!(qty > 0 && qty <= listing.remaining_qty, InvoiceError::InsufficientEscrow);
@@ -408,165 +868,1461 @@ pub struct CreateListingV2<'info> {
             usdc_transfer,
             signer,
         );
-        token::transfer_checked(usdc_ctx, total, ctx.accounts.usdc_mint.decimals)?;
-
-        // Transfer shares from seller to buyer using PDA as delegate authority
-        let share_transfer = TransferChecked {
-            from: ctx.accounts.seller_shares_ata.to_account_info(),
-            to: ctx.accounts.buyer_shares_ata.to_account_info(),
-            authority: ctx.accounts.market_authority.to_account_info(),
-            mint: ctx.accounts.shares_mint.to_account_info(),
-        };
-        let share_ctx = CpiContext::new_with_signer(
-            ctx.accounts.token_program.to_account_info(),
-            share_transfer,
-            signer,
-        );
-        token::transfer_checked(share_ctx, qty, ctx.accounts.shares_mint.decimals)?;
+        token_interface::transfer_checked(usdc_ctx, total, ctx.accounts.usdc_mint.decimals)?;
+
+        // Transfer shares from seller to buyer using PDA as delegate authority.
+        // Built by hand because anchor-spl's transfer_checked drops the extra
+        // accounts a transfer hook needs.
+        let mut share_ix = spl_token_2022::instruction::transfer_checked(
+            ctx.accounts.shares_token_program.key,
+            &ctx.accounts.seller_shares_ata.key(),
+            &ctx.accounts.shares_mint.key(),
+            &ctx.accounts.buyer_shares_ata.key(),
+            ctx.accounts.market_authority.key,
+            &[],
+            qty,
+            ctx.accounts.shares_mint.decimals,
+        )?;
+        let mut share_infos = vec![
+            ctx.accounts.seller_shares_ata.to_account_info(),
+            ctx.accounts.shares_mint.to_account_info(),
+            ctx.accounts.buyer_shares_ata.to_account_info(),
+            ctx.accounts.market_authority.to_account_info(),
+        ];
+        for extra in ctx.remaining_accounts {
+            share_ix.accounts.push(AccountMeta::new_readonly(*extra.key, false));
+            share_infos.push(extra.clone());
+        }
+        anchor_lang::solana_program::program::invoke_signed(&share_ix, &share_infos, signer)?;
+        let buyer = ctx.accounts.buyer.key();
+        ctx.accounts.buyer_profile.open(buyer, ctx.bumps.buyer_profile);
+        ctx.accounts.buyer_position.open(invoice_key, buyer, ctx.bumps.buyer_position);
+        record_purchase(
+            &ctx.accounts.config.exposure_limits,
+            &invoice,
+            listing.seller,
+            &mut ctx.accounts.buyer_profile,
+            &mut ctx.accounts.buyer_position,
+            &ctx.accounts.seller_position,
+            qty,
+        )?;
 
         // Update remaining planned quantity on listing
         listing.remaining_qty = listing.remaining_qty.saturating_sub(qty);
-        // Emit event
+        let seq = listing.next_seq()?;
+        // Emit the unified event, then the deprecated per-version one
+        let clock = Clock::get()?;
+        emit!(ListingFulfilled {
+            schema_version: LISTING_FULFILLED_SCHEMA_VERSION,
+            market_version: MARKET_V2,
+            invoice: invoice_key,
+            listing: listing_key,
+            seller: listing.seller,
+            buyer: ctx.accounts.buyer.key(),
+            qty,
+            total,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         emit!(ListingFulfilledV2 {
-            invoice: invoice.key(),
+            invoice: invoice_key,
             seller: listing.seller,
             buyer: ctx.accounts.buyer.key(),
             qty,
             total,
+            seq,
         });
         Ok(())
     }
 
     pub fn cancel_listing_v2(ctx: Context<CancelListingV2>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_TRADING)?;
+        require!(!ctx.accounts.invoice.load()?.is_frozen(), InvoiceError::InvoiceFrozen);
         // Ensure listing matches invoice and signer is seller
-        let invoice = &ctx.accounts.invoice;
+        let invoice_key = ctx.accounts.invoice.key();
         let listing = &mut ctx.accounts.listing;
-        require!(listing.invoice == invoice.key(), InvoiceError::ListingMismatch);
+        require!(listing.invoice == invoice_key, InvoiceError::ListingMismatch);
         require!(listing.seller == ctx.accounts.seller.key(), InvoiceError::ListingMismatch);
 
         // If seller shares ATA delegated to market_authority, revoke it
         use anchor_lang::solana_program::program_option::COption;
         if ctx.accounts.seller_shares_ata.delegate == COption::Some(ctx.accounts.market_authority.key()) {
-            let revoke_accounts = Revoke {
+            let revoke_accounts = token_interface::Revoke {
                 source: ctx.accounts.seller_shares_ata.to_account_info(),
                 authority: ctx.accounts.seller.to_account_info(),
             };
             let revoke_ctx = CpiContext::new(ctx.accounts.token_program.to_account_info(), revoke_accounts);
-            token::revoke(revoke_ctx)?;
+            token_interface::revoke(revoke_ctx)?;
         }
 
         // Set remaining qty to 0 to reflect cancellation on-chain
         let canceled = listing.remaining_qty;
         listing.remaining_qty = 0;
-        // Emit event
+        let seq = listing.next_seq()?;
+        // Emit the unified event, then the deprecated per-version one
+        let clock = Clock::get()?;
+        emit!(ListingCanceled {
+            schema_version: LISTING_CANCELED_SCHEMA_VERSION,
+            market_version: MARKET_V2,
+            invoice: invoice_key,
+            listing: listing.key(),
+            seller: listing.seller,
+            qty: canceled,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         emit!(ListingCanceledV2 {
-            invoice: invoice.key(),
+            invoice: invoice_key,
             seller: listing.seller,
             qty: canceled,
+            seq,
         });
         Ok(())
     }
     pub fn init_config(ctx: Context<InitConfig>, admin: Pubkey) -> Result<()> {
+        // Only the upgrade authority may claim the singleton config; the accounts
+        // constraints tie payer to the ProgramData of this program.
         ctx.accounts.config.admin = admin;
+        ctx.accounts.config.pending_admin = Pubkey::default();
+        ctx.accounts.config.deployer = ctx.accounts.payer.key();
+        ctx.accounts.config.paused = 0;
+        ctx.accounts.config.compliance = admin;
+        ctx.accounts.config.underwriter = admin;
+        ctx.accounts.config.timelock_delay = DEFAULT_TIMELOCK_DELAY;
+        ctx.accounts.config.settlement_oracle = Pubkey::default();
+        ctx.accounts.config.version = CONFIG_VERSION;
+
+        let clock = Clock::get()?;
+        emit!(ConfigInitialized {
+            admin,
+            deployer: ctx.accounts.config.deployer,
+            timelock_delay: ctx.accounts.config.timelock_delay,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
 
-    pub fn update_config(ctx: Context<UpdateConfig>, new_admin: Pubkey) -> Result<()> {
+    // Timelocked config changes:
+    // - queue_config_change: admin records the change in the config_change PDA with an eta
+    // - execute_config_change: applies it once eta has passed and closes the PDA
+    // - cancel_config_change: admin drops it during the delay
+    // Only one change can be pending at a time; it is readable by anyone.
+    pub fn queue_config_change(ctx: Context<QueueConfigChange>, change: ConfigChange) -> Result<()> {
         require_keys_eq!(ctx.accounts.admin.key(), ctx.accounts.config.admin, InvoiceError::Unauthorized);
-        ctx.accounts.config.admin = new_admin;
+        match change {
+            ConfigChange::Admin(new_admin) => {
+                require!(new_admin != Pubkey::default(), InvoiceError::InvalidParameter)
+            }
+            ConfigChange::Compliance(_) | ConfigChange::SettlementOracle(_) | ConfigChange::Underwriter(_) => {}
+            ConfigChange::TimelockDelay(delay) => {
+                require!((0..=MAX_TIMELOCK_DELAY).contains(&delay), InvoiceError::InvalidParameter)
+            }
+            ConfigChange::AddSettlementMint(entry) => {
+                require!(entry.mint != Pubkey::default(), InvoiceError::InvalidParameter);
+                let config = &ctx.accounts.config;
+                require!(
+                    config.settlement_mint(&entry.mint).is_some() || config.settlement_mints().len() < MAX_SETTLEMENT_MINTS,
+                    InvoiceError::SettlementMintListFull
+                );
+            }
+            ConfigChange::RemoveSettlementMint(mint) => {
+                require!(ctx.accounts.config.settlement_mint(&mint).is_some(), InvoiceError::SettlementMintNotAllowed)
+            }
+            ConfigChange::ExposureLimits(limits) => {
+                require!(limits.max_investor_share_bps as u64 <= BPS_DENOMINATOR, InvoiceError::InvalidParameter)
+            }
+        }
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let eta = now
+            .checked_add(ctx.accounts.config.timelock_delay)
+            .ok_or(InvoiceError::MathOverflow)?;
+        let pending = &mut ctx.accounts.pending_change;
+        pending.change = change.clone();
+        pending.proposer = ctx.accounts.admin.key();
+        pending.queued_at = now;
+        pending.eta = eta;
+        pending.bump = ctx.bumps.pending_change;
+        emit!(ConfigChangeQueued {
+            change,
+            proposer: pending.proposer,
+            eta,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct MintInvoice<'info> {
-    #[account(
-        init,
-        payer = seller,
-        space = 8  // discriminator
-            + 32   // seller
-            + 8    // amount
-            + 4 + METADATA_MAX_LEN // metadata_hash
-            + 8    // due_date
-            + 1    // status
-            + 32   // investor
-            + 8    // funded_amount
-            + 32   // usdc_mint
-            + 1    // escrow_bump
-            + 32   // shares_mint
-    )]
-    pub invoice: Account<'info, Invoice>,
-    #[account(mut)]
-    pub seller: Signer<'info>,
-    pub usdc_mint: Account<'info, Mint>,
-    pub system_program: Program<'info, System>,
-}
+    pub fn execute_config_change(ctx: Context<ExecuteConfigChange>) -> Result<()> {
+        let clock = Clock::get()?;
+        let now = clock.unix_timestamp;
+        let change = ctx.accounts.pending_change.change.clone();
+        require!(now >= ctx.accounts.pending_change.eta, InvoiceError::TimelockNotElapsed);
+        let config = &mut ctx.accounts.config;
+        match change {
+            // Admin handover stays two-step: the timelock only arms the proposal
+            ConfigChange::Admin(new_admin) => {
+                config.pending_admin = new_admin;
+                emit!(AdminProposed {
+                    admin: config.admin,
+                    pending_admin: new_admin,
+                    slot: clock.slot,
+                    timestamp: clock.unix_timestamp,
+                });
+            }
+            ConfigChange::Compliance(compliance) => config.compliance = compliance,
+            ConfigChange::TimelockDelay(delay) => config.timelock_delay = delay,
+            ConfigChange::SettlementOracle(oracle) => config.settlement_oracle = oracle,
+            ConfigChange::AddSettlementMint(entry) => config.add_settlement_mint(entry)?,
+            ConfigChange::RemoveSettlementMint(mint) => config.remove_settlement_mint(&mint)?,
+            ConfigChange::ExposureLimits(limits) => config.exposure_limits = limits,
+            ConfigChange::Underwriter(underwriter) => config.underwriter = underwriter,
+        }
+        emit!(ConfigChangeExecuted {
+            change,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct InitShares<'info> {
-    #[account(mut)]
-    pub invoice: Account<'info, Invoice>,
-    /// Payer to create the shares mint
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    /// CHECK: PDA authority, no data allocation required
-    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump)]
-    pub escrow_authority: UncheckedAccount<'info>,
-    #[account(
-        init,
-        payer = payer,
-        mint::decimals = 6,
-        mint::authority = escrow_authority,
-    )]
-    pub shares_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
-    pub system_program: Program<'info, System>,
-}
+    pub fn cancel_config_change(ctx: Context<CancelConfigChange>) -> Result<()> {
+        require_keys_eq!(ctx.accounts.admin.key(), ctx.accounts.config.admin, InvoiceError::Unauthorized);
+        let clock = Clock::get()?;
+        emit!(ConfigChangeCanceled {
+            change: ctx.accounts.pending_change.change.clone(),
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct CreateEscrow<'info> {
-    #[account(mut)]
-    pub invoice: Account<'info, Invoice>,
-    /// Payer for creating the escrow token account (seller for PoC)
-    #[account(mut)]
-    pub seller: Signer<'info>,
-    pub usdc_mint: Account<'info, Mint>,
-    /// CHECK: PDA authority, no data allocation required
-    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump)]
-    pub escrow_authority: UncheckedAccount<'info>,
-    #[account(
-        init,
-        payer = seller,
-        associated_token::mint = usdc_mint,
-        associated_token::authority = escrow_authority,
-    )]
-    pub escrow_token: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
-}
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        require!(config.pending_admin != Pubkey::default(), InvoiceError::NoPendingAdmin);
+        require_keys_eq!(ctx.accounts.pending_admin.key(), config.pending_admin, InvoiceError::Unauthorized);
+        let previous_admin = config.admin;
+        config.admin = config.pending_admin;
+        config.pending_admin = Pubkey::default();
+        let clock = Clock::get()?;
+        emit!(AdminAccepted {
+            previous_admin,
+            admin: config.admin,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
 
-#[derive(Accounts)]
-pub struct FundInvoice<'info> {
-    #[account(mut)]
-    pub invoice: Account<'info, Invoice>,
-    pub investor: Signer<'info>,
-    #[account(
-        mut,
-        constraint = investor_ata.owner == investor.key(),
-        constraint = investor_ata.mint == invoice.usdc_mint,
-    )]
-    pub investor_ata: Account<'info, TokenAccount>,
-    #[account(
-        mut,
-        constraint = escrow_token.mint == invoice.usdc_mint,
-        constraint = escrow_token.owner == escrow_authority.key(),
-    )]
-    pub escrow_token: Account<'info, TokenAccount>,
-    /// CHECK: PDA authority for escrow
-    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.escrow_bump)]
+    pub fn cancel_admin_proposal(ctx: Context<UpdateConfig>) -> Result<()> {
+        require_keys_eq!(ctx.accounts.admin.key(), ctx.accounts.config.admin, InvoiceError::Unauthorized);
+        let config = &mut ctx.accounts.config;
+        require!(config.pending_admin != Pubkey::default(), InvoiceError::NoPendingAdmin);
+        let canceled = config.pending_admin;
+        config.pending_admin = Pubkey::default();
+        let clock = Clock::get()?;
+        emit!(AdminProposalCanceled {
+            admin: config.admin,
+            pending_admin: canceled,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_paused(ctx: Context<UpdateConfig>, paused: u8) -> Result<()> {
+        require_keys_eq!(ctx.accounts.admin.key(), ctx.accounts.config.admin, InvoiceError::Unauthorized);
+        require!(paused & !PAUSE_ALL == 0, InvoiceError::InvalidParameter);
+        ctx.accounts.config.paused = paused;
+        let clock = Clock::get()?;
+        emit!(PauseUpdated {
+            admin: ctx.accounts.admin.key(),
+            paused,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_invoice_frozen(ctx: Context<SetInvoiceFrozen>, frozen: bool) -> Result<()> {
+        require_keys_eq!(ctx.accounts.compliance.key(), ctx.accounts.config.compliance, InvoiceError::Unauthorized);
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        invoice.frozen = frozen as u8;
+        let seq = invoice.next_seq()?;
+        let clock = Clock::get()?;
+        emit!(InvoiceFrozenUpdated {
+            invoice: ctx.accounts.invoice.key(),
+            compliance: ctx.accounts.compliance.key(),
+            frozen,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // KYC gate: when AdminConfig.kyc_required or the invoice's kyc_required is
+    // set, fund_invoice* and fulfill_listing* require the investor or buyer to
+    // hold an unexpired InvestorAttestation issued by the compliance role.
+    pub fn issue_attestation(ctx: Context<IssueAttestation>, jurisdiction: [u8; 2], expires_at: i64) -> Result<()> {
+        require_keys_eq!(ctx.accounts.compliance.key(), ctx.accounts.config.compliance, InvoiceError::Unauthorized);
+        require!(jurisdiction.iter().all(u8::is_ascii_uppercase), InvoiceError::InvalidParameter);
+        let clock = Clock::get()?;
+        require!(expires_at > clock.unix_timestamp, InvoiceError::InvalidParameter);
+        let attestation = &mut ctx.accounts.attestation;
+        attestation.investor = ctx.accounts.investor.key();
+        attestation.issuer = ctx.accounts.compliance.key();
+        attestation.jurisdiction = jurisdiction;
+        attestation.issued_at = clock.unix_timestamp;
+        attestation.expires_at = expires_at;
+        attestation.bump = ctx.bumps.attestation;
+        attestation.version = ATTESTATION_VERSION;
+        emit!(AttestationIssued {
+            investor: attestation.investor,
+            compliance: attestation.issuer,
+            jurisdiction,
+            expires_at,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn revoke_attestation(ctx: Context<RevokeAttestation>) -> Result<()> {
+        require_keys_eq!(ctx.accounts.compliance.key(), ctx.accounts.config.compliance, InvoiceError::Unauthorized);
+        let clock = Clock::get()?;
+        emit!(AttestationRevoked {
+            investor: ctx.accounts.attestation.investor,
+            compliance: ctx.accounts.compliance.key(),
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_kyc_required(ctx: Context<SetKycRequired>, required: bool) -> Result<()> {
+        require_keys_eq!(ctx.accounts.compliance.key(), ctx.accounts.config.compliance, InvoiceError::Unauthorized);
+        ctx.accounts.config.kyc_required = required as u8;
+        let clock = Clock::get()?;
+        emit!(KycRequiredUpdated {
+            invoice: None,
+            compliance: ctx.accounts.compliance.key(),
+            required,
+            seq: 0,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn set_invoice_kyc_required(ctx: Context<SetInvoiceKycRequired>, required: bool) -> Result<()> {
+        require_keys_eq!(ctx.accounts.compliance.key(), ctx.accounts.config.compliance, InvoiceError::Unauthorized);
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        invoice.kyc_required = required as u8;
+        let seq = invoice.next_seq()?;
+        let clock = Clock::get()?;
+        emit!(KycRequiredUpdated {
+            invoice: Some(ctx.accounts.invoice.key()),
+            compliance: ctx.accounts.compliance.key(),
+            required,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Underwriting: the underwriter rates an invoice with a risk tier and the
+    // APR it expects funders to earn. The rating can be revised until the
+    // first funding arrives, after which it is fixed.
+    pub fn set_invoice_risk(ctx: Context<SetInvoiceRisk>, risk_tier: u8, target_apr_bps: u16) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        require_keys_eq!(ctx.accounts.underwriter.key(), ctx.accounts.config.underwriter, InvoiceError::Unauthorized);
+        require!((1..=MAX_RISK_TIER).contains(&risk_tier), InvoiceError::InvalidParameter);
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        require!(invoice.status()? == InvoiceStatus::Open && invoice.funded_amount == 0, InvoiceError::RiskLocked);
+        invoice.risk_tier = risk_tier;
+        invoice.target_apr_bps = target_apr_bps;
+        let seq = invoice.next_seq()?;
+        let clock = Clock::get()?;
+        emit!(InvoiceRiskUpdated {
+            invoice: ctx.accounts.invoice.key(),
+            underwriter: ctx.accounts.underwriter.key(),
+            risk_tier,
+            target_apr_bps,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Debtor verification: the seller names the debtor and whether funding must
+    // wait for it, then the debtor co-signs the amount and due date with
+    // acknowledge_invoice. Both are fixed once funding starts.
+    pub fn set_invoice_debtor(ctx: Context<SetInvoiceDebtor>, debtor: Pubkey, acknowledgement_required: bool) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require_keys_eq!(ctx.accounts.seller.key(), invoice.seller, InvoiceError::Unauthorized);
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        require!(invoice.status()? == InvoiceStatus::Open && invoice.funded_amount == 0, InvoiceError::WrongStatus);
+        require!(debtor != Pubkey::default() || !acknowledgement_required, InvoiceError::InvalidParameter);
+        // A new debtor has to acknowledge again
+        if invoice.debtor != debtor {
+            invoice.acknowledged = 0;
+        }
+        invoice.debtor = debtor;
+        invoice.acknowledgement_required = acknowledgement_required as u8;
+        let seq = invoice.next_seq()?;
+        let clock = Clock::get()?;
+        emit!(InvoiceDebtorSet {
+            invoice: ctx.accounts.invoice.key(),
+            seller: invoice.seller,
+            debtor,
+            acknowledgement_required,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    pub fn acknowledge_invoice(ctx: Context<AcknowledgeInvoice>, amount: u64, due_date: i64) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_FUNDING)?;
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(invoice.debtor != Pubkey::default(), InvoiceError::Unauthorized);
+        require_keys_eq!(ctx.accounts.debtor.key(), invoice.debtor, InvoiceError::Unauthorized);
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        let status = invoice.status()?;
+        require!(status == InvoiceStatus::Open || status == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        require!(amount == invoice.amount && due_date == invoice.due_date, InvoiceError::AcknowledgementMismatch);
+        invoice.acknowledged = 1;
+        let seq = invoice.next_seq()?;
+        let clock = Clock::get()?;
+        emit!(InvoiceAcknowledged {
+            invoice: ctx.accounts.invoice.key(),
+            debtor: invoice.debtor,
+            amount,
+            due_date,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Onboards a seller or updates its status and credit line. Signed by the
+    // admin or the compliance role; registering again overwrites both.
+    pub fn register_seller(ctx: Context<RegisterSeller>, status: SellerStatus, credit_limit: u64) -> Result<()> {
+        let authority = ctx.accounts.authority.key();
+        let config = &ctx.accounts.config;
+        require!(authority == config.admin || authority == config.compliance, InvoiceError::Unauthorized);
+        let profile = &mut ctx.accounts.seller_profile;
+        profile.open(ctx.accounts.seller.key(), ctx.bumps.seller_profile);
+        profile.status = status;
+        profile.credit_limit = credit_limit;
+        let clock = Clock::get()?;
+        emit!(SellerProfileUpdated {
+            seller: profile.seller,
+            authority,
+            status: profile.status.clone(),
+            credit_limit,
+            outstanding: profile.outstanding,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Writes off a funded invoice the debtor failed to pay by its due date.
+    // The escrow is left for refund_defaulted; the seller's outstanding
+    // financing moves to its defaulted total. Once the debtor has repaid
+    // anything, investors are paid through claim_repayment instead, so the
+    // escrow can't be refunded on top of the installments.
+    pub fn mark_defaulted(ctx: Context<MarkDefaulted>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_SETTLEMENT)?;
+        require_keys_eq!(ctx.accounts.admin.key(), ctx.accounts.config.admin, InvoiceError::Unauthorized);
+        let invoice_key = ctx.accounts.invoice.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        require!(invoice.status()? == InvoiceStatus::Funded, InvoiceError::WrongStatus);
+        require!(invoice.repaid_amount == 0, InvoiceError::WrongStatus);
+        let clock = Clock::get()?;
+        require!(clock.unix_timestamp > invoice.due_date, InvoiceError::InvoiceNotDue);
+
+        let amount = invoice.funded_amount;
+        update_if_exists(&ctx.accounts.seller_profile, |profile: &mut SellerProfile| {
+            profile.outstanding = profile.outstanding.saturating_sub(amount);
+            profile.total_defaulted = profile.total_defaulted.saturating_add(amount);
+        })?;
+        invoice.set_status(InvoiceStatus::Defaulted);
+        let seq = invoice.next_seq()?;
+
+        emit!(InvoiceDefaulted {
+            invoice: invoice_key,
+            seller: invoice.seller,
+            amount,
+            status: invoice.status()?,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Returns a defaulted invoice's escrow to its investors. The seller never
+    // received the funding, so every funded unit is refunded 1:1: the holder's
+    // shares are burned into its claim_basis as in claim_repayment, and the part
+    // of the basis not refunded yet is paid out of the escrow.
+    pub fn refund_defaulted(ctx: Context<RefundDefaulted>) -> Result<()> {
+        ctx.accounts.config.require_not_paused(PAUSE_SETTLEMENT)?;
+        let invoice_key = ctx.accounts.invoice.key();
+        let holder = ctx.accounts.holder.key();
+        let mut invoice = ctx.accounts.invoice.load_mut()?;
+        require!(!invoice.is_frozen(), InvoiceError::InvoiceFrozen);
+        require!(invoice.status()? == InvoiceStatus::Defaulted, InvoiceError::WrongStatus);
+
+        let redeemed = burn_held_shares(
+            &ctx.accounts.shares_mint,
+            &ctx.accounts.holder_shares,
+            &ctx.accounts.shares_token_program,
+            &ctx.accounts.holder,
+        )?;
+        let position = &mut ctx.accounts.position;
+        position.open(invoice_key, holder, ctx.bumps.position);
+        position.claim_basis = position.claim_basis.checked_add(redeemed).ok_or(InvoiceError::MathOverflow)?;
+        let amount = position.claim_basis.saturating_sub(position.refunded);
+        require!(amount > 0, InvoiceError::NothingToClaim);
+        position.refunded = position.claim_basis;
+        invoice.refunded_amount = invoice.refunded_amount.checked_add(amount).ok_or(InvoiceError::MathOverflow)?;
+
+        transfer_as_escrow_authority(
+            &invoice_key,
+            invoice.escrow_bump,
+            &ctx.accounts.escrow_token,
+            &ctx.accounts.usdc_mint,
+            &ctx.accounts.holder_ata,
+            &ctx.accounts.escrow_authority,
+            &ctx.accounts.token_program,
+            amount,
+        )?;
+        let seq = invoice.next_seq()?;
+        let clock = Clock::get()?;
+        emit!(EscrowRefunded {
+            invoice: invoice_key,
+            holder,
+            amount,
+            refunded_amount: invoice.refunded_amount,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Upgrades an Invoice written by an older program to the current layout.
+    // Versions 0 and 1 were variable-size borsh accounts; they are decoded,
+    // grown to Invoice::SPACE (payer tops up the rent) and rewritten as the
+    // fixed-size layout. Anyone may call it; it never changes field values.
+    pub fn migrate_invoice(ctx: Context<MigrateInvoice>) -> Result<()> {
+        let info = ctx.accounts.invoice.to_account_info();
+        let (legacy, frozen, seq, from_version) = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == *Invoice::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            // A current-layout account has nothing to convert, whatever its version byte
+            require!(data.len() < Invoice::SPACE, InvoiceError::AlreadyMigrated);
+            let mut rest = &data[8..];
+            let legacy = LegacyInvoice::deserialize(&mut rest)
+                .map_err(|_| ErrorCode::AccountDidNotDeserialize)?;
+            // The first release ends at shares_mint, and a full-length
+            // metadata_hash leaves nothing after it. Later borsh layouts
+            // appended frozen, seq and then the version byte, so each is
+            // read only when its bytes are present (zero padding reads as 0).
+            let frozen = rest.first().is_some_and(|&byte| byte != 0);
+            let seq = rest.get(1..9).and_then(|bytes| bytes.try_into().ok()).map_or(0, u64::from_le_bytes);
+            let from_version = rest.get(9).copied().unwrap_or(0);
+            (legacy, frozen, seq, from_version)
+        };
+        require!(legacy.metadata_hash.len() <= METADATA_MAX_LEN, InvoiceError::InvalidParameter);
+
+        let required = Rent::get()?.minimum_balance(Invoice::SPACE);
+        let top_up = required.saturating_sub(info.lamports());
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: info.clone(),
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
+        }
+        info.resize(Invoice::SPACE)?;
+
+        let seq = {
+            let mut data = info.try_borrow_mut_data()?;
+            data[8..].fill(0);
+            let invoice: &mut Invoice = bytemuck::from_bytes_mut(&mut data[8..Invoice::SPACE]);
+            invoice.version = INVOICE_VERSION;
+            invoice.set_status(legacy.status);
+            invoice.frozen = frozen as u8;
+            invoice.escrow_bump = legacy.escrow_bump;
+            invoice.metadata_len = legacy.metadata_hash.len() as u8;
+            invoice.metadata_hash[..legacy.metadata_hash.len()].copy_from_slice(legacy.metadata_hash.as_bytes());
+            invoice.seller = legacy.seller;
+            invoice.usdc_mint = legacy.usdc_mint;
+            invoice.investor = legacy.investor;
+            invoice.shares_mint = legacy.shares_mint;
+            invoice.amount = legacy.amount;
+            invoice.funded_amount = legacy.funded_amount;
+            invoice.due_date = legacy.due_date;
+            invoice.seq = seq;
+            invoice.next_seq()?
+        };
+
+        let clock = Clock::get()?;
+        emit!(InvoiceMigrated {
+            invoice: info.key(),
+            payer: ctx.accounts.payer.key(),
+            from_version,
+            to_version: INVOICE_VERSION,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Grows a Listing written before LISTING_VERSION 1. The first release ended
+    // at market_bump and a later one appended seq; both are a prefix of the
+    // current layout, so the bytes are kept, the tail zero-filled and the
+    // version stamped. Anyone may call it; it never changes field values.
+    pub fn migrate_listing(ctx: Context<MigrateListing>) -> Result<()> {
+        let info = ctx.accounts.listing.to_account_info();
+        let old_len = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == *Listing::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            require!(data.len() < Listing::SPACE, InvoiceError::AlreadyMigrated);
+            require!(data.len() >= Listing::V0_SPACE, InvoiceError::InvalidParameter);
+            data.len()
+        };
+
+        let required = Rent::get()?.minimum_balance(Listing::SPACE);
+        let top_up = required.saturating_sub(info.lamports());
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: info.clone(),
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
+        }
+        info.resize(Listing::SPACE)?;
+
+        let seq = {
+            let mut data = info.try_borrow_mut_data()?;
+            data[old_len..].fill(0);
+            let mut listing = Listing::try_deserialize(&mut &data[..])?;
+            listing.version = LISTING_VERSION;
+            let seq = listing.next_seq()?;
+            listing.try_serialize(&mut &mut data[..])?;
+            seq
+        };
+
+        let clock = Clock::get()?;
+        emit!(ListingMigrated {
+            listing: info.key(),
+            payer: ctx.accounts.payer.key(),
+            from_version: 0,
+            to_version: LISTING_VERSION,
+            seq,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Grows an older AdminConfig to the current size. Version 0 held only the
+    // admin, so the roles default to it and the delay to DEFAULT_TIMELOCK_DELAY;
+    // the deployer is read from the program's upgrade authority as in init_config.
+    // Version 1 fields sit where the current layout has them, so its bytes are
+    // kept and the new tail is zero-filled (an empty settlement mint list).
+    // The underwriter was later carved out of the reserved bytes, so any config
+    // without one, full-size or not, gets the admin as underwriter.
+    pub fn migrate_config(ctx: Context<MigrateConfig>) -> Result<()> {
+        let info = ctx.accounts.config.to_account_info();
+        let (v0_admin, from_version) = {
+            let data = info.try_borrow_data()?;
+            require!(
+                data.len() >= 8 && data[..8] == *AdminConfig::DISCRIMINATOR,
+                ErrorCode::AccountDiscriminatorMismatch
+            );
+            if data.len() >= AdminConfig::SPACE {
+                // Version 2 configs written before the underwriter role have the
+                // right size but no underwriter
+                let config = AdminConfig::try_deserialize(&mut &data[..])?;
+                require!(config.underwriter == Pubkey::default(), InvoiceError::AlreadyMigrated);
+                (None, config.version)
+            } else if data.len() == AdminConfig::V0_SPACE {
+                let admin = Pubkey::deserialize(&mut &data[8..]).map_err(|_| ErrorCode::AccountDidNotDeserialize)?;
+                (Some(admin), 0)
+            } else {
+                // Other layouts that end before the version byte are not a known version
+                require!(data.len() > AdminConfig::VERSION_OFFSET, InvoiceError::InvalidParameter);
+                (None, data[AdminConfig::VERSION_OFFSET])
+            }
+        };
+
+        let required = Rent::get()?.minimum_balance(AdminConfig::SPACE);
+        let top_up = required.saturating_sub(info.lamports());
+        if top_up > 0 {
+            let cpi_accounts = system_program::Transfer {
+                from: ctx.accounts.payer.to_account_info(),
+                to: info.clone(),
+            };
+            let cpi_ctx = CpiContext::new(ctx.accounts.system_program.to_account_info(), cpi_accounts);
+            system_program::transfer(cpi_ctx, top_up)?;
+        }
+        info.resize(AdminConfig::SPACE)?;
+        match v0_admin {
+            Some(admin) => {
+                let config = AdminConfig {
+                    admin,
+                    pending_admin: Pubkey::default(),
+                    // Unset for a program deployed as immutable
+                    deployer: ctx.accounts.program_data.upgrade_authority_address.unwrap_or_default(),
+                    paused: 0,
+                    compliance: admin,
+                    timelock_delay: DEFAULT_TIMELOCK_DELAY,
+                    settlement_oracle: Pubkey::default(),
+                    version: CONFIG_VERSION,
+                    kyc_required: 0,
+                    settlement_mint_count: 0,
+                    settlement_mints: [SettlementMint::default(); MAX_SETTLEMENT_MINTS],
+                    exposure_limits: ExposureLimits::default(),
+                    underwriter: admin,
+                    reserved: [0; 13],
+                };
+                config.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+            }
+            None => {
+                let mut data = info.try_borrow_mut_data()?;
+                let mut config = AdminConfig::try_deserialize(&mut &data[..])?;
+                config.version = CONFIG_VERSION;
+                // The underwriter defaults to the admin, as in init_config
+                if config.underwriter == Pubkey::default() {
+                    config.underwriter = config.admin;
+                }
+                config.try_serialize(&mut &mut data[..])?;
+            }
+        }
+
+        let clock = Clock::get()?;
+        emit!(ConfigMigrated {
+            payer: ctx.accounts.payer.key(),
+            from_version,
+            to_version: CONFIG_VERSION,
+            slot: clock.slot,
+            timestamp: clock.unix_timestamp,
+        });
+        Ok(())
+    }
+
+    // Read-only accounting check for audits and monitoring, meant to be simulated.
+    // Remaining accounts are (listing, backing token account) pairs: the listing's
+    // market escrow for V1, the seller's shares ATA for V2. Broken invariants are
+    // reported as INVARIANT_* bits instead of failing so the report is always
+    // returned; accounts that don't belong to the invoice still fail.
+    pub fn verify_invariants<'info>(
+        ctx: Context<'_, '_, 'info, 'info, VerifyInvariants<'info>>,
+    ) -> Result<InvariantReport> {
+        let invoice_key = ctx.accounts.invoice.key();
+        let invoice = ctx.accounts.invoice.load()?;
+        let escrow_balance = ctx.accounts.escrow_token.amount;
+        let shares_supply = ctx.accounts.shares_mint.as_ref().map_or(0, |mint| mint.supply);
+
+        let mut violations = 0;
+        // An unknown status byte is itself a broken invariant
+        let status = invoice.status().unwrap_or_else(|_| {
+            violations |= INVARIANT_STATUS;
+            InvoiceStatus::Open
+        });
+        if invoice.funded_amount > invoice.amount {
+            violations |= INVARIANT_OVERFUNDED;
+        }
+        if (status == InvoiceStatus::Open) != (invoice.funded_amount == 0) {
+            violations |= INVARIANT_STATUS;
+        }
+        // Settlement pays the escrow out; before that it must cover every funding
+        // not yet refunded after a default
+        let unrefunded = invoice.funded_amount.saturating_sub(invoice.refunded_amount);
+        if status != InvoiceStatus::Settled && escrow_balance < unrefunded {
+            violations |= INVARIANT_ESCROW_SHORTFALL;
+        }
+        // Shares are only minted by fractional funding (fund_invoice mints none)
+        if shares_supply > invoice.funded_amount {
+            violations |= INVARIANT_SHARES_EXCEED_FUNDING;
+        }
+
+        let pairs = ctx.remaining_accounts.chunks_exact(2);
+        require!(pairs.remainder().is_empty(), InvoiceError::InvalidParameter);
+        let mut listed_qty: u64 = 0;
+        for pair in pairs {
+            let listing = Account::<Listing>::try_from(&pair[0])?;
+            let backing = InterfaceAccount::<TokenAccount>::try_from(&pair[1])?;
+            require!(listing.invoice == invoice_key, InvoiceError::ListingMismatch);
+            require!(backing.mint == invoice.shares_mint, InvoiceError::SharesMintMissing);
+            let market = Pubkey::create_program_address(
+                &[b"market", listing.key().as_ref(), &[listing.market_bump]],
+                ctx.program_id,
+            )
+            .map_err(|_| InvoiceError::BumpNotFound)?;
+            use anchor_lang::solana_program::program_option::COption;
+            let backed = if backing.owner == market {
+                backing.amount
+            } else if backing.owner == listing.seller {
+                if backing.delegate == COption::Some(market) {
+                    backing.amount.min(backing.delegated_amount)
+                } else {
+                    0
+                }
+            } else {
+                return err!(InvoiceError::ListingMismatch);
+            };
+            if listing.remaining_qty > backed {
+                violations |= INVARIANT_LISTING_UNBACKED;
+            }
+            listed_qty = listed_qty.checked_add(listing.remaining_qty).ok_or(InvoiceError::MathOverflow)?;
+        }
+
+        Ok(InvariantReport {
+            invoice: invoice_key,
+            status,
+            amount: invoice.amount,
+            funded_amount: invoice.funded_amount,
+            escrow_balance,
+            shares_supply,
+            listings_checked: (ctx.remaining_accounts.len() / 2) as u16,
+            listed_qty,
+            violations,
+        })
+    }
+}
+
+// Events
+#[event]
+pub struct InvoiceMinted {
+    pub invoice: Pubkey,
+    pub seller: Pubkey,
+    pub usdc_mint: Pubkey,
+    pub amount: u64,
+    pub due_date: i64,
+    pub status: InvoiceStatus,
+    /// Invoice.seq after this instruction
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EscrowCreated {
+    pub invoice: Pubkey,
+    pub payer: Pubkey,
+    pub escrow_token: Pubkey,
+    pub status: InvoiceStatus,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SharesInitialized {
+    pub invoice: Pubkey,
+    pub payer: Pubkey,
+    pub shares_mint: Pubkey,
+    pub status: InvoiceStatus,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+/// Emitted by both fund_invoice (shares_minted = 0) and fund_invoice_fractional
+#[event]
+pub struct InvoiceFunded {
+    pub invoice: Pubkey,
+    pub investor: Pubkey,
+    pub amount: u64,
+    /// Invoice funded_amount after this funding
+    pub funded_amount: u64,
+    pub shares_minted: u64,
+    pub status: InvoiceStatus,
+    pub risk_tier: u8,
+    pub target_apr_bps: u16,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InvoiceSettled {
+    pub invoice: Pubkey,
+    pub operator: Pubkey,
+    pub seller: Pubkey,
+    pub amount: u64,
+    pub oracle_attested: bool,
+    pub status: InvoiceStatus,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InvoicePaid {
+    pub invoice: Pubkey,
+    pub payer: Pubkey,
+    /// Credited to repaid_amount, net of any transfer fee
+    pub amount: u64,
+    pub repaid_amount: u64,
+    pub status: InvoiceStatus,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RepaymentScheduleSet {
+    pub invoice: Pubkey,
+    pub seller: Pubkey,
+    pub installment_count: u8,
+    pub first_due_date: i64,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InstallmentOverdue {
+    pub invoice: Pubkey,
+    /// Position in RepaymentSchedule.installments
+    pub index: u8,
+    pub due_date: i64,
+    pub amount: u64,
+    pub repaid: u64,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct RepaymentClaimed {
+    pub invoice: Pubkey,
+    pub claimant: Pubkey,
+    /// Paid out of the repayment vault
+    pub amount: u64,
+    pub repaid_amount: u64,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InvoiceDefaulted {
+    pub invoice: Pubkey,
+    pub seller: Pubkey,
+    /// Funding written off
+    pub amount: u64,
+    pub status: InvoiceStatus,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct EscrowRefunded {
+    pub invoice: Pubkey,
+    pub holder: Pubkey,
+    /// Paid out of the escrow
+    pub amount: u64,
+    pub refunded_amount: u64,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ConfigInitialized {
+    pub admin: Pubkey,
+    pub deployer: Pubkey,
+    pub timelock_delay: i64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+// Unified marketplace events, emitted by every marketplace version.
+// market_version is MARKET_V1 (escrow) or MARKET_V2 (allowance).
+#[event]
+pub struct ListingCreated {
+    pub schema_version: u8,
+    pub market_version: u8,
+    pub invoice: Pubkey,
+    pub listing: Pubkey,
+    pub seller: Pubkey,
+    pub price: u64,
+    pub qty: u64,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+    /// Invoice rating at listing time (schema_version 2+)
+    pub risk_tier: u8,
+    pub target_apr_bps: u16,
+}
+
+#[event]
+pub struct ListingFulfilled {
+    pub schema_version: u8,
+    pub market_version: u8,
+    pub invoice: Pubkey,
+    pub listing: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub qty: u64,
+    pub total: u64,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ListingCanceled {
+    pub schema_version: u8,
+    pub market_version: u8,
+    pub invoice: Pubkey,
+    pub listing: Pubkey,
+    pub seller: Pubkey,
+    pub qty: u64,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+// Deprecated per-version events, still emitted alongside the unified ones for
+// one release so existing decoders keep working.
+#[event]
+pub struct ListingFulfilledV1 {
+    pub invoice: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub qty: u64,
+    pub total: u64,
+    /// Listing.seq after this instruction
+    pub seq: u64,
+}
+
+#[event]
+pub struct ListingFulfilledV2 {
+    pub invoice: Pubkey,
+    pub seller: Pubkey,
+    pub buyer: Pubkey,
+    pub qty: u64,
+    pub total: u64,
+    pub seq: u64,
+}
+
+#[event]
+pub struct ListingCanceledV1 {
+    pub invoice: Pubkey,
+    pub seller: Pubkey,
+    pub qty: u64,
+    pub seq: u64,
+}
+
+#[event]
+pub struct ListingCanceledV2 {
+    pub invoice: Pubkey,
+    pub seller: Pubkey,
+    pub qty: u64,
+    pub seq: u64,
+}
+
+#[event]
+pub struct AdminProposed {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AdminAccepted {
+    pub previous_admin: Pubkey,
+    pub admin: Pubkey,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AdminProposalCanceled {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ConfigChangeQueued {
+    pub change: ConfigChange,
+    pub proposer: Pubkey,
+    pub eta: i64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ConfigChangeExecuted {
+    pub change: ConfigChange,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ConfigChangeCanceled {
+    pub change: ConfigChange,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct PauseUpdated {
+    pub admin: Pubkey,
+    pub paused: u8,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InvoiceFrozenUpdated {
+    pub invoice: Pubkey,
+    pub compliance: Pubkey,
+    pub frozen: bool,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AttestationIssued {
+    pub investor: Pubkey,
+    pub compliance: Pubkey,
+    pub jurisdiction: [u8; 2],
+    pub expires_at: i64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct AttestationRevoked {
+    pub investor: Pubkey,
+    pub compliance: Pubkey,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+/// KYC gate toggled globally (`invoice` None, `seq` 0) or for one invoice
+#[event]
+pub struct KycRequiredUpdated {
+    pub invoice: Option<Pubkey>,
+    pub compliance: Pubkey,
+    pub required: bool,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InvoiceRiskUpdated {
+    pub invoice: Pubkey,
+    pub underwriter: Pubkey,
+    pub risk_tier: u8,
+    pub target_apr_bps: u16,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InvoiceDebtorSet {
+    pub invoice: Pubkey,
+    pub seller: Pubkey,
+    /// Pubkey::default() when the seller cleared the debtor
+    pub debtor: Pubkey,
+    pub acknowledgement_required: bool,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InvoiceAcknowledged {
+    pub invoice: Pubkey,
+    pub debtor: Pubkey,
+    pub amount: u64,
+    pub due_date: i64,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct SellerProfileUpdated {
+    pub seller: Pubkey,
+    /// Admin or compliance key that signed
+    pub authority: Pubkey,
+    pub status: SellerStatus,
+    pub credit_limit: u64,
+    pub outstanding: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ConfigMigrated {
+    pub payer: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct InvoiceMigrated {
+    pub invoice: Pubkey,
+    pub payer: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct ListingMigrated {
+    pub listing: Pubkey,
+    pub payer: Pubkey,
+    pub from_version: u8,
+    pub to_version: u8,
+    pub seq: u64,
+    pub slot: u64,
+    pub timestamp: i64,
+}
+
+#[derive(Accounts)]
+pub struct CancelListingV2<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"listing", invoice.key().as_ref(), seller.key().as_ref()],
+        bump = listing.bump,
+        constraint = listing.seller == seller.key(),
+    )]
+    pub listing: Account<'info, Listing>,
+    /// CHECK: PDA authority used as delegate in V2
+    #[account(seeds = [b"market", listing.key().as_ref()], bump = listing.market_bump)]
+    pub market_authority: UncheckedAccount<'info>,
+    #[account(
+        mut,
+        constraint = seller_shares_ata.owner == seller.key(),
+        constraint = seller_shares_ata.mint == listing.shares_mint,
+    )]
+    pub seller_shares_ata: InterfaceAccount<'info, TokenAccount>,
+    /// Token program owning the shares mint
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+ 
+
+#[derive(Accounts)]
+pub struct CreateListingV2<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    /// Invoice for which the listing is created
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub shares_mint: InterfaceAccount<'info, Mint>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = seller,
+        seeds = [b"listing", invoice.key().as_ref(), seller.key().as_ref()],
+        bump,
+        space = Listing::SPACE,
+    )]
+    pub listing: Account<'info, Listing>,
+    /// CHECK: PDA authority used as delegate for allowance-based flow
+    #[account(seeds = [b"market", listing.key().as_ref()], bump)]
+    pub market_authority: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MintInvoice<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(
+        init,
+        payer = seller,
+        space = Invoice::SPACE,
+    )]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    pub system_program: Program<'info, System>,
+    #[account(mut, seeds = [b"seller_profile", seller.key().as_ref()], bump = seller_profile.bump)]
+    pub seller_profile: Account<'info, SellerProfile>,
+}
+
+#[derive(Accounts)]
+pub struct InitShares<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    /// Payer to create the shares mint
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: PDA authority, no data allocation required
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump)]
+    pub escrow_authority: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = payer,
+        mint::decimals = 6,
+        mint::authority = escrow_authority,
+        mint::token_program = token_program,
+    )]
+    pub shares_mint: InterfaceAccount<'info, Mint>,
+    /// Unrestricted shares stay on the classic token program
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitSharesRestricted<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    /// Payer to create the shares mint
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: PDA authority, no data allocation required
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump)]
+    pub escrow_authority: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = payer,
+        mint::decimals = 6,
+        mint::authority = escrow_authority,
+        mint::token_program = token_program,
+        extensions::transfer_hook::authority = escrow_authority,
+        extensions::transfer_hook::program_id = transfer_hook_program,
+    )]
+    pub shares_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: only its address is written into the mint
+    #[account(address = SHARES_TRANSFER_HOOK_ID)]
+    pub transfer_hook_program: UncheckedAccount<'info>,
+    pub token_program: Program<'info, Token2022>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CreateEscrow<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    /// Payer for creating the escrow token account (seller for PoC)
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: PDA authority, no data allocation required
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump)]
+    pub escrow_authority: UncheckedAccount<'info>,
+    #[account(
+        init,
+        payer = seller,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = escrow_authority,
+        associated_token::token_program = token_program,
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    /// Token program owning the USDC mint (spl-token or Token-2022)
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FundInvoice<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut)]
+    pub investor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = investor_ata.owner == investor.key(),
+        constraint = investor_ata.mint == invoice.load()?.usdc_mint,
+    )]
+    pub investor_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_token.mint == invoice.load()?.usdc_mint,
+        constraint = escrow_token.owner == escrow_authority.key(),
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: PDA authority for escrow
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.load()?.escrow_bump)]
     pub escrow_authority: UncheckedAccount<'info>,
-    pub token_program: Program<'info, Token>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: the investor's InvestorAttestation PDA; only read when KYC is required
+    #[account(seeds = [b"attestation", investor.key().as_ref()], bump)]
+    pub attestation: UncheckedAccount<'info>,
+    #[account(constraint = usdc_mint.key() == invoice.load()?.usdc_mint)]
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds = [b"seller_profile", invoice.load()?.seller.as_ref()], bump = seller_profile.bump)]
+    pub seller_profile: Box<Account<'info, SellerProfile>>,
+    #[account(
+        init_if_needed,
+        payer = investor,
+        space = InvestorProfile::SPACE,
+        seeds = [b"investor_profile", investor.key().as_ref()],
+        bump,
+    )]
+    pub investor_profile: Box<Account<'info, InvestorProfile>>,
+    #[account(
+        init_if_needed,
+        payer = investor,
+        space = InvestorPosition::SPACE,
+        seeds = [b"position", invoice.key().as_ref(), investor.key().as_ref()],
+        bump,
+    )]
+    pub position: Box<Account<'info, InvestorPosition>>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
@@ -574,13 +2330,18 @@ pub struct InitConfig<'info> {
     #[account(
         init,
         payer = payer,
-        space = 8 + 32,
+        space = AdminConfig::SPACE,
         seeds = [b"config"],
         bump,
     )]
     pub config: Account<'info, AdminConfig>,
+    /// Must be the program's upgrade authority
     #[account(mut)]
     pub payer: Signer<'info>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ InvoiceError::Unauthorized)]
+    pub program: Program<'info, crate::program::InvoiceManager>,
+    #[account(constraint = program_data.upgrade_authority_address == Some(payer.key()) @ InvoiceError::Unauthorized)]
+    pub program_data: Account<'info, ProgramData>,
     pub system_program: Program<'info, System>,
 }
 
@@ -591,9 +2352,284 @@ pub struct UpdateConfig<'info> {
     pub admin: Signer<'info>,
 }
 
+#[derive(Accounts)]
+pub struct QueueConfigChange<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        init,
+        payer = admin,
+        seeds = [b"config_change"],
+        bump,
+        space = 8  // discriminator
+            + 1 + SettlementMint::SPACE // change, sized for the largest variant
+            + 32   // proposer
+            + 8    // queued_at
+            + 8    // eta
+            + 1    // bump
+    )]
+    pub pending_change: Account<'info, PendingConfigChange>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ExecuteConfigChange<'info> {
+    #[account(mut, seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(
+        mut,
+        seeds = [b"config_change"],
+        bump = pending_change.bump,
+        close = proposer,
+    )]
+    pub pending_change: Account<'info, PendingConfigChange>,
+    /// CHECK: receives the rent of the closed change; must match the proposer
+    #[account(mut, address = pending_change.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct CancelConfigChange<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config_change"],
+        bump = pending_change.bump,
+        close = proposer,
+    )]
+    pub pending_change: Account<'info, PendingConfigChange>,
+    /// CHECK: receives the rent of the closed change; must match the proposer
+    #[account(mut, address = pending_change.proposer)]
+    pub proposer: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetInvoiceFrozen<'info> {
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub compliance: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct IssueAttestation<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub compliance: Signer<'info>,
+    /// CHECK: wallet being attested; only used as a seed
+    pub investor: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = compliance,
+        space = InvestorAttestation::SPACE,
+        seeds = [b"attestation", investor.key().as_ref()],
+        bump,
+    )]
+    pub attestation: Account<'info, InvestorAttestation>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RegisterSeller<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    /// Admin or compliance
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// CHECK: seller wallet being registered; only used as a seed
+    pub seller: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = authority,
+        space = SellerProfile::SPACE,
+        seeds = [b"seller_profile", seller.key().as_ref()],
+        bump,
+    )]
+    pub seller_profile: Account<'info, SellerProfile>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MarkDefaulted<'info> {
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub admin: Signer<'info>,
+    /// CHECK: the seller's SellerProfile PDA; absent for invoices funded before profiles existed
+    #[account(mut, seeds = [b"seller_profile", invoice.load()?.seller.as_ref()], bump)]
+    pub seller_profile: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RefundDefaulted<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut)]
+    pub holder: Signer<'info>,
+    #[account(mut, constraint = holder_ata.mint == invoice.load()?.usdc_mint)]
+    pub holder_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_token.mint == invoice.load()?.usdc_mint,
+        constraint = escrow_token.owner == escrow_authority.key(),
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: PDA authority for escrow
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.load()?.escrow_bump)]
+    pub escrow_authority: UncheckedAccount<'info>,
+    #[account(constraint = usdc_mint.key() == invoice.load()?.usdc_mint)]
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        payer = holder,
+        space = InvestorPosition::SPACE,
+        seeds = [b"position", invoice.key().as_ref(), holder.key().as_ref()],
+        bump,
+    )]
+    pub position: Box<Account<'info, InvestorPosition>>,
+    /// Omitted, with the two accounts below, to be refunded on shares redeemed earlier
+    #[account(mut, constraint = shares_mint.key() == invoice.load()?.shares_mint)]
+    pub shares_mint: Option<InterfaceAccount<'info, Mint>>,
+    #[account(
+        mut,
+        constraint = holder_shares.owner == holder.key(),
+        constraint = holder_shares.mint == invoice.load()?.shares_mint,
+    )]
+    pub holder_shares: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub shares_token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeAttestation<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub compliance: Signer<'info>,
+    #[account(
+        mut,
+        close = compliance,
+        seeds = [b"attestation", attestation.investor.as_ref()],
+        bump = attestation.bump,
+    )]
+    pub attestation: Account<'info, InvestorAttestation>,
+}
+
+#[derive(Accounts)]
+pub struct SetKycRequired<'info> {
+    #[account(mut, seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub compliance: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetInvoiceKycRequired<'info> {
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub compliance: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetInvoiceRisk<'info> {
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub underwriter: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetInvoiceDebtor<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    pub seller: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcknowledgeInvoice<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    pub debtor: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateInvoice<'info> {
+    /// CHECK: may predate the current layout, so it is checked by hand
+    #[account(mut, owner = crate::ID)]
+    pub invoice: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateListing<'info> {
+    /// CHECK: may predate the current layout, so it is checked by hand
+    #[account(mut, owner = crate::ID)]
+    pub listing: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateConfig<'info> {
+    /// CHECK: may predate the current layout, so it is checked by hand
+    #[account(mut, owner = crate::ID, seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()) @ InvoiceError::Unauthorized)]
+    pub program: Program<'info, crate::program::InvoiceManager>,
+    /// Supplies the deployer of a version 0 config
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct VerifyInvariants<'info> {
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(
+        constraint = escrow_token.mint == invoice.load()?.usdc_mint,
+        constraint = escrow_token.owner == escrow_authority.key(),
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: PDA authority for escrow
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.load()?.escrow_bump)]
+    pub escrow_authority: UncheckedAccount<'info>,
+    /// Omitted for invoices without a shares mint
+    #[account(constraint = shares_mint.key() == invoice.load()?.shares_mint)]
+    pub shares_mint: Option<InterfaceAccount<'info, Mint>>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(mut, seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub pending_admin: Signer<'info>,
+}
+
 #[derive(Accounts)]
 pub struct FulfillListingV2<'info> {
-    pub invoice: Account<'info, Invoice>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub invoice: AccountLoader<'info, Invoice>,
     #[account(mut)]
     pub buyer: Signer<'info>,
     #[account(
@@ -610,116 +2646,292 @@ pub struct FulfillListingV2<'info> {
         constraint = buyer_usdc_ata.owner == buyer.key(),
         constraint = buyer_usdc_ata.mint == listing.usdc_mint,
     )]
-    pub buyer_usdc_ata: Account<'info, TokenAccount>,
+    pub buyer_usdc_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
         constraint = seller_usdc_ata.owner == listing.seller,
         constraint = seller_usdc_ata.mint == listing.usdc_mint,
     )]
-    pub seller_usdc_ata: Account<'info, TokenAccount>,
+    pub seller_usdc_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
         constraint = seller_shares_ata.owner == listing.seller,
         constraint = seller_shares_ata.mint == listing.shares_mint,
     )]
-    pub seller_shares_ata: Account<'info, TokenAccount>,
+    pub seller_shares_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         init_if_needed,
         payer = buyer,
         associated_token::mint = shares_mint,
         associated_token::authority = buyer,
+        associated_token::token_program = shares_token_program,
+    )]
+    pub buyer_shares_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(mint::token_program = shares_token_program)]
+    pub shares_mint: InterfaceAccount<'info, Mint>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: the buyer's InvestorAttestation PDA; only read when KYC is required
+    #[account(seeds = [b"attestation", buyer.key().as_ref()], bump)]
+    pub attestation: UncheckedAccount<'info>,
+    /// Token program owning the shares mint (Token-2022 for restricted shares)
+    pub shares_token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = InvestorProfile::SPACE,
+        seeds = [b"investor_profile", buyer.key().as_ref()],
+        bump,
+    )]
+    pub buyer_profile: Box<Account<'info, InvestorProfile>>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = InvestorPosition::SPACE,
+        seeds = [b"position", invoice.key().as_ref(), buyer.key().as_ref()],
+        bump,
+    )]
+    pub buyer_position: Box<Account<'info, InvestorPosition>>,
+    /// CHECK: the listing seller's InvestorPosition PDA; absent when its shares arrived outside the program
+    #[account(mut, seeds = [b"position", invoice.key().as_ref(), listing.seller.as_ref()], bump)]
+    pub seller_position: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FundInvoiceFractional<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut)]
+    pub investor: Signer<'info>,
+    #[account(
+        mut,
+        constraint = investor_ata.owner == investor.key(),
+        constraint = investor_ata.mint == invoice.load()?.usdc_mint,
+    )]
+    pub investor_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_token.mint == invoice.load()?.usdc_mint,
+        constraint = escrow_token.owner == escrow_authority.key(),
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: PDA authority for escrow and shares mint
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.load()?.escrow_bump)]
+    pub escrow_authority: UncheckedAccount<'info>,
+    /// Shares mint must match invoice.shares_mint
+    #[account(mut, mint::token_program = shares_token_program)]
+    pub shares_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = investor,
+        associated_token::mint = shares_mint,
+        associated_token::authority = investor,
+        associated_token::token_program = shares_token_program,
+    )]
+    pub investor_shares_ata: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+    /// CHECK: the investor's InvestorAttestation PDA; only read when KYC is required
+    #[account(seeds = [b"attestation", investor.key().as_ref()], bump)]
+    pub attestation: UncheckedAccount<'info>,
+    /// Token program owning the shares mint (Token-2022 for restricted shares)
+    pub shares_token_program: Interface<'info, TokenInterface>,
+    #[account(constraint = usdc_mint.key() == invoice.load()?.usdc_mint)]
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, seeds = [b"seller_profile", invoice.load()?.seller.as_ref()], bump = seller_profile.bump)]
+    pub seller_profile: Box<Account<'info, SellerProfile>>,
+    #[account(
+        init_if_needed,
+        payer = investor,
+        space = InvestorProfile::SPACE,
+        seeds = [b"investor_profile", investor.key().as_ref()],
+        bump,
+    )]
+    pub investor_profile: Box<Account<'info, InvestorProfile>>,
+    #[account(
+        init_if_needed,
+        payer = investor,
+        space = InvestorPosition::SPACE,
+        seeds = [b"position", invoice.key().as_ref(), investor.key().as_ref()],
+        bump,
+    )]
+    pub position: Box<Account<'info, InvestorPosition>>,
+}
+
+#[derive(Accounts)]
+pub struct SetSettled<'info> {
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub operator: Signer<'info>, // later: require multisig / verified relayer
+    #[account(
+        mut,
+        constraint = seller_ata.owner == invoice.load()?.seller,
+        constraint = seller_ata.mint == invoice.load()?.usdc_mint,
     )]
-    pub buyer_shares_ata: Account<'info, TokenAccount>,
-    pub shares_mint: Account<'info, Mint>,
-    pub usdc_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
-    pub system_program: Program<'info, System>,
+    pub seller_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = escrow_token.mint == invoice.load()?.usdc_mint,
+        constraint = escrow_token.owner == escrow_authority.key(),
+    )]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: PDA authority for escrow
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.load()?.escrow_bump)]
+    pub escrow_authority: UncheckedAccount<'info>,
+    /// CHECK: instructions sysvar, read for oracle attestations
+    #[account(address = ix_sysvar::ID)]
+    pub instructions: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    #[account(constraint = usdc_mint.key() == invoice.load()?.usdc_mint)]
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    /// CHECK: the seller's SellerProfile PDA; absent for invoices funded before profiles existed
+    #[account(mut, seeds = [b"seller_profile", invoice.load()?.seller.as_ref()], bump)]
+    pub seller_profile: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
-pub struct FundInvoiceFractional<'info> {
+pub struct PayInvoice<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
     #[account(mut)]
-    pub invoice: Account<'info, Invoice>,
+    pub invoice: AccountLoader<'info, Invoice>,
     #[account(mut)]
-    pub investor: Signer<'info>,
+    pub payer: Signer<'info>,
+    #[account(mut, constraint = payer_ata.mint == invoice.load()?.usdc_mint)]
+    pub payer_ata: InterfaceAccount<'info, TokenAccount>,
+    /// Holds repayments; owned by the escrow authority
+    #[account(
+        init_if_needed,
+        payer = payer,
+        seeds = [b"repayment", invoice.key().as_ref()],
+        bump,
+        token::mint = usdc_mint,
+        token::authority = escrow_authority,
+        token::token_program = token_program,
+    )]
+    pub repayment_vault: Box<InterfaceAccount<'info, TokenAccount>>,
     #[account(
         mut,
-        constraint = investor_ata.owner == investor.key(),
-        constraint = investor_ata.mint == invoice.usdc_mint,
+        constraint = seller_ata.owner == invoice.load()?.seller,
+        constraint = seller_ata.mint == invoice.load()?.usdc_mint,
     )]
-    pub investor_ata: Account<'info, TokenAccount>,
+    pub seller_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
-        constraint = escrow_token.mint == invoice.usdc_mint,
+        constraint = escrow_token.mint == invoice.load()?.usdc_mint,
         constraint = escrow_token.owner == escrow_authority.key(),
     )]
-    pub escrow_token: Account<'info, TokenAccount>,
-    /// CHECK: PDA authority for escrow and shares mint
-    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.escrow_bump)]
+    pub escrow_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: PDA authority for the escrow and the repayment vault
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.load()?.escrow_bump)]
     pub escrow_authority: UncheckedAccount<'info>,
-    /// Shares mint must match invoice.shares_mint
+    #[account(constraint = usdc_mint.key() == invoice.load()?.usdc_mint)]
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    /// CHECK: the seller's SellerProfile PDA; absent for invoices funded before profiles existed
+    #[account(mut, seeds = [b"seller_profile", invoice.load()?.seller.as_ref()], bump)]
+    pub seller_profile: UncheckedAccount<'info>,
+    /// CHECK: the invoice's RepaymentSchedule PDA; absent when repayment isn't scheduled
+    #[account(mut, seeds = [b"schedule", invoice.key().as_ref()], bump)]
+    pub repayment_schedule: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRepaymentSchedule<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
     #[account(mut)]
-    pub shares_mint: Account<'info, Mint>,
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut)]
+    pub seller: Signer<'info>,
     #[account(
         init_if_needed,
-        payer = investor,
-        associated_token::mint = shares_mint,
-        associated_token::authority = investor,
+        payer = seller,
+        space = RepaymentSchedule::SPACE,
+        seeds = [b"schedule", invoice.key().as_ref()],
+        bump,
     )]
-    pub investor_shares_ata: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
-    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub repayment_schedule: Box<Account<'info, RepaymentSchedule>>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct SetSettled<'info> {
+pub struct FlagOverdueInstallments<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
     #[account(mut)]
-    pub invoice: Account<'info, Invoice>,
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut, seeds = [b"schedule", invoice.key().as_ref()], bump = repayment_schedule.bump)]
+    pub repayment_schedule: Box<Account<'info, RepaymentSchedule>>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimRepayment<'info> {
     #[account(seeds = [b"config"], bump)]
     pub config: Account<'info, AdminConfig>,
-    pub operator: Signer<'info>, // later: require multisig / verified relayer
+    #[account(mut)]
+    pub invoice: AccountLoader<'info, Invoice>,
+    #[account(mut)]
+    pub claimant: Signer<'info>,
+    #[account(mut, constraint = claimant_ata.mint == invoice.load()?.usdc_mint)]
+    pub claimant_ata: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, seeds = [b"repayment", invoice.key().as_ref()], bump = invoice.load()?.repayment_bump)]
+    pub repayment_vault: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: PDA authority for the repayment vault
+    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.load()?.escrow_bump)]
+    pub escrow_authority: UncheckedAccount<'info>,
+    #[account(constraint = usdc_mint.key() == invoice.load()?.usdc_mint)]
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
     #[account(
-        mut,
-        constraint = seller_ata.owner == invoice.seller,
-        constraint = seller_ata.mint == invoice.usdc_mint,
+        init_if_needed,
+        payer = claimant,
+        space = InvestorPosition::SPACE,
+        seeds = [b"position", invoice.key().as_ref(), claimant.key().as_ref()],
+        bump,
     )]
-    pub seller_ata: Account<'info, TokenAccount>,
+    pub position: Box<Account<'info, InvestorPosition>>,
+    /// Omitted, with the two accounts below, to claim on shares redeemed earlier
+    #[account(mut, constraint = shares_mint.key() == invoice.load()?.shares_mint)]
+    pub shares_mint: Option<InterfaceAccount<'info, Mint>>,
     #[account(
         mut,
-        constraint = escrow_token.mint == invoice.usdc_mint,
-        constraint = escrow_token.owner == escrow_authority.key(),
+        constraint = claimant_shares.owner == claimant.key(),
+        constraint = claimant_shares.mint == invoice.load()?.shares_mint,
     )]
-    pub escrow_token: Account<'info, TokenAccount>,
-    /// CHECK: PDA authority for escrow
-    #[account(seeds = [b"escrow", invoice.key().as_ref()], bump = invoice.escrow_bump)]
-    pub escrow_authority: UncheckedAccount<'info>,
-    pub token_program: Program<'info, Token>,
+    pub claimant_shares: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub shares_token_program: Option<Interface<'info, TokenInterface>>,
+    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 pub struct CreateListing<'info> {
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
     #[account(mut)]
-    pub invoice: Account<'info, Invoice>,
+    pub invoice: AccountLoader<'info, Invoice>,
     #[account(mut)]
     pub seller: Signer<'info>,
-    pub shares_mint: Account<'info, Mint>,
-    pub usdc_mint: Account<'info, Mint>,
+    pub shares_mint: InterfaceAccount<'info, Mint>,
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    /// Restricted shares can't move into the V1 escrow, which holds no
+    /// attestation. Checked here because init accounts are created before the
+    /// other constraints run, and this one precedes the escrow account.
     #[account(
         init,
         payer = seller,
         seeds = [b"listing", invoice.key().as_ref(), seller.key().as_ref()],
         bump,
-        space = 8  // disc
-            + 32  // invoice
-            + 32  // seller
-            + 32  // shares_mint
-            + 32  // usdc_mint
-            + 8   // price
-            + 8   // remaining_qty
-            + 1   // bump
-            + 1   // market_bump
+        space = Listing::SPACE,
+        constraint = invoice.load()?.shares_restricted == 0 @ InvoiceError::RestrictedSharesUnsupported,
     )]
     pub listing: Account<'info, Listing>,
     /// CHECK: PDA authority over escrow ATAs
@@ -730,22 +2942,25 @@ pub struct CreateListing<'info> {
         constraint = seller_shares_ata.owner == seller.key(),
         constraint = seller_shares_ata.mint == shares_mint.key(),
     )]
-    pub seller_shares_ata: Account<'info, TokenAccount>,
+    pub seller_shares_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         init_if_needed,
         payer = seller,
         associated_token::mint = shares_mint,
         associated_token::authority = market_authority,
+        associated_token::token_program = token_program,
     )]
-    pub escrow_shares_ata: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
+    pub escrow_shares_ata: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 pub struct FulfillListing<'info> {
-    pub invoice: Account<'info, Invoice>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub invoice: AccountLoader<'info, Invoice>,
     #[account(mut)]
     pub buyer: Signer<'info>,
     #[account(
@@ -762,35 +2977,64 @@ pub struct FulfillListing<'info> {
         constraint = buyer_usdc_ata.owner == buyer.key(),
         constraint = buyer_usdc_ata.mint == listing.usdc_mint,
     )]
-    pub buyer_usdc_ata: Account<'info, TokenAccount>,
+    pub buyer_usdc_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
         constraint = seller_usdc_ata.owner == listing.seller,
         constraint = seller_usdc_ata.mint == listing.usdc_mint,
     )]
-    pub seller_usdc_ata: Account<'info, TokenAccount>,
+    pub seller_usdc_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
         constraint = escrow_shares_ata.mint == listing.shares_mint,
         constraint = escrow_shares_ata.owner == market_authority.key(),
     )]
-    pub escrow_shares_ata: Account<'info, TokenAccount>,
+    pub escrow_shares_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         init_if_needed,
         payer = buyer,
         associated_token::mint = shares_mint,
         associated_token::authority = buyer,
+        associated_token::token_program = shares_token_program,
     )]
-    pub buyer_shares_ata: Account<'info, TokenAccount>,
-    pub shares_mint: Account<'info, Mint>,
-    pub token_program: Program<'info, Token>,
+    pub buyer_shares_ata: InterfaceAccount<'info, TokenAccount>,
+    pub shares_mint: InterfaceAccount<'info, Mint>,
+    /// Token program owning the USDC mint
+    pub token_program: Interface<'info, TokenInterface>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
+    /// CHECK: the buyer's InvestorAttestation PDA; only read when KYC is required
+    #[account(seeds = [b"attestation", buyer.key().as_ref()], bump)]
+    pub attestation: UncheckedAccount<'info>,
+    #[account(constraint = usdc_mint.key() == listing.usdc_mint)]
+    pub usdc_mint: InterfaceAccount<'info, Mint>,
+    pub shares_token_program: Interface<'info, TokenInterface>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = InvestorProfile::SPACE,
+        seeds = [b"investor_profile", buyer.key().as_ref()],
+        bump,
+    )]
+    pub buyer_profile: Box<Account<'info, InvestorProfile>>,
+    #[account(
+        init_if_needed,
+        payer = buyer,
+        space = InvestorPosition::SPACE,
+        seeds = [b"position", invoice.key().as_ref(), buyer.key().as_ref()],
+        bump,
+    )]
+    pub buyer_position: Box<Account<'info, InvestorPosition>>,
+    /// CHECK: the listing seller's InvestorPosition PDA; absent when its shares arrived outside the program
+    #[account(mut, seeds = [b"position", invoice.key().as_ref(), listing.seller.as_ref()], bump)]
+    pub seller_position: UncheckedAccount<'info>,
 }
 
 #[derive(Accounts)]
 pub struct CancelListing<'info> {
-    pub invoice: Account<'info, Invoice>,
+    #[account(seeds = [b"config"], bump)]
+    pub config: Account<'info, AdminConfig>,
+    pub invoice: AccountLoader<'info, Invoice>,
     #[account(mut)]
     pub seller: Signer<'info>,
     #[account(
@@ -808,33 +3052,751 @@ pub struct CancelListing<'info> {
         constraint = escrow_shares_ata.mint == listing.shares_mint,
         constraint = escrow_shares_ata.owner == market_authority.key(),
     )]
-    pub escrow_shares_ata: Account<'info, TokenAccount>,
+    pub escrow_shares_ata: InterfaceAccount<'info, TokenAccount>,
     #[account(
         mut,
         constraint = seller_shares_ata.owner == seller.key(),
         constraint = seller_shares_ata.mint == listing.shares_mint,
     )]
-    pub seller_shares_ata: Account<'info, TokenAccount>,
-    pub token_program: Program<'info, Token>,
+    pub seller_shares_ata: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    #[account(constraint = shares_mint.key() == listing.shares_mint)]
+    pub shares_mint: InterfaceAccount<'info, Mint>,
 }
 
 #[account]
 pub struct AdminConfig {
     pub admin: Pubkey,
+    /// Proposed successor; Pubkey::default() when no handover is pending
+    pub pending_admin: Pubkey,
+    /// Upgrade authority that initialized the config
+    pub deployer: Pubkey,
+    /// Bitfield of PAUSE_* flags
+    pub paused: u8,
+    /// Role allowed to freeze individual invoices
+    pub compliance: Pubkey,
+    /// Seconds a queued config change must wait before execution
+    pub timelock_delay: i64,
+    /// Off-chain payment oracle allowed to attest settlements; Pubkey::default() disables it
+    pub settlement_oracle: Pubkey,
+    /// CONFIG_VERSION at the time of the last write
+    pub version: u8,
+    /// Non-zero when every investor and buyer needs an InvestorAttestation
+    pub kyc_required: u8,
+    /// Number of leading entries of settlement_mints in use
+    pub settlement_mint_count: u8,
+    /// Mints mint_invoice accepts as usdc_mint; edited through timelocked config changes
+    pub settlement_mints: [SettlementMint; MAX_SETTLEMENT_MINTS],
+    /// Caps enforced when investors fund or buy; edited through timelocked config changes
+    pub exposure_limits: ExposureLimits,
+    /// Role allowed to rate invoices before they are funded
+    pub underwriter: Pubkey,
+    pub reserved: [u8; 13],
+}
+
+impl AdminConfig {
+    pub const SPACE: usize = 8  // discriminator
+        + 32   // admin
+        + 32   // pending_admin
+        + 32   // deployer
+        + 1    // paused
+        + 32   // compliance
+        + 8    // timelock_delay
+        + 32   // settlement_oracle
+        + 1    // version
+        + 1    // kyc_required
+        + 1    // settlement_mint_count
+        + SettlementMint::SPACE * MAX_SETTLEMENT_MINTS
+        + ExposureLimits::SPACE
+        + 32   // underwriter
+        + 13;  // reserved
+
+    /// Size of the version 0 account, which held only the admin
+    pub const V0_SPACE: usize = 8 + 32;
+
+    /// Offset of `version` in the account data, discriminator included
+    pub const VERSION_OFFSET: usize = 8 + 32 + 32 + 32 + 1 + 32 + 8 + 32;
+
+    pub fn require_not_paused(&self, flag: u8) -> Result<()> {
+        require!(self.paused & flag == 0, InvoiceError::Paused);
+        Ok(())
+    }
+
+    pub fn settlement_mints(&self) -> &[SettlementMint] {
+        &self.settlement_mints[..self.settlement_mint_count as usize]
+    }
+
+    pub fn settlement_mint(&self, mint: &Pubkey) -> Option<&SettlementMint> {
+        self.settlement_mints().iter().find(|entry| entry.mint == *mint)
+    }
+
+    /// Lists `entry.mint`, or updates its decimals and minimum if already listed
+    fn add_settlement_mint(&mut self, entry: SettlementMint) -> Result<()> {
+        let count = self.settlement_mint_count as usize;
+        if let Some(existing) = self.settlement_mints[..count].iter_mut().find(|e| e.mint == entry.mint) {
+            *existing = entry;
+            return Ok(());
+        }
+        require!(count < MAX_SETTLEMENT_MINTS, InvoiceError::SettlementMintListFull);
+        self.settlement_mints[count] = entry;
+        self.settlement_mint_count += 1;
+        Ok(())
+    }
+
+    /// Invoices already minted in `mint` are unaffected
+    fn remove_settlement_mint(&mut self, mint: &Pubkey) -> Result<()> {
+        let count = self.settlement_mint_count as usize;
+        let index = self.settlement_mints[..count]
+            .iter()
+            .position(|e| e.mint == *mint)
+            .ok_or(InvoiceError::SettlementMintNotAllowed)?;
+        self.settlement_mints.copy_within(index + 1..count, index);
+        self.settlement_mints[count - 1] = SettlementMint::default();
+        self.settlement_mint_count -= 1;
+        Ok(())
+    }
+}
+
+/// An accepted settlement mint (e.g. USDC, USDT, EURC)
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SettlementMint {
+    pub mint: Pubkey,
+    /// Must match the mint's own decimals when an invoice is minted
+    pub decimals: u8,
+    /// Smallest invoice amount accepted in this mint, in base units
+    pub min_invoice_amount: u64,
+}
+
+impl SettlementMint {
+    pub const SPACE: usize = 32 + 1 + 8;
+}
+
+/// Risk caps on investor and seller exposure; 0 disables a cap
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExposureLimits {
+    /// Most one investor may hold in a single invoice, in settlement mint base units
+    pub max_investor_position: u64,
+    /// Largest fraction of an invoice's face value one investor may hold, in basis points
+    pub max_investor_share_bps: u16,
+    /// Most financing a seller may have outstanding across its invoices
+    pub max_seller_outstanding: u64,
+}
+
+impl ExposureLimits {
+    pub const SPACE: usize = 8 + 2 + 8;
+
+    /// Checks an investor's position in `invoice` after acquiring more of it
+    fn check_investor(&self, invoice: &Invoice, position: u64) -> Result<()> {
+        if self.max_investor_position > 0 {
+            require!(position <= self.max_investor_position, InvoiceError::InvestorPositionLimit);
+        }
+        if self.max_investor_share_bps > 0 {
+            let cap = (invoice.amount as u128) * (self.max_investor_share_bps as u128) / (BPS_DENOMINATOR as u128);
+            require!((position as u128) <= cap, InvoiceError::InvestorShareLimit);
+        }
+        Ok(())
+    }
+
+    /// Checks a seller's outstanding financing after new funding arrives
+    fn check_seller(&self, outstanding: u64) -> Result<()> {
+        if self.max_seller_outstanding > 0 {
+            require!(outstanding <= self.max_seller_outstanding, InvoiceError::SellerExposureLimit);
+        }
+        Ok(())
+    }
 }
 
+/// KYC/accreditation record for one wallet, issued by the compliance role.
+/// Re-issuing overwrites it; revoking closes it.
 #[account]
-pub struct Invoice {
+pub struct InvestorAttestation {
+    pub investor: Pubkey,
+    /// Compliance key that issued it
+    pub issuer: Pubkey,
+    /// ISO 3166-1 alpha-2 country code, e.g. *b"US"
+    pub jurisdiction: [u8; 2],
+    pub issued_at: i64,
+    /// The attestation stops admitting the investor at this unix timestamp
+    pub expires_at: i64,
+    pub bump: u8,
+    /// ATTESTATION_VERSION the account was written with
+    pub version: u8,
+    pub reserved: [u8; 64],
+}
+
+impl InvestorAttestation {
+    pub const SPACE: usize = 8  // discriminator
+        + 32  // investor
+        + 32  // issuer
+        + 2   // jurisdiction
+        + 8   // issued_at
+        + 8   // expires_at
+        + 1   // bump
+        + 1   // version
+        + 64; // reserved
+}
+
+/// Onboarding state, credit line and running totals of one seller. Created by
+/// register_seller, and required by mint_invoice and every funding.
+#[account]
+pub struct SellerProfile {
     pub seller: Pubkey,
+    /// Funding received across the seller's invoices that haven't settled or defaulted yet
+    pub outstanding: u64,
+    /// Lifetime funding received
+    pub total_funded: u64,
+    pub bump: u8,
+    /// PROFILE_VERSION the account was written with
+    pub version: u8,
+    /// Only Approved sellers may mint invoices or receive funding
+    pub status: SellerStatus,
+    /// Most `outstanding` may reach, counting a new invoice's face value at mint time
+    pub credit_limit: u64,
+    /// Lifetime face value of invoices minted
+    pub total_issued: u64,
+    /// Lifetime funding settled through set_settled or pay_invoice
+    pub total_repaid: u64,
+    /// Lifetime funding written off through mark_defaulted
+    pub total_defaulted: u64,
+    pub reserved: [u8; 31],
+}
+
+impl SellerProfile {
+    pub const SPACE: usize = 8  // discriminator
+        + 32  // seller
+        + 8   // outstanding
+        + 8   // total_funded
+        + 1   // bump
+        + 1   // version
+        + 1   // status
+        + 8   // credit_limit
+        + 8   // total_issued
+        + 8   // total_repaid
+        + 8   // total_defaulted
+        + 31; // reserved
+
+    /// Fills in a profile created by register_seller's init_if_needed
+    fn open(&mut self, seller: Pubkey, bump: u8) {
+        if self.version == 0 {
+            self.seller = seller;
+            self.bump = bump;
+            self.version = PROFILE_VERSION;
+        }
+    }
+
+    /// Invoices minted within the credit line can still exceed it together once
+    /// funded, so the line and the seller's status are checked again here
+    fn record_funding(&mut self, limits: &ExposureLimits, amount: u64) -> Result<()> {
+        require!(self.status == SellerStatus::Approved, InvoiceError::SellerNotApproved);
+        self.outstanding = self.outstanding.checked_add(amount).ok_or(InvoiceError::MathOverflow)?;
+        require!(self.outstanding <= self.credit_limit, InvoiceError::CreditLimitExceeded);
+        limits.check_seller(self.outstanding)?;
+        self.total_funded = self.total_funded.checked_add(amount).ok_or(InvoiceError::MathOverflow)?;
+        Ok(())
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum SellerStatus {
+    Pending,
+    Approved,
+    Suspended,
+}
+
+/// Lifetime activity of one investor across all invoices
+#[account]
+pub struct InvestorProfile {
+    pub investor: Pubkey,
+    /// Settlement mint base units paid into escrows through fund_invoice*
+    pub total_funded: u64,
+    /// Shares bought through fulfill_listing*
+    pub total_purchased: u64,
+    pub bump: u8,
+    /// PROFILE_VERSION the account was written with
+    pub version: u8,
+    pub reserved: [u8; 64],
+}
+
+impl InvestorProfile {
+    pub const SPACE: usize = 8  // discriminator
+        + 32  // investor
+        + 8   // total_funded
+        + 8   // total_purchased
+        + 1   // bump
+        + 1   // version
+        + 64; // reserved
+
+    /// Fills in a profile created by register_seller's init_if_needed
+    fn open(&mut self, investor: Pubkey, bump: u8) {
+        if self.version == 0 {
+            self.investor = investor;
+            self.bump = bump;
+            self.version = PROFILE_VERSION;
+        }
+    }
+}
+
+/// What one investor holds in one invoice, as funded plus bought minus sold
+/// through the program. Shares are 1:1 with funded base units.
+#[account]
+pub struct InvestorPosition {
+    pub invoice: Pubkey,
+    pub investor: Pubkey,
+    pub amount: u64,
+    pub bump: u8,
+    /// PROFILE_VERSION the account was written with
+    pub version: u8,
+    /// Funding units this investor claims repayments on: shares burned by
+    /// claim_repayment plus fund_invoice funding, which mints no shares
+    pub claim_basis: u64,
+    /// Lifetime repayments paid out by claim_repayment
+    pub claimed_repayment: u64,
+    /// Part of claim_basis paid back out of the escrow by refund_defaulted
+    pub refunded: u64,
+    pub reserved: [u8; 8],
+}
+
+impl InvestorPosition {
+    pub const SPACE: usize = 8  // discriminator
+        + 32  // invoice
+        + 32  // investor
+        + 8   // amount
+        + 1   // bump
+        + 1   // version
+        + 8   // claim_basis
+        + 8   // claimed_repayment
+        + 8   // refunded
+        + 8;  // reserved
+
+    /// Fills in a position created by init_if_needed
+    fn open(&mut self, invoice: Pubkey, investor: Pubkey, bump: u8) {
+        if self.version == 0 {
+            self.invoice = invoice;
+            self.investor = investor;
+            self.bump = bump;
+            self.version = PROFILE_VERSION;
+        }
+    }
+
+    fn acquire(&mut self, limits: &ExposureLimits, invoice: &Invoice, amount: u64) -> Result<()> {
+        self.amount = self.amount.checked_add(amount).ok_or(InvoiceError::MathOverflow)?;
+        limits.check_investor(invoice, self.amount)
+    }
+}
+
+/// Due dates and amounts an invoice is repaid in, set by the seller before
+/// funding. pay_invoice applies each payment to the earliest installments that
+/// aren't fully repaid.
+#[account]
+pub struct RepaymentSchedule {
+    pub invoice: Pubkey,
+    pub bump: u8,
+    /// SCHEDULE_VERSION the account was written with
+    pub version: u8,
+    /// Number of leading entries of installments in use
+    pub installment_count: u8,
+    pub installments: [Installment; MAX_INSTALLMENTS],
+    pub reserved: [u8; 32],
+}
+
+impl RepaymentSchedule {
+    pub const SPACE: usize = 8  // discriminator
+        + 32  // invoice
+        + 1   // bump
+        + 1   // version
+        + 1   // installment_count
+        + Installment::SPACE * MAX_INSTALLMENTS
+        + 32; // reserved
+
+    /// Fills in a schedule created by init_if_needed
+    fn open(&mut self, invoice: Pubkey, bump: u8) {
+        if self.version == 0 {
+            self.invoice = invoice;
+            self.bump = bump;
+            self.version = SCHEDULE_VERSION;
+        }
+    }
+
+    pub fn installments(&self) -> &[Installment] {
+        &self.installments[..self.installment_count as usize]
+    }
+
+    /// Replaces the installments. They must be in due date order, fall due by
+    /// the invoice's due date and add up to its face value.
+    fn set(&mut self, invoice: &Invoice, terms: &[InstallmentTerms]) -> Result<()> {
+        require!(!terms.is_empty() && terms.len() <= MAX_INSTALLMENTS, InvoiceError::InvalidSchedule);
+        let mut total: u64 = 0;
+        for (index, term) in terms.iter().enumerate() {
+            require!(term.amount > 0, InvoiceError::InvalidSchedule);
+            require!(index == 0 || term.due_date > terms[index - 1].due_date, InvoiceError::InvalidSchedule);
+            total = total.checked_add(term.amount).ok_or(InvoiceError::MathOverflow)?;
+        }
+        require!(terms[terms.len() - 1].due_date <= invoice.due_date, InvoiceError::InvalidSchedule);
+        require!(total == invoice.amount, InvoiceError::InvalidSchedule);
+
+        self.installments = [Installment::default(); MAX_INSTALLMENTS];
+        for (installment, term) in self.installments.iter_mut().zip(terms) {
+            installment.due_date = term.due_date;
+            installment.amount = term.amount;
+        }
+        self.installment_count = terms.len() as u8;
+        Ok(())
+    }
+
+    /// Spreads a payment over the installments in due date order
+    fn apply_payment(&mut self, mut amount: u64) {
+        let count = self.installment_count as usize;
+        for installment in self.installments[..count].iter_mut() {
+            let applied = amount.min(installment.amount.saturating_sub(installment.repaid));
+            installment.repaid += applied;
+            amount -= applied;
+        }
+    }
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Installment {
+    pub due_date: i64,
     pub amount: u64,
-    pub metadata_hash: String,
+    /// Part of amount covered by pay_invoice
+    pub repaid: u64,
+    /// Non-zero once flag_overdue_installments found it unpaid after its due date
+    pub overdue: u8,
+}
+
+impl Installment {
+    pub const SPACE: usize = 8 + 8 + 8 + 1;
+
+    pub fn is_overdue(&self, now: i64) -> bool {
+        now > self.due_date && self.repaid < self.amount
+    }
+}
+
+/// One installment as passed to set_repayment_schedule
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq)]
+pub struct InstallmentTerms {
     pub due_date: i64,
-    pub status: InvoiceStatus,
-    pub investor: Pubkey,
-    pub funded_amount: u64,
-    pub usdc_mint: Pubkey,
+    pub amount: u64,
+}
+
+/// `part / whole` of `value`, rounded down
+fn pro_rata(part: u64, value: u64, whole: u64) -> u64 {
+    if whole == 0 {
+        return 0;
+    }
+    u64::try_from(part as u128 * value as u128 / whole as u128).unwrap_or(u64::MAX)
+}
+
+/// Moves `qty` shares of `invoice` from the listing seller's position to the
+/// buyer's, enforcing the buyer's exposure limits. The seller's position PDA
+/// doesn't exist when its shares arrived outside the program.
+fn record_purchase(
+    limits: &ExposureLimits,
+    invoice: &Invoice,
+    seller: Pubkey,
+    profile: &mut InvestorProfile,
+    position: &mut InvestorPosition,
+    seller_position: &AccountInfo,
+    qty: u64,
+) -> Result<()> {
+    profile.total_purchased = profile.total_purchased.checked_add(qty).ok_or(InvoiceError::MathOverflow)?;
+    if seller == position.investor {
+        return Ok(());
+    }
+    position.acquire(limits, invoice, qty)?;
+    update_if_exists(seller_position, |p: &mut InvestorPosition| p.amount = p.amount.saturating_sub(qty))
+}
+
+/// Deserializes, updates and writes back a program account at a PDA whose
+/// address is checked by the accounts constraints but which may not exist.
+fn update_if_exists<T: AccountSerialize + AccountDeserialize>(info: &AccountInfo, update: impl FnOnce(&mut T)) -> Result<()> {
+    if info.owner != &crate::ID || info.data_is_empty() {
+        return Ok(());
+    }
+    let mut state = T::try_deserialize(&mut &info.try_borrow_data()?[..])?;
+    update(&mut state);
+    state.try_serialize(&mut &mut info.try_borrow_mut_data()?[..])?;
+    Ok(())
+}
+
+/// Burns `holder`'s whole balance of the invoice's shares and returns the
+/// amount burned; 0 when the shares accounts were omitted. The constraints tie
+/// the mint to the invoice and the token account to the holder.
+fn burn_held_shares<'info>(
+    shares_mint: &Option<InterfaceAccount<'info, Mint>>,
+    holder_shares: &Option<InterfaceAccount<'info, TokenAccount>>,
+    shares_token_program: &Option<Interface<'info, TokenInterface>>,
+    holder: &Signer<'info>,
+) -> Result<u64> {
+    let (Some(mint), Some(from), Some(token_program)) = (shares_mint, holder_shares, shares_token_program) else {
+        return Ok(0);
+    };
+    let amount = from.amount;
+    if amount > 0 {
+        let burn_accounts = token_interface::Burn {
+            mint: mint.to_account_info(),
+            from: from.to_account_info(),
+            authority: holder.to_account_info(),
+        };
+        token_interface::burn(CpiContext::new(token_program.to_account_info(), burn_accounts), amount)?;
+    }
+    Ok(amount)
+}
+
+/// Enforces the KYC gate for `investor` when the config or the invoice turns it
+/// on. `attestation` is the investor's attestation PDA (address checked by the
+/// accounts constraints), which may not exist.
+fn require_attested(config: &AdminConfig, invoice: &Invoice, attestation: &AccountInfo, investor: &Pubkey) -> Result<()> {
+    if config.kyc_required == 0 && invoice.kyc_required == 0 && invoice.shares_restricted == 0 {
+        return Ok(());
+    }
+    require!(
+        attestation.owner == &crate::ID && !attestation.data_is_empty(),
+        InvoiceError::AttestationMissing
+    );
+    let attestation = InvestorAttestation::try_deserialize(&mut &attestation.try_borrow_data()?[..])?;
+    require_keys_eq!(attestation.investor, *investor, InvoiceError::AttestationMissing);
+    require!(Clock::get()?.unix_timestamp < attestation.expires_at, InvoiceError::AttestationExpired);
+    Ok(())
+}
+
+/// Moves `amount` from `from` into `escrow` with `transfer_checked` and returns
+/// what the escrow actually received, which is less than `amount` when the mint
+/// charges a transfer fee.
+fn deposit_to_escrow<'info>(
+    from: &InterfaceAccount<'info, TokenAccount>,
+    escrow: &mut InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    authority: &Signer<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<u64> {
+    let before = escrow.amount;
+    let cpi_accounts = TransferChecked {
+        from: from.to_account_info(),
+        mint: mint.to_account_info(),
+        to: escrow.to_account_info(),
+        authority: authority.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(token_program.to_account_info(), cpi_accounts);
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)?;
+    escrow.reload()?;
+    Ok(escrow.amount.checked_sub(before).ok_or(InvoiceError::MathOverflow)?)
+}
+
+/// Pays `amount` out of the invoice's escrow to the seller on settlement and
+/// moves it from the seller's outstanding to its repaid total.
+#[allow(clippy::too_many_arguments)]
+fn release_escrow<'info>(
+    invoice_key: &Pubkey,
+    escrow_bump: u8,
+    escrow: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    seller_ata: &InterfaceAccount<'info, TokenAccount>,
+    escrow_authority: &AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    seller_profile: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<()> {
+    transfer_as_escrow_authority(invoice_key, escrow_bump, escrow, mint, seller_ata, escrow_authority, token_program, amount)?;
+    // Invoices funded before seller profiles existed have none to update
+    update_if_exists(seller_profile, |profile: &mut SellerProfile| {
+        profile.outstanding = profile.outstanding.saturating_sub(amount);
+        profile.total_repaid = profile.total_repaid.saturating_add(amount);
+    })
+}
+
+/// Moves `amount` out of a token account owned by the invoice's escrow
+/// authority (the escrow or the repayment vault), signing with its PDA seeds.
+#[allow(clippy::too_many_arguments)]
+fn transfer_as_escrow_authority<'info>(
+    invoice_key: &Pubkey,
+    escrow_bump: u8,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    escrow_authority: &AccountInfo<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    amount: u64,
+) -> Result<()> {
+    let signer_seeds: &[&[u8]] = &[b"escrow", invoice_key.as_ref(), &[escrow_bump]];
+    let signer: &[&[&[u8]]] = &[signer_seeds];
+    let cpi_accounts = TransferChecked {
+        from: from.to_account_info(),
+        mint: mint.to_account_info(),
+        to: to.to_account_info(),
+        authority: escrow_authority.clone(),
+    };
+    let cpi_ctx = CpiContext::new_with_signer(token_program.to_account_info(), cpi_accounts, signer);
+    token_interface::transfer_checked(cpi_ctx, amount, mint.decimals)
+}
+
+/// Checks that the instruction right before the current one is an Ed25519
+/// program instruction verifying exactly one signature by `signer` over `message`,
+/// with all offsets pointing into that same instruction's data.
+fn verify_ed25519_ix(instructions: &AccountInfo, signer: &Pubkey, message: &[u8]) -> Result<()> {
+    // Layout: num_signatures u8, padding u8, then one 14-byte offsets struct
+    const OFFSETS_START: usize = 2;
+    const OFFSETS_LEN: usize = 14;
+    const THIS_IX: u16 = u16::MAX;
+
+    let ix = ix_sysvar::get_instruction_relative(-1, instructions)
+        .map_err(|_| error!(InvoiceError::InvalidAttestation))?;
+    require_keys_eq!(ix.program_id, ed25519_program::ID, InvoiceError::InvalidAttestation);
+    let data = &ix.data;
+    require!(data.len() >= OFFSETS_START + OFFSETS_LEN && data[0] == 1, InvoiceError::InvalidAttestation);
+
+    let read_u16 = |at: usize| u16::from_le_bytes([data[at], data[at + 1]]);
+    let offsets = OFFSETS_START;
+    let signature_ix = read_u16(offsets + 2);
+    let pubkey_offset = read_u16(offsets + 4) as usize;
+    let pubkey_ix = read_u16(offsets + 6);
+    let message_offset = read_u16(offsets + 8) as usize;
+    let message_size = read_u16(offsets + 10) as usize;
+    let message_ix = read_u16(offsets + 12);
+    require!(
+        signature_ix == THIS_IX && pubkey_ix == THIS_IX && message_ix == THIS_IX,
+        InvoiceError::InvalidAttestation
+    );
+
+    let pubkey = data
+        .get(pubkey_offset..pubkey_offset + 32)
+        .ok_or(InvoiceError::InvalidAttestation)?;
+    require!(pubkey == signer.as_ref(), InvoiceError::InvalidAttestation);
+    let signed = data
+        .get(message_offset..message_offset + message_size)
+        .ok_or(InvoiceError::InvalidAttestation)?;
+    require!(signed == message, InvoiceError::InvalidAttestation);
+    Ok(())
+}
+
+/// Fixed-size, zero-copy layout: every field sits at a constant offset (see the
+/// INVOICE_*_OFFSET constants) so `getProgramAccounts` memcmp filters can select
+/// invoices by status, seller or mint. Status and flags are stored as bytes; use
+/// the accessors below.
+#[account(zero_copy)]
+pub struct Invoice {
+    /// INVOICE_VERSION the account was written with
+    pub version: u8,
+    /// InvoiceStatus discriminant; see Invoice::status
+    pub status: u8,
+    /// Non-zero when frozen by the compliance role; blocks funding, trading and settlement
+    pub frozen: u8,
     pub escrow_bump: u8,
+    /// Bytes of metadata_hash in use
+    pub metadata_len: u8,
+    /// Non-zero when buyers of this invoice need an InvestorAttestation, on top of AdminConfig.kyc_required
+    pub kyc_required: u8,
+    /// Non-zero when shares_mint is a Token-2022 mint with the attestation transfer hook
+    pub shares_restricted: u8,
+    /// Set by the underwriter before funding; 0 until rated, see MAX_RISK_TIER
+    pub risk_tier: u8,
+    pub seller: Pubkey,
+    pub usdc_mint: Pubkey,
+    pub investor: Pubkey,
     pub shares_mint: Pubkey,
+    pub amount: u64,
+    pub funded_amount: u64,
+    pub due_date: i64,
+    /// Bumped by every instruction that mutates the invoice and carried in its
+    /// event, so indexers can detect missed events
+    pub seq: u64,
+    pub metadata_hash: [u8; METADATA_MAX_LEN],
+    /// Expected annualized yield for funders, in basis points, set with risk_tier
+    pub target_apr_bps: u16,
+    /// Non-zero once debtor has signed acknowledge_invoice
+    pub acknowledged: u8,
+    /// Non-zero when fund_invoice* must wait for the debtor's acknowledgement
+    pub acknowledgement_required: u8,
+    /// Party expected to pay the invoice; Pubkey::default() when not named
+    pub debtor: Pubkey,
+    /// Bump of the ["repayment", invoice] vault; 0 until the first pay_invoice
+    pub repayment_bump: u8,
+    pub padding: [u8; 3],
+    /// Face value received through pay_invoice, net of transfer fees
+    pub repaid_amount: u64,
+    /// Paid out of the repayment vault to investors by claim_repayment
+    pub investors_claimed: u64,
+    /// Paid out of the repayment vault to the seller for the unfunded share
+    pub seller_claimed: u64,
+    /// Escrow paid back to investors by refund_defaulted
+    pub refunded_amount: u64,
+    pub reserved: [u8; 184],
+}
+
+// Byte offsets of Invoice fields in the account data, discriminator included,
+// for memcmp filters
+pub const INVOICE_VERSION_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, version);
+pub const INVOICE_STATUS_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, status);
+pub const INVOICE_FROZEN_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, frozen);
+pub const INVOICE_SELLER_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, seller);
+pub const INVOICE_USDC_MINT_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, usdc_mint);
+pub const INVOICE_INVESTOR_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, investor);
+pub const INVOICE_SHARES_MINT_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, shares_mint);
+pub const INVOICE_RISK_TIER_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, risk_tier);
+pub const INVOICE_DEBTOR_OFFSET: usize = 8 + core::mem::offset_of!(Invoice, debtor);
+
+impl Invoice {
+    pub const SPACE: usize = 8 + core::mem::size_of::<Invoice>();
+
+    pub fn status(&self) -> Result<InvoiceStatus> {
+        match self.status {
+            0 => Ok(InvoiceStatus::Open),
+            1 => Ok(InvoiceStatus::Funded),
+            2 => Ok(InvoiceStatus::Settled),
+            3 => Ok(InvoiceStatus::Defaulted),
+            _ => err!(InvoiceError::WrongStatus),
+        }
+    }
+
+    pub fn set_status(&mut self, status: InvoiceStatus) {
+        self.status = status as u8;
+    }
+
+    pub fn is_frozen(&self) -> bool {
+        self.frozen != 0
+    }
+
+    pub fn awaits_acknowledgement(&self) -> bool {
+        self.acknowledgement_required != 0 && self.acknowledged == 0
+    }
+
+    /// Part of repaid_amount owed to shareholders, pro rata to funded_amount
+    pub fn investor_repayment(&self) -> u64 {
+        pro_rata(self.funded_amount, self.repaid_amount, self.amount)
+    }
+
+    /// Part of repaid_amount owed to the seller for the face value nobody funded
+    pub fn seller_repayment(&self) -> u64 {
+        pro_rata(self.amount.saturating_sub(self.funded_amount), self.repaid_amount, self.amount)
+    }
+
+    pub fn metadata_hash(&self) -> &str {
+        let len = (self.metadata_len as usize).min(METADATA_MAX_LEN);
+        core::str::from_utf8(&self.metadata_hash[..len]).unwrap_or_default()
+    }
+
+    pub fn next_seq(&mut self) -> Result<u64> {
+        self.seq = self.seq.checked_add(1).ok_or(InvoiceError::MathOverflow)?;
+        Ok(self.seq)
+    }
+}
+
+/// Borsh fields shared by versions 0 and 1, read by `migrate_invoice`. The
+/// first release ended at `shares_mint`; the optional `frozen`, `seq` and
+/// version bytes that follow are decoded there.
+#[derive(AnchorDeserialize)]
+struct LegacyInvoice {
+    seller: Pubkey,
+    amount: u64,
+    metadata_hash: String,
+    due_date: i64,
+    status: InvoiceStatus,
+    investor: Pubkey,
+    funded_amount: u64,
+    usdc_mint: Pubkey,
+    escrow_bump: u8,
+    shares_mint: Pubkey,
 }
 
 #[account]
@@ -847,13 +3809,108 @@ pub struct Listing {
     pub remaining_qty: u64,
     pub bump: u8,
     pub market_bump: u8,
+    /// Bumped by every instruction that mutates the listing; see Invoice.seq
+    pub seq: u64,
+    /// LISTING_VERSION the account was written with
+    pub version: u8,
+    pub reserved: [u8; 64],
+}
+
+impl Listing {
+    /// Size of the first release's account, which ended at market_bump
+    pub const V0_SPACE: usize = 8 + 32 + 32 + 32 + 32 + 8 + 8 + 1 + 1;
+
+    pub const SPACE: usize = 8  // discriminator
+        + 32  // invoice
+        + 32  // seller
+        + 32  // shares_mint
+        + 32  // usdc_mint
+        + 8   // price
+        + 8   // remaining_qty
+        + 1   // bump
+        + 1   // market_bump
+        + 8   // seq
+        + 1   // version
+        + 64; // reserved
+
+    pub fn next_seq(&mut self) -> Result<u64> {
+        self.seq = self.seq.checked_add(1).ok_or(InvoiceError::MathOverflow)?;
+        Ok(self.seq)
+    }
+}
+
+#[account]
+pub struct PendingConfigChange {
+    pub change: ConfigChange,
+    pub proposer: Pubkey,
+    pub queued_at: i64,
+    /// Earliest unix timestamp at which the change can be executed
+    pub eta: i64,
+    pub bump: u8,
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub enum ConfigChange {
+    /// Arms a two-step handover; the new admin must still accept_admin
+    Admin(Pubkey),
+    Compliance(Pubkey),
+    TimelockDelay(i64),
+    /// Pubkey::default() turns oracle settlement off
+    SettlementOracle(Pubkey),
+    /// Lists a settlement mint, or updates an already listed one
+    AddSettlementMint(SettlementMint),
+    RemoveSettlementMint(Pubkey),
+    ExposureLimits(ExposureLimits),
+    Underwriter(Pubkey),
+}
+
+/// Payment proof signed off-chain by AdminConfig.settlement_oracle
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct SettlementAttestation {
+    /// Off-chain payment reference (e.g. hash of the bank transfer id)
+    pub payment_ref: [u8; 32],
+    /// Unix timestamp at which the oracle signed
+    pub timestamp: i64,
+}
+
+impl SettlementAttestation {
+    pub const MESSAGE_LEN: usize = 32 + 8 + 32 + 8;
+
+    /// Signed message: invoice (32) | amount u64 LE | payment_ref (32) | timestamp i64 LE
+    pub fn message(&self, invoice: &Pubkey, amount: u64) -> [u8; Self::MESSAGE_LEN] {
+        let mut msg = [0u8; Self::MESSAGE_LEN];
+        msg[..32].copy_from_slice(invoice.as_ref());
+        msg[32..40].copy_from_slice(&amount.to_le_bytes());
+        msg[40..72].copy_from_slice(&self.payment_ref);
+        msg[72..].copy_from_slice(&self.timestamp.to_le_bytes());
+        msg
+    }
+}
+
+/// Returned by `verify_invariants` through the return data
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
+pub struct InvariantReport {
+    pub invoice: Pubkey,
+    pub status: InvoiceStatus,
+    pub amount: u64,
+    pub funded_amount: u64,
+    pub escrow_balance: u64,
+    /// 0 when no shares mint was passed
+    pub shares_supply: u64,
+    pub listings_checked: u16,
+    /// Sum of remaining_qty over the listings checked
+    pub listed_qty: u64,
+    /// INVARIANT_* bits; 0 when every invariant holds
+    pub violations: u8,
 }
 
 #[derive(AnchorSerialize, AnchorDeserialize, Clone, PartialEq, Eq)]
 pub enum InvoiceStatus {
-    Open,
-    Funded,
-    Settled,
+    Open = 0,
+    Funded = 1,
+    Settled = 2,
+    /// Written off by mark_defaulted after the due date
+    Defaulted = 3,
 }
 
 #[error_code]
@@ -870,4 +3927,31 @@ pub enum InvoiceError {
     #[msg("Insufficient delegated allowance")] InsufficientAllowance,
     #[msg("Invalid parameter provided")] InvalidParameter,
     #[msg("Unauthorized")] Unauthorized,
+    #[msg("No admin handover is pending")] NoPendingAdmin,
+    #[msg("This operation is paused")] Paused,
+    #[msg("Invoice is frozen")] InvoiceFrozen,
+    #[msg("Timelock delay has not elapsed")] TimelockNotElapsed,
+    #[msg("No settlement oracle configured")] OracleNotConfigured,
+    #[msg("Missing or invalid oracle attestation")] InvalidAttestation,
+    #[msg("Oracle attestation timestamp out of range")] StaleAttestation,
+    #[msg("Account is already at the current layout version")] AlreadyMigrated,
+    #[msg("Investor attestation missing")] AttestationMissing,
+    #[msg("Investor attestation expired")] AttestationExpired,
+    #[msg("Invoice already has a shares mint")] SharesAlreadyInitialized,
+    #[msg("Mint is not an accepted settlement mint")] SettlementMintNotAllowed,
+    #[msg("Invoice amount is below the settlement mint's minimum")] InvoiceBelowMinimum,
+    #[msg("Settlement mint list is full")] SettlementMintListFull,
+    #[msg("Investor would exceed the per-invoice position limit")] InvestorPositionLimit,
+    #[msg("Investor would exceed the per-invoice ownership share limit")] InvestorShareLimit,
+    #[msg("Seller would exceed the outstanding financing limit")] SellerExposureLimit,
+    #[msg("Seller is not approved to mint or receive funding")] SellerNotApproved,
+    #[msg("Invoice or funding would exceed the seller's credit limit")] CreditLimitExceeded,
+    #[msg("Invoice is not past its due date")] InvoiceNotDue,
+    #[msg("Risk rating is locked once funding starts")] RiskLocked,
+    #[msg("Invoice must be acknowledged by its debtor before funding")] AcknowledgementRequired,
+    #[msg("Acknowledged amount or due date does not match the invoice")] AcknowledgementMismatch,
+    #[msg("Payment would exceed the invoice's face value")] Overpayment,
+    #[msg("Installments must be in due date order, due by the invoice's due date and sum to its face value")] InvalidSchedule,
+    #[msg("Nothing to claim")] NothingToClaim,
+    #[msg("Restricted shares can only be listed on the V2 market")] RestrictedSharesUnsupported,
 }
@@ -0,0 +1,406 @@
+//! Shared fixtures for the in-process program tests. Each test binary uses a
+//! different subset, hence the dead_code allowance.
+#![allow(dead_code)]
+
+pub mod svm;
+
+use anchor_lang::error::ERROR_CODE_OFFSET;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program_error::ProgramError;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::program_pack::Pack;
+use anchor_lang::solana_program::pubkey::Pubkey;
+use anchor_lang::solana_program::rent::Rent;
+use invoice_manager::{
+    AdminConfig, ConfigChange, InvariantReport, InvestorAttestation, InvestorPosition, InvestorProfile, Invoice,
+    InvoiceError, Listing, RepaymentSchedule, SellerProfile, SellerStatus, SettlementMint,
+};
+use invoice_manager_client::accounts::{decode, decode_invoice};
+use invoice_manager_client::events::{parse_logs, InvoiceManagerEvent};
+use invoice_manager_client::{decode_invariant_report, instructions, pda, SettlementAttestation};
+use solana_system_interface::instruction as system_instruction;
+use spl_token_2022::extension::transfer_fee::instruction::initialize_transfer_fee_config;
+use spl_token_2022::extension::{ExtensionType, StateWithExtensions};
+
+pub use svm::{Account, Svm, TxError};
+
+pub const SOL: u64 = 1_000_000_000;
+/// One USDC (or one share) in base units
+pub const UNIT: u64 = 1_000_000;
+pub const INVOICE_AMOUNT: u64 = 1_000 * UNIT;
+
+/// The program's error as surfaced to the runtime
+pub fn program_error(err: InvoiceError) -> ProgramError {
+    ProgramError::Custom(ERROR_CODE_OFFSET + err as u32)
+}
+
+pub fn anchor_error(err: anchor_lang::error::ErrorCode) -> ProgramError {
+    ProgramError::Custom(err as u32)
+}
+
+#[track_caller]
+pub fn assert_program_error<T>(result: Result<T, TxError>, err: InvoiceError) {
+    let expected = program_error(err);
+    match result {
+        Err(tx) => assert_eq!(tx.error, expected, "logs: {:#?}", tx.logs),
+        Ok(_) => panic!("expected {expected:?}, transaction succeeded"),
+    }
+}
+
+/// An Ed25519 key standing in for the settlement oracle
+pub struct Oracle {
+    secret: ed25519_dalek::SecretKey,
+    pub pubkey: Pubkey,
+}
+
+impl Oracle {
+    pub fn new(seed: u8) -> Self {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[seed; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        Self { pubkey: Pubkey::new_from_array(public.to_bytes()), secret }
+    }
+
+    pub fn sign(&self, message: &[u8]) -> [u8; 64] {
+        let public = ed25519_dalek::PublicKey::from(&self.secret);
+        ed25519_dalek::ExpandedSecretKey::from(&self.secret).sign(message, &public).to_bytes()
+    }
+
+    /// The Ed25519 instruction attesting `attestation` for `invoice`/`amount`
+    pub fn attest(&self, invoice: &Pubkey, amount: u64, attestation: &SettlementAttestation) -> Instruction {
+        let message = attestation.message(invoice, amount);
+        instructions::oracle_attestation(&self.pubkey, &self.sign(&message), &message)
+    }
+}
+
+/// A deployed program with an initialized config, a USDC mint and funded actors.
+pub struct TestEnv {
+    pub svm: Svm,
+    /// Upgrade authority, admin and compliance after `init_config`
+    pub admin: Pubkey,
+    pub usdc_mint: Pubkey,
+    /// Token program owning `usdc_mint`: spl-token unless built by `with_transfer_fee`
+    pub usdc_program: Pubkey,
+    pub seller: Pubkey,
+    pub investor: Pubkey,
+    pub buyer: Pubkey,
+}
+
+impl TestEnv {
+    /// Config initialized with `usdc_mint` as its only settlement mint and
+    /// `seller` approved without a meaningful credit limit
+    pub fn new() -> Self {
+        let mut env = Self::without_config();
+        let admin = env.admin;
+        env.send(instructions::init_config(admin, admin), &[admin]).unwrap();
+        let usdc_mint = env.usdc_mint;
+        env.list_settlement_mint(usdc_mint, 0);
+        env.register_seller(SellerStatus::Approved, u64::MAX);
+        env
+    }
+
+    /// Deployed program, no `AdminConfig` yet
+    pub fn without_config() -> Self {
+        let mut svm = Svm::new();
+        let admin = Pubkey::new_unique();
+        svm.deploy_invoice_manager(admin);
+        let mut env = Self {
+            svm,
+            admin,
+            usdc_mint: Pubkey::new_unique(),
+            usdc_program: spl_token::ID,
+            seller: Pubkey::new_unique(),
+            investor: Pubkey::new_unique(),
+            buyer: Pubkey::new_unique(),
+        };
+        env.create_mint(env.usdc_mint, 6);
+        for user in [env.admin, env.seller, env.investor, env.buyer] {
+            env.svm.airdrop(&user, 100 * SOL);
+        }
+        for user in [env.seller, env.investor, env.buyer] {
+            env.mint_usdc(&user, 1_000_000 * UNIT);
+        }
+        env
+    }
+
+    /// Like `new`, but USDC is a Token-2022 mint charging `fee_bps` on every
+    /// transfer (capped at `max_fee`), set up through the real instructions
+    pub fn with_transfer_fee(fee_bps: u16, max_fee: u64) -> Self {
+        let mut env = Self::new();
+        let (admin, mint) = (env.admin, Pubkey::new_unique());
+        let space = ExtensionType::try_calculate_account_len::<spl_token_2022::state::Mint>(&[ExtensionType::TransferFeeConfig]).unwrap();
+        let program = spl_token_2022::ID;
+        let ixs = [
+            system_instruction::create_account(&admin, &mint, Rent::default().minimum_balance(space), space as u64, &program),
+            initialize_transfer_fee_config(&program, &mint, Some(&admin), Some(&admin), fee_bps, max_fee).unwrap(),
+            spl_token_2022::instruction::initialize_mint2(&program, &mint, &admin, None, 6).unwrap(),
+        ];
+        env.svm.process(&ixs, &[admin, mint]).unwrap();
+        env.usdc_mint = mint;
+        env.usdc_program = program;
+        env.list_settlement_mint(mint, 0);
+        for user in [env.seller, env.investor, env.buyer] {
+            env.mint_usdc(&user, 1_000_000 * UNIT);
+        }
+        env
+    }
+
+    pub fn send(&mut self, ix: Instruction, signers: &[Pubkey]) -> Result<Vec<InvoiceManagerEvent>, TxError> {
+        self.send_all(&[ix], signers)
+    }
+
+    /// Sends a transaction and returns the events it emitted
+    pub fn send_all(&mut self, ixs: &[Instruction], signers: &[Pubkey]) -> Result<Vec<InvoiceManagerEvent>, TxError> {
+        self.svm.process(ixs, signers).map(|logs| parse_logs(&logs))
+    }
+
+    // Token fixtures, written straight into the account store
+
+    pub fn create_mint(&mut self, mint: Pubkey, decimals: u8) {
+        let state = spl_token::state::Mint {
+            mint_authority: COption::Some(Pubkey::new_unique()),
+            supply: 0,
+            decimals,
+            is_initialized: true,
+            freeze_authority: COption::None,
+        };
+        self.write_packed(mint, state);
+    }
+
+    /// Credits `amount` USDC to `owner`'s ATA, creating it if needed
+    pub fn mint_usdc(&mut self, owner: &Pubkey, amount: u64) -> Pubkey {
+        let mint = self.usdc_mint;
+        if self.usdc_program == spl_token::ID {
+            return self.credit_tokens(owner, &mint, amount);
+        }
+        // Token-2022 accounts carry extensions, so go through the program
+        let (admin, program) = (self.admin, self.usdc_program);
+        let ata = pda::ata_with_program(owner, &mint, &program);
+        let ixs = [
+            spl_associated_token_account::instruction::create_associated_token_account_idempotent(&admin, owner, &mint, &program),
+            spl_token_2022::instruction::mint_to(&program, &mint, &ata, &admin, &[], amount).unwrap(),
+        ];
+        self.svm.process(&ixs, &[admin]).unwrap();
+        ata
+    }
+
+    /// `owner`'s USDC account, whichever token program owns the mint
+    pub fn usdc_ata(&self, owner: &Pubkey) -> Pubkey {
+        pda::ata_with_program(owner, &self.usdc_mint, &self.usdc_program)
+    }
+
+    pub fn credit_tokens(&mut self, owner: &Pubkey, mint: &Pubkey, amount: u64) -> Pubkey {
+        let ata = pda::ata(owner, mint);
+        let mut account = self.token_account(&ata).unwrap_or(spl_token::state::Account {
+            mint: *mint,
+            owner: *owner,
+            state: spl_token::state::AccountState::Initialized,
+            ..Default::default()
+        });
+        account.amount += amount;
+        self.write_packed(ata, account);
+
+        let mut mint_state = self.mint(mint);
+        mint_state.supply += amount;
+        self.write_packed(*mint, mint_state);
+        ata
+    }
+
+    pub fn write_packed<T: Pack>(&mut self, address: Pubkey, state: T) {
+        let mut data = vec![0; T::LEN];
+        state.pack_into_slice(&mut data);
+        self.svm.set_account(address, Account {
+            lamports: Rent::default().minimum_balance(T::LEN),
+            data,
+            owner: spl_token::ID,
+            executable: false,
+        });
+    }
+
+    pub fn token_account(&self, address: &Pubkey) -> Option<spl_token::state::Account> {
+        self.svm.account(address).map(|a| spl_token::state::Account::unpack(&a.data).unwrap())
+    }
+
+    pub fn balance(&self, address: &Pubkey) -> u64 {
+        self.token_account(address).map_or(0, |a| a.amount)
+    }
+
+    pub fn mint(&self, address: &Pubkey) -> spl_token::state::Mint {
+        spl_token::state::Mint::unpack(&self.svm.account(address).unwrap().data).unwrap()
+    }
+
+    pub fn lamports(&self, address: &Pubkey) -> u64 {
+        self.svm.account(address).map_or(0, |a| a.lamports)
+    }
+
+    // Program account readers
+
+    pub fn invoice(&self, address: &Pubkey) -> Invoice {
+        decode_invoice(&self.svm.account(address).unwrap().data).unwrap()
+    }
+
+    pub fn listing(&self, address: &Pubkey) -> Listing {
+        decode(&self.svm.account(address).unwrap().data).unwrap()
+    }
+
+    pub fn attestation(&self, investor: &Pubkey) -> InvestorAttestation {
+        decode(&self.svm.account(&pda::attestation(investor).0).unwrap().data).unwrap()
+    }
+
+    pub fn config(&self) -> AdminConfig {
+        decode(&self.svm.account(&pda::config().0).unwrap().data).unwrap()
+    }
+
+    pub fn seller_profile(&self, seller: &Pubkey) -> SellerProfile {
+        decode(&self.svm.account(&pda::seller_profile(seller).0).unwrap().data).unwrap()
+    }
+
+    pub fn investor_profile(&self, investor: &Pubkey) -> InvestorProfile {
+        decode(&self.svm.account(&pda::investor_profile(investor).0).unwrap().data).unwrap()
+    }
+
+    pub fn position(&self, invoice: &Pubkey, investor: &Pubkey) -> InvestorPosition {
+        decode(&self.svm.account(&pda::position(invoice, investor).0).unwrap().data).unwrap()
+    }
+
+    pub fn repayment_schedule(&self, invoice: &Pubkey) -> RepaymentSchedule {
+        decode(&self.svm.account(&pda::repayment_schedule(invoice).0).unwrap().data).unwrap()
+    }
+
+    // Invoice lifecycle shortcuts
+
+    pub fn mint_invoice(&mut self, amount: u64) -> Pubkey {
+        let invoice = Pubkey::new_unique();
+        self.mint_invoice_at(invoice, amount);
+        invoice
+    }
+
+    pub fn mint_invoice_at(&mut self, invoice: Pubkey, amount: u64) {
+        let due_date = self.svm.clock().unix_timestamp + 30 * 24 * 60 * 60;
+        let ix = instructions::mint_invoice(invoice, self.seller, self.usdc_mint, "hash".into(), amount, due_date);
+        self.send(ix, &[invoice, self.seller]).unwrap();
+    }
+
+    /// Minted invoice with its escrow and shares mint in place
+    pub fn open_invoice(&mut self, amount: u64) -> (Pubkey, Pubkey) {
+        let invoice = self.mint_invoice(amount);
+        self.send(instructions::create_escrow(invoice, self.seller, self.usdc_mint, self.usdc_program), &[self.seller]).unwrap();
+        let shares_mint = Pubkey::new_unique();
+        self.send(instructions::init_shares(invoice, self.seller, shares_mint), &[self.seller, shares_mint]).unwrap();
+        (invoice, shares_mint)
+    }
+
+    /// Like `open_invoice`, with a restricted Token-2022 shares mint and its hook
+    /// validation account
+    pub fn open_restricted_invoice(&mut self, amount: u64) -> (Pubkey, Pubkey) {
+        let invoice = self.mint_invoice(amount);
+        self.send(instructions::create_escrow(invoice, self.seller, self.usdc_mint, self.usdc_program), &[self.seller]).unwrap();
+        let shares_mint = Pubkey::new_unique();
+        let ixs = [
+            instructions::init_shares_restricted(invoice, self.seller, shares_mint),
+            instructions::init_extra_account_meta_list(self.seller, shares_mint),
+        ];
+        self.send_all(&ixs, &[self.seller, shares_mint]).unwrap();
+        (invoice, shares_mint)
+    }
+
+    pub fn fund_fractional(&mut self, invoice: Pubkey, shares_mint: Pubkey, investor: Pubkey, amount: u64) -> Result<Vec<InvoiceManagerEvent>, TxError> {
+        let ix = instructions::fund_invoice_fractional(invoice, investor, self.seller, self.usdc_mint, self.usdc_program, shares_mint, spl_token::ID, amount);
+        self.send(ix, &[investor])
+    }
+
+    /// Invoice fractionally funded by `investor` for `funded`
+    pub fn funded_invoice(&mut self, amount: u64, funded: u64) -> (Pubkey, Pubkey) {
+        let (invoice, shares_mint) = self.open_invoice(amount);
+        let investor = self.investor;
+        self.fund_fractional(invoice, shares_mint, investor, funded).unwrap();
+        (invoice, shares_mint)
+    }
+
+    /// Approves `delegate` to move `amount` out of `owner`'s ATA for `mint`
+    pub fn approve(&mut self, owner: Pubkey, mint: &Pubkey, delegate: &Pubkey, amount: u64) {
+        let ix = spl_token::instruction::approve(&spl_token::ID, &pda::ata(&owner, mint), delegate, &owner, &[], amount).unwrap();
+        self.svm.process(&[ix], &[owner]).unwrap();
+    }
+
+    /// `approve` for a Token-2022 (restricted shares) account
+    pub fn approve_2022(&mut self, owner: Pubkey, mint: &Pubkey, delegate: &Pubkey, amount: u64) {
+        let ata = pda::ata_with_program(&owner, mint, &spl_token_2022::ID);
+        let ix = spl_token_2022::instruction::approve(&spl_token_2022::ID, &ata, delegate, &owner, &[], amount).unwrap();
+        self.svm.process(&[ix], &[owner]).unwrap();
+    }
+
+    /// Balance of a Token-2022 account, extensions included
+    pub fn balance_2022(&self, address: &Pubkey) -> u64 {
+        self.svm.account(address).map_or(0, |a| {
+            StateWithExtensions::<spl_token_2022::state::Account>::unpack(&a.data).unwrap().base.amount
+        })
+    }
+
+    /// Sends `claim_repayment` for `claimant`, burning the shares in its ATA
+    /// when it has one, as the CLI does
+    pub fn claim_repayment(&mut self, invoice: Pubkey, claimant: Pubkey) -> Result<Vec<InvoiceManagerEvent>, TxError> {
+        let (usdc_mint, shares) = self.held_shares(invoice, claimant);
+        let ix = instructions::claim_repayment(invoice, claimant, usdc_mint, self.usdc_program, shares);
+        self.send(ix, &[claimant])
+    }
+
+    /// Calls `refund_defaulted` for `holder`, passing its shares the same way
+    /// as [`TestEnv::claim_repayment`]
+    pub fn refund_defaulted(&mut self, invoice: Pubkey, holder: Pubkey) -> Result<Vec<InvoiceManagerEvent>, TxError> {
+        let (usdc_mint, shares) = self.held_shares(invoice, holder);
+        let ix = instructions::refund_defaulted(invoice, holder, usdc_mint, self.usdc_program, shares);
+        self.send(ix, &[holder])
+    }
+
+    /// The invoice's USDC mint and, when `owner` has a shares account, the
+    /// shares (mint, token program) pair
+    fn held_shares(&self, invoice: Pubkey, owner: Pubkey) -> (Pubkey, Option<(Pubkey, Pubkey)>) {
+        let state = self.invoice(&invoice);
+        let shares = self.svm.account(&state.shares_mint).map(|mint| (state.shares_mint, mint.owner));
+        let shares = shares.filter(|(mint, program)| self.svm.account(&pda::ata_with_program(&owner, mint, program)).is_some());
+        (state.usdc_mint, shares)
+    }
+
+    /// Runs `verify_invariants` and decodes its report. `listings` are
+    /// (listing, backing token account) pairs.
+    pub fn verify_invariants(&mut self, invoice: Pubkey, listings: &[(Pubkey, Pubkey)]) -> InvariantReport {
+        let state = self.invoice(&invoice);
+        let shares_mint = (state.shares_mint != Pubkey::default()).then_some(state.shares_mint);
+        let ix = instructions::verify_invariants(invoice, state.usdc_mint, self.usdc_program, shares_mint, listings);
+        self.svm.process(&[ix], &[]).unwrap();
+        let (program_id, data) = self.svm.return_data().unwrap();
+        assert_eq!(program_id, invoice_manager::ID);
+        decode_invariant_report(&data).unwrap()
+    }
+
+    /// Issues `investor` a US attestation valid for `valid_for` seconds
+    pub fn attest(&mut self, investor: Pubkey, valid_for: i64) {
+        let (compliance, expires_at) = (self.admin, self.svm.clock().unix_timestamp + valid_for);
+        self.send(instructions::issue_attestation(compliance, investor, *b"US", expires_at), &[compliance]).unwrap();
+    }
+
+    /// Sets `seller`'s onboarding status and credit limit as the admin
+    pub fn register_seller(&mut self, status: SellerStatus, credit_limit: u64) {
+        let (admin, seller) = (self.admin, self.seller);
+        self.send(instructions::register_seller(admin, seller, status, credit_limit), &[admin]).unwrap();
+    }
+
+    pub fn set_paused(&mut self, paused: u8) {
+        let admin = self.admin;
+        self.send(instructions::set_paused(admin, paused), &[admin]).unwrap();
+    }
+
+    /// Adds a 6-decimal `mint` to the settlement mint allow-list
+    pub fn list_settlement_mint(&mut self, mint: Pubkey, min_invoice_amount: u64) {
+        self.apply_config_change(ConfigChange::AddSettlementMint(SettlementMint { mint, decimals: 6, min_invoice_amount }));
+    }
+
+    /// Queues `change` and executes it once the timelock has elapsed
+    pub fn apply_config_change(&mut self, change: ConfigChange) {
+        let admin = self.admin;
+        self.send(instructions::queue_config_change(admin, change), &[admin]).unwrap();
+        let delay = self.config().timelock_delay;
+        self.svm.warp(delay);
+        self.send(instructions::execute_config_change(admin), &[]).unwrap();
+    }
+}
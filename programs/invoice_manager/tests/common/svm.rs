@@ -0,0 +1,157 @@
+//! Thin wrapper over LiteSVM for the integration tests.
+//!
+//! invoice_manager and the shares transfer hook run as the SBF binaries that
+//! `anchor build` leaves in `target/deploy`, on the real loader, with LiteSVM's
+//! bundled SPL Token, Token-2022 and Associated Token programs and its Ed25519
+//! precompile. Signature verification and the blockhash check are off, so any key
+//! can sign. Transaction history is off so identical transactions can be resent, and
+//! every transaction is paid by a fee payer owned by the harness so the actors'
+//! lamports only move through the programs.
+
+use anchor_lang::solana_program::clock::Clock;
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program_error::ProgramError;
+use anchor_lang::solana_program::pubkey::Pubkey;
+use litesvm::LiteSVM;
+use solana_loader_v3_interface::state::UpgradeableLoaderState;
+use solana_message::Message;
+use solana_sdk_ids::bpf_loader_upgradeable;
+use solana_transaction::Transaction;
+use solana_transaction_error::TransactionError;
+
+const DEPLOY_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../../target/deploy");
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Account {
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub owner: Pubkey,
+    pub executable: bool,
+}
+
+/// A failed transaction: the index of the failing instruction, its error and the logs.
+#[derive(Debug)]
+pub struct TxError {
+    pub index: usize,
+    pub error: ProgramError,
+    pub logs: Vec<String>,
+}
+
+pub struct Svm {
+    svm: LiteSVM,
+    fee_payer: Pubkey,
+    return_data: Option<(Pubkey, Vec<u8>)>,
+}
+
+fn program_binary(name: &str) -> Vec<u8> {
+    let path = format!("{DEPLOY_DIR}/{name}.so");
+    std::fs::read(&path).unwrap_or_else(|err| panic!("{path}: {err}; run `anchor build` first"))
+}
+
+impl Svm {
+    pub fn new() -> Self {
+        let mut svm = LiteSVM::new()
+            .with_sigverify(false)
+            .with_blockhash_check(false)
+            .with_transaction_history(0)
+            .with_log_bytes_limit(None);
+        svm.add_program(shares_transfer_hook::ID, &program_binary("shares_transfer_hook")).unwrap();
+        let fee_payer = Pubkey::new_unique();
+        svm.airdrop(&fee_payer, 1_000_000_000_000).unwrap();
+        let mut clock: Clock = svm.get_sysvar();
+        clock.slot = 1;
+        clock.unix_timestamp = 1_700_000_000;
+        svm.set_sysvar(&clock);
+        Self { svm, fee_payer, return_data: None }
+    }
+
+    /// Deploys the program as an upgradeable program whose upgrade authority is `authority`.
+    pub fn deploy_invoice_manager(&mut self, authority: Pubkey) {
+        let program_data = Pubkey::find_program_address(&[invoice_manager::ID.as_ref()], &bpf_loader_upgradeable::ID).0;
+        let program_state = UpgradeableLoaderState::Program { programdata_address: program_data };
+        let data_state = UpgradeableLoaderState::ProgramData { slot: 0, upgrade_authority_address: Some(authority) };
+        let mut data = bincode::serialize(&data_state).unwrap();
+        data.resize(UpgradeableLoaderState::size_of_programdata_metadata(), 0);
+        data.extend(program_binary("invoice_manager"));
+        // The loader resolves the program through its data account, so that goes first
+        self.set_account(program_data, Account {
+            lamports: self.svm.minimum_balance_for_rent_exemption(data.len()),
+            data,
+            owner: bpf_loader_upgradeable::ID,
+            executable: false,
+        });
+        let data = bincode::serialize(&program_state).unwrap();
+        self.set_account(invoice_manager::ID, Account {
+            lamports: self.svm.minimum_balance_for_rent_exemption(data.len()),
+            data,
+            owner: bpf_loader_upgradeable::ID,
+            executable: true,
+        });
+    }
+
+    pub fn account(&self, address: &Pubkey) -> Option<Account> {
+        let account = self.svm.get_account(address).filter(|a| a.lamports > 0)?;
+        Some(Account { lamports: account.lamports, data: account.data, owner: account.owner, executable: account.executable })
+    }
+
+    pub fn set_account(&mut self, address: Pubkey, account: Account) {
+        let Account { lamports, data, owner, executable } = account;
+        let account = solana_account::Account { lamports, data, owner, executable, rent_epoch: 0 };
+        self.svm.set_account(address, account).unwrap();
+    }
+
+    pub fn airdrop(&mut self, address: &Pubkey, lamports: u64) {
+        self.svm.airdrop(address, lamports).unwrap();
+    }
+
+    /// Return data left by the last instruction of the last transaction
+    pub fn return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        self.return_data.clone()
+    }
+
+    pub fn clock(&self) -> Clock {
+        self.svm.get_sysvar()
+    }
+
+    /// Moves the clock forward by `seconds` (and the slot by one per 400ms).
+    pub fn warp(&mut self, seconds: i64) {
+        let mut clock = self.clock();
+        clock.unix_timestamp += seconds;
+        clock.slot += (seconds.max(0) as u64) * 5 / 2;
+        self.svm.set_sysvar(&clock);
+    }
+
+    pub fn warp_to_timestamp(&mut self, unix_timestamp: i64) {
+        let now = self.clock().unix_timestamp;
+        self.warp(unix_timestamp - now);
+    }
+
+    /// Executes `instructions` atomically. Every key in `signers` is treated as
+    /// having signed the transaction. Returns the transaction logs.
+    pub fn process(&mut self, instructions: &[Instruction], signers: &[Pubkey]) -> Result<Vec<String>, TxError> {
+        for (index, ix) in instructions.iter().enumerate() {
+            if let Some(meta) = ix.accounts.iter().find(|m| m.is_signer && !signers.contains(&m.pubkey)) {
+                let logs = vec![format!("{} did not sign", meta.pubkey)];
+                return Err(TxError { index, error: ProgramError::MissingRequiredSignature, logs });
+            }
+        }
+        let tx = Transaction::new_unsigned(Message::new(instructions, Some(&self.fee_payer)));
+        self.return_data = None;
+        match self.svm.send_transaction(tx) {
+            Ok(meta) => {
+                let return_data = meta.return_data;
+                if !return_data.data.is_empty() {
+                    self.return_data = Some((return_data.program_id, return_data.data));
+                }
+                Ok(meta.logs)
+            }
+            Err(failed) => match failed.err {
+                TransactionError::InstructionError(index, err) => {
+                    let error = ProgramError::try_from(err.clone()).unwrap_or_else(|_| panic!("{err:?}: {:#?}", failed.meta.logs));
+                    Err(TxError { index: index as usize, error, logs: failed.meta.logs })
+                }
+                err => panic!("transaction rejected: {err:?}: {:#?}", failed.meta.logs),
+            },
+        }
+    }
+}
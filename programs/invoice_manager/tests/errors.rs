@@ -0,0 +1,655 @@
+//! Every `InvoiceError` variant, triggered through the instruction that raises it.
+
+mod common;
+
+use anchor_lang::solana_program::pubkey::Pubkey;
+use anchor_lang::AccountSerialize;
+use common::*;
+use invoice_manager::{
+    ConfigChange, ExposureLimits, InstallmentTerms, InvoiceError, InvoiceStatus, SellerStatus, SettlementMint, INVOICE_STATUS_OFFSET, MAX_ATTESTATION_AGE,
+    MAX_INSTALLMENTS, MAX_RISK_TIER, MAX_SETTLEMENT_MINTS, PAUSE_FUNDING, PAUSE_SETTLEMENT, PAUSE_TRADING,
+};
+use invoice_manager_client::{instructions, pda, SettlementAttestation};
+
+fn settle_ix(env: &TestEnv, invoice: Pubkey, operator: Pubkey, amount: u64) -> anchor_lang::solana_program::instruction::Instruction {
+    instructions::set_settled(invoice, operator, env.seller, env.usdc_mint, spl_token::ID, amount, None)
+}
+
+#[test]
+fn wrong_status() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 500 * UNIT);
+    let admin = env.admin;
+    env.send(settle_ix(&env, invoice, admin, 500 * UNIT), &[admin]).unwrap();
+
+    let investor = env.investor;
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, UNIT), InvoiceError::WrongStatus);
+    assert_program_error(env.send(settle_ix(&env, invoice, admin, 500 * UNIT), &[admin]), InvoiceError::WrongStatus);
+    assert_program_error(env.refund_defaulted(invoice, investor), InvoiceError::WrongStatus);
+
+    let (open, _) = env.open_invoice(INVOICE_AMOUNT);
+    let (debtor, seller) = (env.buyer, env.seller);
+    let ix = instructions::pay_invoice(open, debtor, seller, env.usdc_mint, spl_token::ID, UNIT);
+    assert_program_error(env.send(ix, &[debtor]), InvoiceError::WrongStatus);
+
+    // Funding closes once the debtor starts repaying
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 500 * UNIT);
+    env.send(instructions::pay_invoice(invoice, debtor, seller, env.usdc_mint, spl_token::ID, UNIT), &[debtor]).unwrap();
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, UNIT), InvoiceError::WrongStatus);
+    // ...and so does off-chain settlement
+    assert_program_error(env.send(settle_ix(&env, invoice, admin, 500 * UNIT), &[admin]), InvoiceError::WrongStatus);
+
+    // Schedules are fixed once funding starts
+    let terms = vec![InstallmentTerms { due_date: env.invoice(&invoice).due_date, amount: INVOICE_AMOUNT }];
+    let ix = instructions::set_repayment_schedule(invoice, seller, terms);
+    assert_program_error(env.send(ix, &[seller]), InvoiceError::WrongStatus);
+
+    // Claims only pay out of Funded and Settled invoices. mark_defaulted refuses
+    // repaid invoices, so the status is written directly.
+    let mut account = env.svm.account(&invoice).unwrap();
+    account.data[INVOICE_STATUS_OFFSET] = InvoiceStatus::Defaulted as u8;
+    env.svm.set_account(invoice, account);
+    assert_program_error(env.claim_repayment(invoice, investor), InvoiceError::WrongStatus);
+}
+
+#[test]
+fn bump_not_found() {
+    // An invoice whose escrow was never created still has escrow_bump = 0. If the
+    // bump-0 address happens to be a valid PDA, anyone can open a token account for
+    // it, so the seeds check alone does not stop funding.
+    let mut env = TestEnv::new();
+    let (invoice, bump_zero_authority) = std::iter::repeat_with(Pubkey::new_unique)
+        .find_map(|key| {
+            Pubkey::create_program_address(&[b"escrow", key.as_ref(), &[0]], &invoice_manager::ID)
+                .ok()
+                .map(|pda| (key, pda))
+        })
+        .unwrap();
+    env.mint_invoice_at(invoice, INVOICE_AMOUNT);
+    let (seller, investor, usdc) = (env.seller, env.investor, env.usdc_mint);
+    let shares_mint = Pubkey::new_unique();
+    env.send(instructions::init_shares(invoice, seller, shares_mint), &[seller, shares_mint]).unwrap();
+    let create_ata = spl_associated_token_account::instruction::create_associated_token_account(
+        &investor,
+        &bump_zero_authority,
+        &usdc,
+        &spl_token::ID,
+    );
+    env.svm.process(&[create_ata], &[investor]).unwrap();
+
+    let mut ix = instructions::fund_invoice_fractional(invoice, investor, env.seller, usdc, spl_token::ID, shares_mint, spl_token::ID, UNIT);
+    for meta in &mut ix.accounts {
+        if meta.pubkey == pda::escrow_authority(&invoice).0 {
+            meta.pubkey = bump_zero_authority;
+        } else if meta.pubkey == pda::escrow_token(&invoice, &usdc) {
+            meta.pubkey = pda::ata(&bump_zero_authority, &usdc);
+        }
+    }
+    assert_program_error(env.send(ix, &[investor]), InvoiceError::BumpNotFound);
+}
+
+#[test]
+fn mint_mismatch() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let other_mint = Pubkey::new_unique();
+    env.create_mint(other_mint, 6);
+    let seller = env.investor;
+    let ix = instructions::create_listing(invoice, seller, shares_mint, other_mint, UNIT, UNIT);
+    assert_program_error(env.send(ix, &[seller]), InvoiceError::MintMismatch);
+}
+
+#[test]
+fn shares_mint_missing() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.open_invoice(INVOICE_AMOUNT);
+    let other_mint = Pubkey::new_unique();
+    env.create_mint(other_mint, 6);
+    let investor = env.investor;
+    assert_program_error(env.fund_fractional(invoice, other_mint, investor, UNIT), InvoiceError::SharesMintMissing);
+}
+
+#[test]
+fn math_overflow() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, buyer, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    env.send(instructions::create_listing(invoice, seller, shares_mint, usdc, 10 * UNIT, u64::MAX), &[seller])
+        .unwrap();
+    let ix = instructions::fulfill_listing(invoice, seller, buyer, shares_mint, usdc, spl_token::ID, 2);
+    assert_program_error(env.send(ix, &[buyer]), InvoiceError::MathOverflow);
+}
+
+#[test]
+fn listing_mismatch() {
+    // Listing addresses are derived from their invoice, so a mismatch needs a
+    // corrupted listing; write one directly.
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, usdc) = (env.investor, env.usdc_mint);
+    env.send(instructions::create_listing_v2(invoice, seller, shares_mint, usdc, UNIT, UNIT), &[seller]).unwrap();
+    let listing_key = pda::listing(&invoice, &seller).0;
+    let mut listing = env.listing(&listing_key);
+    listing.invoice = Pubkey::new_unique();
+    let mut account = env.svm.account(&listing_key).unwrap();
+    account.data.clear();
+    listing.try_serialize(&mut account.data).unwrap();
+    env.svm.set_account(listing_key, account);
+
+    let ix = instructions::cancel_listing_v2(invoice, seller, shares_mint, spl_token::ID);
+    assert_program_error(env.send(ix, &[seller]), InvoiceError::ListingMismatch);
+}
+
+#[test]
+fn insufficient_escrow() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, buyer, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    env.send(instructions::create_listing(invoice, seller, shares_mint, usdc, 10 * UNIT, UNIT), &[seller]).unwrap();
+
+    let ix = instructions::fulfill_listing(invoice, seller, buyer, shares_mint, usdc, spl_token::ID, 11 * UNIT);
+    assert_program_error(env.send(ix, &[buyer]), InvoiceError::InsufficientEscrow);
+    let ix = instructions::fulfill_listing(invoice, seller, buyer, shares_mint, usdc, spl_token::ID, 0);
+    assert_program_error(env.send(ix, &[buyer]), InvoiceError::InsufficientEscrow);
+}
+
+#[test]
+fn overfund() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 900 * UNIT);
+    let (investor, admin) = (env.investor, env.admin);
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, 101 * UNIT), InvoiceError::Overfund);
+    // Settlement must release exactly the funded amount
+    assert_program_error(env.send(settle_ix(&env, invoice, admin, INVOICE_AMOUNT), &[admin]), InvoiceError::Overfund);
+}
+
+#[test]
+fn delegate_missing() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, buyer, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    env.send(instructions::create_listing_v2(invoice, seller, shares_mint, usdc, 10 * UNIT, UNIT), &[seller]).unwrap();
+
+    let ix = instructions::fulfill_listing_v2(invoice, seller, buyer, shares_mint, spl_token::ID, usdc, spl_token::ID, UNIT);
+    assert_program_error(env.send(ix, &[buyer]), InvoiceError::DelegateMissing);
+}
+
+#[test]
+fn insufficient_allowance() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, buyer, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    env.send(instructions::create_listing_v2(invoice, seller, shares_mint, usdc, 10 * UNIT, UNIT), &[seller]).unwrap();
+    let market = pda::market_authority(&pda::listing(&invoice, &seller).0).0;
+    env.approve(seller, &shares_mint, &market, 5 * UNIT);
+    env.approve(buyer, &usdc, &market, 10 * UNIT);
+
+    let ix = instructions::fulfill_listing_v2(invoice, seller, buyer, shares_mint, spl_token::ID, usdc, spl_token::ID, 6 * UNIT);
+    assert_program_error(env.send(ix, &[buyer]), InvoiceError::InsufficientAllowance);
+}
+
+#[test]
+fn invalid_parameter() {
+    let mut env = TestEnv::new();
+    let admin = env.admin;
+    assert_program_error(env.send(instructions::set_paused(admin, 1 << 7), &[admin]), InvoiceError::InvalidParameter);
+    let ix = instructions::queue_config_change(admin, ConfigChange::Admin(Pubkey::default()));
+    assert_program_error(env.send(ix, &[admin]), InvoiceError::InvalidParameter);
+
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, usdc) = (env.investor, env.usdc_mint);
+    let ix = instructions::create_listing(invoice, seller, shares_mint, usdc, 0, UNIT);
+    assert_program_error(env.send(ix, &[seller]), InvoiceError::InvalidParameter);
+
+    let invoice = env.mint_invoice(INVOICE_AMOUNT);
+    let ix = instructions::set_invoice_risk(invoice, admin, MAX_RISK_TIER + 1, 900);
+    assert_program_error(env.send(ix, &[admin]), InvoiceError::InvalidParameter);
+
+    // A config too short to hold the version byte is not a known layout
+    let config = pda::config().0;
+    let mut account = env.svm.account(&config).unwrap();
+    account.data.truncate(100);
+    env.svm.set_account(config, account);
+    let payer = env.buyer;
+    assert_program_error(env.send(instructions::migrate_config(payer), &[payer]), InvoiceError::InvalidParameter);
+}
+
+#[test]
+fn unauthorized() {
+    let mut env = TestEnv::without_config();
+    let stranger = env.buyer;
+    let ix = instructions::init_config(stranger, stranger);
+    assert_program_error(env.send(ix, &[stranger]), InvoiceError::Unauthorized);
+
+    let admin = env.admin;
+    env.send(instructions::init_config(admin, admin), &[admin]).unwrap();
+    assert_program_error(env.send(instructions::set_paused(stranger, PAUSE_FUNDING), &[stranger]), InvoiceError::Unauthorized);
+    let ix = instructions::queue_config_change(stranger, ConfigChange::Compliance(stranger));
+    assert_program_error(env.send(ix, &[stranger]), InvoiceError::Unauthorized);
+
+    let usdc = env.usdc_mint;
+    env.list_settlement_mint(usdc, 0);
+    let ix = instructions::register_seller(stranger, env.seller, SellerStatus::Approved, u64::MAX);
+    assert_program_error(env.send(ix, &[stranger]), InvoiceError::Unauthorized);
+    env.register_seller(SellerStatus::Approved, u64::MAX);
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let ix = instructions::set_invoice_frozen(invoice, stranger, true);
+    assert_program_error(env.send(ix, &[stranger]), InvoiceError::Unauthorized);
+    let ix = instructions::set_invoice_risk(invoice, stranger, 1, 900);
+    assert_program_error(env.send(ix, &[stranger]), InvoiceError::Unauthorized);
+    let ix = instructions::set_invoice_debtor(invoice, stranger, stranger, false);
+    assert_program_error(env.send(ix, &[stranger]), InvoiceError::Unauthorized);
+    let ix = instructions::acknowledge_invoice(invoice, stranger, INVOICE_AMOUNT, env.invoice(&invoice).due_date);
+    assert_program_error(env.send(ix, &[stranger]), InvoiceError::Unauthorized);
+    assert_program_error(env.send(settle_ix(&env, invoice, stranger, INVOICE_AMOUNT), &[stranger]), InvoiceError::Unauthorized);
+}
+
+#[test]
+fn no_pending_admin() {
+    let mut env = TestEnv::new();
+    let admin = env.admin;
+    assert_program_error(env.send(instructions::accept_admin(admin), &[admin]), InvoiceError::NoPendingAdmin);
+    assert_program_error(env.send(instructions::cancel_admin_proposal(admin), &[admin]), InvoiceError::NoPendingAdmin);
+}
+
+#[test]
+fn paused() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, admin, usdc) = (env.investor, env.admin, env.usdc_mint);
+    let (open, _) = env.open_invoice(INVOICE_AMOUNT);
+    let (owner, debtor, due_date) = (env.seller, env.buyer, env.invoice(&open).due_date);
+    let terms = vec![InstallmentTerms { due_date, amount: INVOICE_AMOUNT }];
+    env.send(instructions::set_repayment_schedule(open, owner, terms.clone()), &[owner]).unwrap();
+    env.send(instructions::set_invoice_debtor(open, owner, debtor, true), &[owner]).unwrap();
+
+    env.set_paused(PAUSE_FUNDING);
+    let ix = instructions::mint_invoice(Pubkey::new_unique(), env.seller, usdc, "h".into(), UNIT, 0);
+    let signers = [ix.accounts[1].pubkey, env.seller];
+    assert_program_error(env.send(ix, &signers), InvoiceError::Paused);
+    // Setting up an invoice for funding counts as funding
+    let ix = instructions::set_repayment_schedule(open, owner, terms);
+    assert_program_error(env.send(ix, &[owner]), InvoiceError::Paused);
+    let ix = instructions::set_invoice_risk(open, admin, 1, 900);
+    assert_program_error(env.send(ix, &[admin]), InvoiceError::Paused);
+    let ix = instructions::set_invoice_debtor(open, owner, debtor, false);
+    assert_program_error(env.send(ix, &[owner]), InvoiceError::Paused);
+    let ix = instructions::acknowledge_invoice(open, debtor, INVOICE_AMOUNT, due_date);
+    assert_program_error(env.send(ix, &[debtor]), InvoiceError::Paused);
+
+    env.set_paused(PAUSE_TRADING);
+    let ix = instructions::create_listing(invoice, seller, shares_mint, usdc, UNIT, UNIT);
+    assert_program_error(env.send(ix, &[seller]), InvoiceError::Paused);
+
+    env.set_paused(PAUSE_SETTLEMENT);
+    assert_program_error(env.send(settle_ix(&env, invoice, admin, INVOICE_AMOUNT), &[admin]), InvoiceError::Paused);
+    assert_program_error(env.send(instructions::flag_overdue_installments(open), &[]), InvoiceError::Paused);
+}
+
+#[test]
+fn invoice_frozen() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 500 * UNIT);
+    let (compliance, investor, admin) = (env.admin, env.investor, env.admin);
+    env.send(instructions::set_invoice_frozen(invoice, compliance, true), &[compliance]).unwrap();
+
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, UNIT), InvoiceError::InvoiceFrozen);
+    let ix = instructions::create_listing(invoice, investor, shares_mint, env.usdc_mint, UNIT, UNIT);
+    assert_program_error(env.send(ix, &[investor]), InvoiceError::InvoiceFrozen);
+    assert_program_error(env.send(settle_ix(&env, invoice, admin, 500 * UNIT), &[admin]), InvoiceError::InvoiceFrozen);
+}
+
+#[test]
+fn timelock_not_elapsed() {
+    let mut env = TestEnv::new();
+    let admin = env.admin;
+    env.send(instructions::queue_config_change(admin, ConfigChange::TimelockDelay(0)), &[admin]).unwrap();
+    assert_program_error(env.send(instructions::execute_config_change(admin), &[]), InvoiceError::TimelockNotElapsed);
+
+    let delay = env.config().timelock_delay;
+    env.svm.warp(delay - 1);
+    assert_program_error(env.send(instructions::execute_config_change(admin), &[]), InvoiceError::TimelockNotElapsed);
+    env.svm.warp(1);
+    env.send(instructions::execute_config_change(admin), &[]).unwrap();
+}
+
+fn attested_settlement(env: &TestEnv, oracle: &Oracle, invoice: Pubkey, amount: u64, timestamp: i64) -> Vec<anchor_lang::solana_program::instruction::Instruction> {
+    let attestation = SettlementAttestation { payment_ref: [1; 32], timestamp };
+    vec![
+        oracle.attest(&invoice, amount, &attestation),
+        instructions::set_settled(invoice, env.buyer, env.seller, env.usdc_mint, spl_token::ID, amount, Some(attestation)),
+    ]
+}
+
+#[test]
+fn oracle_not_configured() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let now = env.svm.clock().unix_timestamp;
+    let ixs = attested_settlement(&env, &Oracle::new(3), invoice, INVOICE_AMOUNT, now);
+    let relayer = env.buyer;
+    assert_program_error(env.send_all(&ixs, &[relayer]), InvoiceError::OracleNotConfigured);
+}
+
+#[test]
+fn invalid_attestation() {
+    let mut env = TestEnv::new();
+    let oracle = Oracle::new(3);
+    env.apply_config_change(ConfigChange::SettlementOracle(oracle.pubkey));
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (now, relayer) = (env.svm.clock().unix_timestamp, env.buyer);
+
+    // No Ed25519 instruction in front of set_settled
+    let ixs = attested_settlement(&env, &oracle, invoice, INVOICE_AMOUNT, now);
+    assert_program_error(env.send_all(&ixs[1..], &[relayer]), InvoiceError::InvalidAttestation);
+
+    // Signed by someone other than the configured oracle
+    let ixs = attested_settlement(&env, &Oracle::new(4), invoice, INVOICE_AMOUNT, now);
+    assert_program_error(env.send_all(&ixs, &[relayer]), InvoiceError::InvalidAttestation);
+
+    // Valid signature, but over a different payment reference
+    let mut ixs = attested_settlement(&env, &oracle, invoice, INVOICE_AMOUNT, now);
+    let other = SettlementAttestation { payment_ref: [2; 32], timestamp: now };
+    ixs[1] = instructions::set_settled(invoice, relayer, env.seller, env.usdc_mint, spl_token::ID, INVOICE_AMOUNT, Some(other));
+    assert_program_error(env.send_all(&ixs, &[relayer]), InvoiceError::InvalidAttestation);
+}
+
+#[test]
+fn stale_attestation() {
+    let mut env = TestEnv::new();
+    let oracle = Oracle::new(3);
+    env.apply_config_change(ConfigChange::SettlementOracle(oracle.pubkey));
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (signed_at, relayer) = (env.svm.clock().unix_timestamp, env.buyer);
+
+    env.svm.warp(MAX_ATTESTATION_AGE + 1);
+    let ixs = attested_settlement(&env, &oracle, invoice, INVOICE_AMOUNT, signed_at);
+    assert_program_error(env.send_all(&ixs, &[relayer]), InvoiceError::StaleAttestation);
+
+    // Timestamps too far in the future are rejected as well
+    let future = env.svm.clock().unix_timestamp + MAX_ATTESTATION_AGE + 1;
+    let ixs = attested_settlement(&env, &oracle, invoice, INVOICE_AMOUNT, future);
+    assert_program_error(env.send_all(&ixs, &[relayer]), InvoiceError::StaleAttestation);
+}
+
+#[test]
+fn already_migrated() {
+    let mut env = TestEnv::new();
+    let invoice = env.mint_invoice(INVOICE_AMOUNT);
+    let payer = env.seller;
+    assert_program_error(env.send(instructions::migrate_invoice(invoice, payer), &[payer]), InvoiceError::AlreadyMigrated);
+}
+
+#[test]
+fn attestation_missing() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 500 * UNIT);
+    let (compliance, buyer, seller, usdc) = (env.admin, env.buyer, env.investor, env.usdc_mint);
+    env.send(instructions::create_listing(invoice, seller, shares_mint, usdc, 10 * UNIT, UNIT), &[seller]).unwrap();
+
+    // Globally, for funding
+    env.send(instructions::set_kyc_required(compliance, true), &[compliance]).unwrap();
+    assert_program_error(env.fund_fractional(invoice, shares_mint, buyer, UNIT), InvoiceError::AttestationMissing);
+
+    // Per invoice, for a V1 buy
+    env.send(instructions::set_kyc_required(compliance, false), &[compliance]).unwrap();
+    env.send(instructions::set_invoice_kyc_required(invoice, compliance, true), &[compliance]).unwrap();
+    let ix = instructions::fulfill_listing(invoice, seller, buyer, shares_mint, usdc, spl_token::ID, UNIT);
+    assert_program_error(env.send(ix, &[buyer]), InvoiceError::AttestationMissing);
+}
+
+#[test]
+fn attestation_expired() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let (compliance, investor) = (env.admin, env.investor);
+    env.send(instructions::set_invoice_kyc_required(invoice, compliance, true), &[compliance]).unwrap();
+    env.attest(investor, 60);
+    env.svm.warp(60);
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, UNIT), InvoiceError::AttestationExpired);
+}
+
+#[test]
+fn shares_already_initialized() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.open_invoice(INVOICE_AMOUNT);
+    let (seller, shares_mint) = (env.seller, Pubkey::new_unique());
+    let ix = instructions::init_shares_restricted(invoice, seller, shares_mint);
+    assert_program_error(env.send(ix, &[seller, shares_mint]), InvoiceError::SharesAlreadyInitialized);
+}
+
+#[test]
+fn settlement_mint_not_allowed() {
+    let mut env = TestEnv::new();
+    let (seller, other_mint) = (env.seller, Pubkey::new_unique());
+    env.create_mint(other_mint, 6);
+    let invoice = Pubkey::new_unique();
+    let due_date = env.svm.clock().unix_timestamp + 86_400;
+    let ix = instructions::mint_invoice(invoice, seller, other_mint, "hash".into(), INVOICE_AMOUNT, due_date);
+    assert_program_error(env.send(ix, &[invoice, seller]), InvoiceError::SettlementMintNotAllowed);
+
+    let admin = env.admin;
+    let ix = instructions::queue_config_change(admin, ConfigChange::RemoveSettlementMint(other_mint));
+    assert_program_error(env.send(ix, &[admin]), InvoiceError::SettlementMintNotAllowed);
+}
+
+#[test]
+fn invoice_below_minimum() {
+    let mut env = TestEnv::new();
+    let usdc = env.usdc_mint;
+    env.list_settlement_mint(usdc, INVOICE_AMOUNT);
+    let (invoice, seller) = (Pubkey::new_unique(), env.seller);
+    let due_date = env.svm.clock().unix_timestamp + 86_400;
+    let ix = instructions::mint_invoice(invoice, seller, usdc, "hash".into(), INVOICE_AMOUNT - 1, due_date);
+    assert_program_error(env.send(ix, &[invoice, seller]), InvoiceError::InvoiceBelowMinimum);
+}
+
+#[test]
+fn settlement_mint_list_full() {
+    let mut env = TestEnv::new();
+    for _ in 1..MAX_SETTLEMENT_MINTS {
+        env.list_settlement_mint(Pubkey::new_unique(), 0);
+    }
+    let admin = env.admin;
+    let change = ConfigChange::AddSettlementMint(SettlementMint { mint: Pubkey::new_unique(), decimals: 6, min_invoice_amount: 0 });
+    assert_program_error(env.send(instructions::queue_config_change(admin, change), &[admin]), InvoiceError::SettlementMintListFull);
+}
+
+#[test]
+fn investor_position_limit() {
+    let mut env = TestEnv::new();
+    let limits = ExposureLimits { max_investor_position: 300 * UNIT, ..Default::default() };
+    env.apply_config_change(ConfigChange::ExposureLimits(limits));
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 200 * UNIT);
+    let (investor, buyer, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, 101 * UNIT), InvoiceError::InvestorPositionLimit);
+
+    // Buying on the market counts toward the buyer's position too
+    env.fund_fractional(invoice, shares_mint, buyer, 250 * UNIT).unwrap();
+    env.send(instructions::create_listing(invoice, investor, shares_mint, usdc, 100 * UNIT, UNIT), &[investor]).unwrap();
+    let ix = instructions::fulfill_listing(invoice, investor, buyer, shares_mint, usdc, spl_token::ID, 51 * UNIT);
+    assert_program_error(env.send(ix, &[buyer]), InvoiceError::InvestorPositionLimit);
+}
+
+#[test]
+fn investor_share_limit() {
+    let mut env = TestEnv::new();
+    let limits = ExposureLimits { max_investor_share_bps: 2_500, ..Default::default() };
+    env.apply_config_change(ConfigChange::ExposureLimits(limits));
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let investor = env.investor;
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, 251 * UNIT), InvoiceError::InvestorShareLimit);
+
+    let admin = env.admin;
+    let limits = ExposureLimits { max_investor_share_bps: 10_001, ..Default::default() };
+    let ix = instructions::queue_config_change(admin, ConfigChange::ExposureLimits(limits));
+    assert_program_error(env.send(ix, &[admin]), InvoiceError::InvalidParameter);
+}
+
+#[test]
+fn seller_exposure_limit() {
+    let mut env = TestEnv::new();
+    let limits = ExposureLimits { max_seller_outstanding: 1_500 * UNIT, ..Default::default() };
+    env.apply_config_change(ConfigChange::ExposureLimits(limits));
+    let (first, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (second, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let investor = env.investor;
+    assert_program_error(env.fund_fractional(second, shares_mint, investor, 501 * UNIT), InvoiceError::SellerExposureLimit);
+
+    // Settling the first invoice frees the seller's capacity
+    let admin = env.admin;
+    env.send(settle_ix(&env, first, admin, INVOICE_AMOUNT), &[admin]).unwrap();
+    env.fund_fractional(second, shares_mint, investor, INVOICE_AMOUNT).unwrap();
+}
+
+#[test]
+fn seller_not_approved() {
+    let mut env = TestEnv::new();
+    let (seller, usdc) = (env.seller, env.usdc_mint);
+    let due_date = env.svm.clock().unix_timestamp + 86_400;
+    for status in [SellerStatus::Pending, SellerStatus::Suspended] {
+        env.register_seller(status, u64::MAX);
+        let invoice = Pubkey::new_unique();
+        let ix = instructions::mint_invoice(invoice, seller, usdc, "hash".into(), INVOICE_AMOUNT, due_date);
+        assert_program_error(env.send(ix, &[invoice, seller]), InvoiceError::SellerNotApproved);
+    }
+
+    // Suspension also stops funding of invoices minted before it
+    env.register_seller(SellerStatus::Approved, u64::MAX);
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    env.register_seller(SellerStatus::Suspended, u64::MAX);
+    let investor = env.investor;
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, UNIT), InvoiceError::SellerNotApproved);
+
+    // Funding never creates a missing profile in the Pending state
+    env.svm.set_account(pda::seller_profile(&seller).0, Account::default());
+    let result = env.fund_fractional(invoice, shares_mint, investor, UNIT).err().map(|tx| tx.error);
+    assert_eq!(result, Some(anchor_error(anchor_lang::error::ErrorCode::AccountNotInitialized)));
+}
+
+#[test]
+fn credit_limit_exceeded() {
+    let mut env = TestEnv::new();
+    env.register_seller(SellerStatus::Approved, 1_500 * UNIT);
+    env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (invoice, seller, usdc) = (Pubkey::new_unique(), env.seller, env.usdc_mint);
+    let due_date = env.svm.clock().unix_timestamp + 86_400;
+    let ix = instructions::mint_invoice(invoice, seller, usdc, "hash".into(), 501 * UNIT, due_date);
+    assert_program_error(env.send(ix, &[invoice, seller]), InvoiceError::CreditLimitExceeded);
+
+    // Invoices minted within the line can't be funded past it together
+    let (invoice, shares_mint) = env.open_invoice(500 * UNIT);
+    env.register_seller(SellerStatus::Approved, 1_200 * UNIT);
+    let investor = env.investor;
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, 201 * UNIT), InvoiceError::CreditLimitExceeded);
+    env.fund_fractional(invoice, shares_mint, investor, 200 * UNIT).unwrap();
+}
+
+#[test]
+fn invoice_not_due() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (admin, seller) = (env.admin, env.seller);
+    assert_program_error(env.send(instructions::mark_defaulted(invoice, admin, seller), &[admin]), InvoiceError::InvoiceNotDue);
+}
+
+#[test]
+fn risk_locked() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, UNIT);
+    let underwriter = env.admin;
+    let ix = instructions::set_invoice_risk(invoice, underwriter, 2, 900);
+    assert_program_error(env.send(ix, &[underwriter]), InvoiceError::RiskLocked);
+}
+
+#[test]
+fn acknowledgement_required() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let (seller, debtor, investor) = (env.seller, env.buyer, env.investor);
+    env.send(instructions::set_invoice_debtor(invoice, seller, debtor, true), &[seller]).unwrap();
+    assert_program_error(env.fund_fractional(invoice, shares_mint, investor, UNIT), InvoiceError::AcknowledgementRequired);
+    let ix = instructions::fund_invoice(invoice, investor, seller, env.usdc_mint, spl_token::ID, UNIT);
+    assert_program_error(env.send(ix, &[investor]), InvoiceError::AcknowledgementRequired);
+}
+
+#[test]
+fn acknowledgement_mismatch() {
+    let mut env = TestEnv::new();
+    let invoice = env.mint_invoice(INVOICE_AMOUNT);
+    let (seller, debtor) = (env.seller, env.buyer);
+    env.send(instructions::set_invoice_debtor(invoice, seller, debtor, true), &[seller]).unwrap();
+    let due_date = env.invoice(&invoice).due_date;
+    let ix = instructions::acknowledge_invoice(invoice, debtor, INVOICE_AMOUNT - 1, due_date);
+    assert_program_error(env.send(ix, &[debtor]), InvoiceError::AcknowledgementMismatch);
+    let ix = instructions::acknowledge_invoice(invoice, debtor, INVOICE_AMOUNT, due_date + 1);
+    assert_program_error(env.send(ix, &[debtor]), InvoiceError::AcknowledgementMismatch);
+}
+
+#[test]
+fn overpayment() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (debtor, seller) = (env.buyer, env.seller);
+    env.send(instructions::pay_invoice(invoice, debtor, seller, env.usdc_mint, spl_token::ID, 900 * UNIT), &[debtor]).unwrap();
+    let ix = instructions::pay_invoice(invoice, debtor, seller, env.usdc_mint, spl_token::ID, 100 * UNIT + 1);
+    assert_program_error(env.send(ix, &[debtor]), InvoiceError::Overpayment);
+}
+
+#[test]
+fn invalid_schedule() {
+    let mut env = TestEnv::new();
+    let invoice = env.mint_invoice(INVOICE_AMOUNT);
+    let seller = env.seller;
+    let due_date = env.invoice(&invoice).due_date;
+    let term = |due_date: i64, amount: u64| InstallmentTerms { due_date, amount };
+    for installments in [
+        vec![],
+        vec![term(due_date, INVOICE_AMOUNT - 1)],
+        vec![term(due_date + 1, INVOICE_AMOUNT)],
+        vec![term(due_date, 500 * UNIT), term(due_date - 1, 500 * UNIT)],
+        vec![term(due_date - 1, INVOICE_AMOUNT), term(due_date, 0)],
+        vec![term(due_date, UNIT); MAX_INSTALLMENTS + 1],
+    ] {
+        let ix = instructions::set_repayment_schedule(invoice, seller, installments);
+        assert_program_error(env.send(ix, &[seller]), InvoiceError::InvalidSchedule);
+    }
+}
+
+#[test]
+fn nothing_to_claim() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (investor, buyer, seller, usdc) = (env.investor, env.buyer, env.seller, env.usdc_mint);
+    env.send(instructions::pay_invoice(invoice, buyer, seller, usdc, spl_token::ID, 100 * UNIT), &[buyer]).unwrap();
+    // The buyer paid but holds no shares, and the seller's invoice was fully funded
+    for claimant in [buyer, seller] {
+        assert_program_error(env.claim_repayment(invoice, claimant), InvoiceError::NothingToClaim);
+    }
+    env.claim_repayment(invoice, investor).unwrap();
+    assert_program_error(env.claim_repayment(invoice, investor), InvoiceError::NothingToClaim);
+}
+
+#[test]
+fn restricted_shares_unsupported() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_restricted_invoice(INVOICE_AMOUNT);
+    let (investor, usdc) = (env.investor, env.usdc_mint);
+    env.attest(investor, 86_400);
+    let ix = instructions::fund_invoice_fractional(invoice, investor, env.seller, usdc, spl_token::ID, shares_mint, spl_token_2022::ID, 100 * UNIT);
+    env.send(ix, &[investor]).unwrap();
+
+    // Rejected before any transfer, whichever token program the caller passes
+    let holding = pda::ata_with_program(&investor, &shares_mint, &spl_token_2022::ID);
+    for token_program in [spl_token::ID, spl_token_2022::ID] {
+        let mut ix = instructions::create_listing(invoice, investor, shares_mint, usdc, 50 * UNIT, UNIT);
+        for meta in ix.accounts.iter_mut() {
+            if meta.pubkey == pda::ata(&investor, &shares_mint) {
+                meta.pubkey = holding;
+            } else if meta.pubkey == spl_token::ID {
+                meta.pubkey = token_program;
+            }
+        }
+        assert_program_error(env.send(ix, &[investor]), InvoiceError::RestrictedSharesUnsupported);
+    }
+}
@@ -0,0 +1,940 @@
+//! Happy path of every instruction, run in-process (see `common::svm`).
+
+mod common;
+
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::pubkey::Pubkey;
+use anchor_lang::solana_program::rent::Rent;
+use anchor_lang::{AccountSerialize, Discriminator};
+use common::*;
+use invoice_manager::{
+    AdminConfig, ConfigChange, InstallmentTerms, Invoice, InvoiceError, InvoiceStatus, Listing, SellerStatus, SettlementMint, CONFIG_VERSION,
+    DEFAULT_TIMELOCK_DELAY, INVOICE_DEBTOR_OFFSET, INVARIANT_ESCROW_SHORTFALL, INVARIANT_LISTING_UNBACKED, INVARIANT_SHARES_EXCEED_FUNDING,
+    INVOICE_FROZEN_OFFSET, INVOICE_INVESTOR_OFFSET, INVOICE_RISK_TIER_OFFSET, INVOICE_SELLER_OFFSET, INVOICE_SHARES_MINT_OFFSET,
+    INVOICE_STATUS_OFFSET, INVOICE_USDC_MINT_OFFSET, INVOICE_VERSION, INVOICE_VERSION_OFFSET, LISTING_CANCELED_SCHEMA_VERSION,
+    LISTING_CREATED_SCHEMA_VERSION, LISTING_FULFILLED_SCHEMA_VERSION, LISTING_VERSION, MARKET_V1, MARKET_V2,
+    PAUSE_FUNDING,
+};
+use invoice_manager_client::accounts::{decode_invoice, decode_listing, invoice_filters};
+use invoice_manager_client::events::InvoiceManagerEvent as Event;
+use invoice_manager_client::{instructions, pda, SettlementAttestation};
+
+#[test]
+fn init_config_records_admin_and_deployer() {
+    let mut env = TestEnv::without_config();
+    let (admin, deployer) = (Pubkey::new_unique(), env.admin);
+    let events = env.send(instructions::init_config(deployer, admin), &[deployer]).unwrap();
+
+    let config = env.config();
+    assert_eq!(config.admin, admin);
+    assert_eq!(config.compliance, admin);
+    assert_eq!(config.deployer, deployer);
+    assert_eq!(config.pending_admin, Pubkey::default());
+    assert_eq!(config.paused, 0);
+    assert_eq!(config.timelock_delay, DEFAULT_TIMELOCK_DELAY);
+    assert!(matches!(&events[..], [Event::ConfigInitialized(e)] if e.admin == admin && e.deployer == deployer));
+}
+
+#[test]
+fn mint_escrow_and_shares() {
+    let mut env = TestEnv::new();
+    let invoice = Pubkey::new_unique();
+    let due_date = env.svm.clock().unix_timestamp + 86_400;
+    let ix = instructions::mint_invoice(invoice, env.seller, env.usdc_mint, "QmHash".into(), INVOICE_AMOUNT, due_date);
+    let events = env.send(ix, &[invoice, env.seller]).unwrap();
+
+    let state = env.invoice(&invoice);
+    assert_eq!(state.seller, env.seller);
+    assert_eq!(state.amount, INVOICE_AMOUNT);
+    assert_eq!(state.metadata_hash(), "QmHash");
+    assert_eq!(state.due_date, due_date);
+    assert!(state.status().unwrap() == InvoiceStatus::Open);
+    assert_eq!(state.usdc_mint, env.usdc_mint);
+    assert!(matches!(&events[..], [Event::InvoiceMinted(e)] if e.invoice == invoice && e.seq == 0));
+
+    let events = env.send(instructions::create_escrow(invoice, env.seller, env.usdc_mint, spl_token::ID), &[env.seller]).unwrap();
+    let (authority, bump) = pda::escrow_authority(&invoice);
+    let escrow = env.token_account(&pda::escrow_token(&invoice, &env.usdc_mint)).unwrap();
+    assert_eq!(escrow.owner, authority);
+    assert_eq!(escrow.mint, env.usdc_mint);
+    assert_eq!(env.invoice(&invoice).escrow_bump, bump);
+    assert!(matches!(&events[..], [Event::EscrowCreated(e)] if e.seq == 1));
+
+    let shares_mint = Pubkey::new_unique();
+    let events = env.send(instructions::init_shares(invoice, env.seller, shares_mint), &[env.seller, shares_mint]).unwrap();
+    let mint = env.mint(&shares_mint);
+    assert_eq!(mint.mint_authority, COption::Some(authority));
+    assert_eq!(mint.decimals, 6);
+    assert_eq!(env.invoice(&invoice).shares_mint, shares_mint);
+    assert!(matches!(&events[..], [Event::SharesInitialized(e)] if e.shares_mint == shares_mint && e.seq == 2));
+}
+
+#[test]
+fn fund_invoice_moves_usdc_to_escrow() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.open_invoice(INVOICE_AMOUNT);
+    let investor_usdc = pda::ata(&env.investor, &env.usdc_mint);
+    let before = env.balance(&investor_usdc);
+
+    let events = env
+        .send(instructions::fund_invoice(invoice, env.investor, env.seller, env.usdc_mint, spl_token::ID, 400 * UNIT), &[env.investor])
+        .unwrap();
+
+    assert_eq!(env.balance(&pda::escrow_token(&invoice, &env.usdc_mint)), 400 * UNIT);
+    assert_eq!(env.balance(&investor_usdc), before - 400 * UNIT);
+    let state = env.invoice(&invoice);
+    assert!(state.status().unwrap() == InvoiceStatus::Funded);
+    assert_eq!(state.funded_amount, 400 * UNIT);
+    assert_eq!(state.investor, env.investor);
+    assert!(matches!(&events[..], [Event::InvoiceFunded(e)] if e.shares_minted == 0 && e.funded_amount == 400 * UNIT));
+}
+
+#[test]
+fn fund_invoice_fractional_mints_shares_one_to_one() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let (investor, buyer) = (env.investor, env.buyer);
+
+    env.fund_fractional(invoice, shares_mint, investor, 300 * UNIT).unwrap();
+    let events = env.fund_fractional(invoice, shares_mint, buyer, 700 * UNIT).unwrap();
+
+    assert_eq!(env.balance(&pda::ata(&investor, &shares_mint)), 300 * UNIT);
+    assert_eq!(env.balance(&pda::ata(&buyer, &shares_mint)), 700 * UNIT);
+    assert_eq!(env.mint(&shares_mint).supply, INVOICE_AMOUNT);
+    assert_eq!(env.balance(&pda::escrow_token(&invoice, &env.usdc_mint)), INVOICE_AMOUNT);
+    assert_eq!(env.invoice(&invoice).funded_amount, INVOICE_AMOUNT);
+    assert!(matches!(&events[..], [Event::InvoiceFunded(e)] if e.shares_minted == 700 * UNIT && e.seq == 4));
+}
+
+#[test]
+fn admin_settles_funded_invoice() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, 600 * UNIT);
+    let seller_usdc = pda::ata(&env.seller, &env.usdc_mint);
+    let before = env.balance(&seller_usdc);
+
+    let ix = instructions::set_settled(invoice, env.admin, env.seller, env.usdc_mint, spl_token::ID, 600 * UNIT, None);
+    let events = env.send(ix, &[env.admin]).unwrap();
+
+    assert_eq!(env.balance(&seller_usdc), before + 600 * UNIT);
+    assert_eq!(env.balance(&pda::escrow_token(&invoice, &env.usdc_mint)), 0);
+    assert!(env.invoice(&invoice).status().unwrap() == InvoiceStatus::Settled);
+    assert!(matches!(&events[..], [Event::InvoiceSettled(e)] if !e.oracle_attested && e.amount == 600 * UNIT));
+}
+
+#[test]
+fn debtor_pays_invoice_into_vault_and_settles_at_face_value() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, 600 * UNIT);
+    let (debtor, seller, usdc) = (env.buyer, env.seller, env.usdc_mint);
+    let seller_usdc = pda::ata(&seller, &usdc);
+    let before = env.balance(&seller_usdc);
+    let vault = pda::repayment_vault(&invoice).0;
+
+    let events = env.send(instructions::pay_invoice(invoice, debtor, seller, usdc, spl_token::ID, 400 * UNIT), &[debtor]).unwrap();
+    assert_eq!(env.balance(&vault), 400 * UNIT);
+    assert_eq!(env.invoice(&invoice).repaid_amount, 400 * UNIT);
+    assert!(env.invoice(&invoice).status().unwrap() == InvoiceStatus::Funded);
+    assert!(matches!(&events[..], [Event::InvoicePaid(e)]
+        if e.invoice == invoice && e.payer == debtor && e.amount == 400 * UNIT && e.repaid_amount == 400 * UNIT));
+
+    let events = env.send(instructions::pay_invoice(invoice, debtor, seller, usdc, spl_token::ID, 600 * UNIT), &[debtor]).unwrap();
+    assert_eq!(env.balance(&vault), INVOICE_AMOUNT);
+    assert_eq!(env.balance(&seller_usdc), before + 600 * UNIT);
+    assert_eq!(env.balance(&pda::escrow_token(&invoice, &usdc)), 0);
+    assert!(env.invoice(&invoice).status().unwrap() == InvoiceStatus::Settled);
+    assert_eq!(env.seller_profile(&seller).total_repaid, 600 * UNIT);
+    assert!(matches!(&events[..], [Event::InvoicePaid(p), Event::InvoiceSettled(s)]
+        if p.repaid_amount == INVOICE_AMOUNT && s.operator == debtor && s.amount == 600 * UNIT && !s.oracle_attested));
+}
+
+#[test]
+fn scheduled_repayment_is_tracked_per_installment_and_claimed_pro_rata() {
+    const DAY: i64 = 24 * 60 * 60;
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let (seller, investor, buyer, usdc) = (env.seller, env.investor, env.buyer, env.usdc_mint);
+    let due_date = env.invoice(&invoice).due_date;
+    let installments = vec![
+        InstallmentTerms { due_date: due_date - 20 * DAY, amount: 300 * UNIT },
+        InstallmentTerms { due_date: due_date - 10 * DAY, amount: 300 * UNIT },
+        InstallmentTerms { due_date, amount: 400 * UNIT },
+    ];
+    let events = env.send(instructions::set_repayment_schedule(invoice, seller, installments), &[seller]).unwrap();
+    assert_eq!(env.repayment_schedule(&invoice).installments().len(), 3);
+    assert!(matches!(&events[..], [Event::RepaymentScheduleSet(e)]
+        if e.invoice == invoice && e.installment_count == 3 && e.first_due_date == due_date - 20 * DAY));
+    env.fund_fractional(invoice, shares_mint, investor, 600 * UNIT).unwrap();
+
+    env.svm.warp(11 * DAY);
+    let events = env.send(instructions::flag_overdue_installments(invoice), &[env.admin]).unwrap();
+    assert!(matches!(&events[..], [Event::InstallmentOverdue(e)] if e.index == 0 && e.amount == 300 * UNIT && e.repaid == 0));
+    let ix = instructions::flag_overdue_installments(invoice);
+    assert_program_error(env.send(ix, &[env.admin]), InvoiceError::InvoiceNotDue);
+
+    let debtor = Pubkey::new_unique();
+    env.svm.airdrop(&debtor, SOL);
+    env.mint_usdc(&debtor, INVOICE_AMOUNT);
+    env.send(instructions::pay_invoice(invoice, debtor, seller, usdc, spl_token::ID, 450 * UNIT), &[debtor]).unwrap();
+    let repaid: Vec<u64> = env.repayment_schedule(&invoice).installments().iter().map(|i| i.repaid).collect();
+    assert_eq!(repaid, [300 * UNIT, 150 * UNIT, 0]);
+
+    // Repayments follow the shares: the investor sells 100 after the first
+    // payment, then claims on its other 500, which are burned
+    let listing = pda::listing(&invoice, &investor).0;
+    let market = pda::market_authority(&listing).0;
+    env.send(instructions::create_listing_v2(invoice, investor, shares_mint, usdc, 100 * UNIT, UNIT), &[investor]).unwrap();
+    env.approve(investor, &shares_mint, &market, 100 * UNIT);
+    env.approve(buyer, &usdc, &market, 100 * UNIT);
+    let ix = instructions::fulfill_listing_v2(invoice, investor, buyer, shares_mint, spl_token::ID, usdc, spl_token::ID, 100 * UNIT);
+    env.send(ix, &[buyer]).unwrap();
+    let events = env.claim_repayment(invoice, investor).unwrap();
+    assert!(matches!(&events[..], [Event::RepaymentClaimed(e)] if e.amount == 225 * UNIT && e.repaid_amount == 450 * UNIT));
+    assert_eq!(env.balance(&pda::ata(&investor, &shares_mint)), 0);
+    assert_eq!(env.position(&invoice, &investor).claim_basis, 500 * UNIT);
+    assert_eq!(env.mint(&shares_mint).supply, 100 * UNIT);
+
+    env.send(instructions::pay_invoice(invoice, debtor, seller, usdc, spl_token::ID, 550 * UNIT), &[debtor]).unwrap();
+    assert!(env.repayment_schedule(&invoice).installments().iter().all(|i| i.repaid == i.amount));
+    assert!(env.invoice(&invoice).status().unwrap() == InvoiceStatus::Settled);
+
+    // The rest of the investor's 500, all of the buyer's 100 and the unfunded
+    // 400 to the seller
+    for (claimant, expected) in [(investor, 275 * UNIT), (buyer, 100 * UNIT), (seller, 400 * UNIT)] {
+        let before = env.balance(&pda::ata(&claimant, &usdc));
+        let events = env.claim_repayment(invoice, claimant).unwrap();
+        assert_eq!(env.balance(&pda::ata(&claimant, &usdc)), before + expected);
+        assert!(matches!(&events[..], [Event::RepaymentClaimed(e)]
+            if e.claimant == claimant && e.amount == expected && e.repaid_amount == INVOICE_AMOUNT));
+    }
+    assert_eq!(env.balance(&pda::repayment_vault(&invoice).0), 0);
+    assert_eq!(env.position(&invoice, &investor).claimed_repayment, 500 * UNIT);
+    let state = env.invoice(&invoice);
+    assert_eq!((state.investors_claimed, state.seller_claimed), (600 * UNIT, 400 * UNIT));
+}
+
+#[test]
+fn repayment_claims_follow_shares_moved_outside_the_program() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 500 * UNIT);
+    let (alice, carol, seller, usdc) = (env.investor, env.buyer, env.seller, env.usdc_mint);
+    let bob = Pubkey::new_unique();
+    env.svm.airdrop(&bob, SOL);
+    env.mint_usdc(&bob, 0);
+
+    // Alice hands her shares to Bob with a plain token transfer, and Bob sells
+    // them to Carol through the program
+    let create = spl_associated_token_account::instruction::create_associated_token_account(&alice, &bob, &shares_mint, &spl_token::ID);
+    let transfer = spl_token::instruction::transfer(&spl_token::ID, &pda::ata(&alice, &shares_mint), &pda::ata(&bob, &shares_mint), &alice, &[], 500 * UNIT).unwrap();
+    env.svm.process(&[create, transfer], &[alice]).unwrap();
+    let market = pda::market_authority(&pda::listing(&invoice, &bob).0).0;
+    env.send(instructions::create_listing_v2(invoice, bob, shares_mint, usdc, 500 * UNIT, UNIT), &[bob]).unwrap();
+    env.approve(bob, &shares_mint, &market, 500 * UNIT);
+    env.approve(carol, &usdc, &market, 500 * UNIT);
+    let ix = instructions::fulfill_listing_v2(invoice, bob, carol, shares_mint, spl_token::ID, usdc, spl_token::ID, 500 * UNIT);
+    env.send(ix, &[carol]).unwrap();
+    // Positions now add up to twice the funding
+    assert_eq!(env.position(&invoice, &alice).amount + env.position(&invoice, &carol).amount, INVOICE_AMOUNT);
+
+    env.send(instructions::pay_invoice(invoice, carol, seller, usdc, spl_token::ID, INVOICE_AMOUNT), &[carol]).unwrap();
+    for claimant in [alice, bob] {
+        assert_program_error(env.claim_repayment(invoice, claimant), InvoiceError::NothingToClaim);
+    }
+    let before = env.balance(&pda::ata(&carol, &usdc));
+    env.claim_repayment(invoice, carol).unwrap();
+    assert_eq!(env.balance(&pda::ata(&carol, &usdc)), before + 500 * UNIT);
+    assert_eq!(env.balance(&pda::ata(&carol, &shares_mint)), 0);
+    env.claim_repayment(invoice, seller).unwrap();
+    assert_eq!(env.balance(&pda::repayment_vault(&invoice).0), 0);
+}
+
+#[test]
+fn defaulted_escrow_is_refunded_to_share_holders() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 500 * UNIT);
+    let (alice, admin, seller, usdc) = (env.investor, env.admin, env.seller, env.usdc_mint);
+    let bob = Pubkey::new_unique();
+    env.svm.airdrop(&bob, SOL);
+    env.mint_usdc(&bob, 0);
+
+    let create = spl_associated_token_account::instruction::create_associated_token_account(&alice, &bob, &shares_mint, &spl_token::ID);
+    let transfer = spl_token::instruction::transfer(&spl_token::ID, &pda::ata(&alice, &shares_mint), &pda::ata(&bob, &shares_mint), &alice, &[], 200 * UNIT).unwrap();
+    env.svm.process(&[create, transfer], &[alice]).unwrap();
+
+    env.svm.warp(31 * 24 * 60 * 60);
+    env.send(instructions::mark_defaulted(invoice, admin, seller), &[admin]).unwrap();
+
+    for (holder, amount) in [(alice, 300 * UNIT), (bob, 200 * UNIT)] {
+        let before = env.balance(&pda::ata(&holder, &usdc));
+        let events = env.refund_defaulted(invoice, holder).unwrap();
+        assert!(matches!(&events[..], [Event::EscrowRefunded(e)] if e.holder == holder && e.amount == amount));
+        assert_eq!(env.balance(&pda::ata(&holder, &usdc)), before + amount);
+        assert_eq!(env.balance(&pda::ata(&holder, &shares_mint)), 0);
+        assert_program_error(env.refund_defaulted(invoice, holder), InvoiceError::NothingToClaim);
+    }
+    assert_eq!(env.invoice(&invoice).refunded_amount, 500 * UNIT);
+    assert_eq!(env.verify_invariants(invoice, &[]).violations, 0);
+}
+
+#[test]
+fn partially_repaid_invoice_cannot_be_defaulted() {
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, 500 * UNIT);
+    let (investor, debtor, admin, seller, usdc) = (env.investor, env.buyer, env.admin, env.seller, env.usdc_mint);
+    env.send(instructions::pay_invoice(invoice, debtor, seller, usdc, spl_token::ID, 200 * UNIT), &[debtor]).unwrap();
+
+    env.svm.warp(31 * 24 * 60 * 60);
+    assert_program_error(env.send(instructions::mark_defaulted(invoice, admin, seller), &[admin]), InvoiceError::WrongStatus);
+    assert_program_error(env.refund_defaulted(invoice, investor), InvoiceError::WrongStatus);
+    assert!(env.invoice(&invoice).status().unwrap() == InvoiceStatus::Funded);
+
+    // The installment is still claimed from the vault, and only from there
+    let before = env.balance(&pda::ata(&investor, &usdc));
+    env.claim_repayment(invoice, investor).unwrap();
+    assert_eq!(env.balance(&pda::ata(&investor, &usdc)), before + 100 * UNIT);
+    assert_eq!(env.balance(&pda::escrow_token(&invoice, &usdc)), 500 * UNIT);
+}
+
+#[test]
+fn oracle_attested_settlement_submitted_by_relayer() {
+    let mut env = TestEnv::new();
+    let oracle = Oracle::new(7);
+    env.apply_config_change(ConfigChange::SettlementOracle(oracle.pubkey));
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+
+    let relayer = env.buyer;
+    let attestation = SettlementAttestation { payment_ref: [9; 32], timestamp: env.svm.clock().unix_timestamp };
+    let verify = oracle.attest(&invoice, INVOICE_AMOUNT, &attestation);
+    let settle = instructions::set_settled(invoice, relayer, env.seller, env.usdc_mint, spl_token::ID, INVOICE_AMOUNT, Some(attestation));
+    let events = env.send_all(&[verify, settle], &[relayer]).unwrap();
+
+    assert!(env.invoice(&invoice).status().unwrap() == InvoiceStatus::Settled);
+    assert!(matches!(&events[..], [Event::InvoiceSettled(e)] if e.oracle_attested && e.operator == relayer));
+}
+
+#[test]
+fn marketplace_v1_list_fulfill_cancel() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, buyer, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    let listing = pda::listing(&invoice, &seller).0;
+    let escrow_shares = pda::escrow_shares(&listing, &shares_mint);
+
+    let ix = instructions::create_listing(invoice, seller, shares_mint, usdc, 500 * UNIT, 1_020_000);
+    let events = env.send(ix, &[seller]).unwrap();
+    assert_eq!(env.balance(&escrow_shares), 500 * UNIT);
+    assert_eq!(env.listing(&listing).remaining_qty, 500 * UNIT);
+    assert!(matches!(&events[..], [Event::ListingCreated(e)] if e.market_version == MARKET_V1 && e.qty == 500 * UNIT));
+
+    let seller_usdc_before = env.balance(&pda::ata(&seller, &usdc));
+    let ix = instructions::fulfill_listing(invoice, seller, buyer, shares_mint, usdc, spl_token::ID, 200 * UNIT);
+    let events = env.send(ix, &[buyer]).unwrap();
+    assert_eq!(env.balance(&pda::ata(&buyer, &shares_mint)), 200 * UNIT);
+    assert_eq!(env.balance(&pda::ata(&seller, &usdc)), seller_usdc_before + 204 * UNIT);
+    assert_eq!(env.listing(&listing).remaining_qty, 300 * UNIT);
+    assert!(matches!(
+        &events[..],
+        [Event::ListingFulfilled(e), Event::ListingFulfilledV1(_)] if e.total == 204 * UNIT && e.seq == 1 && e.schema_version == LISTING_FULFILLED_SCHEMA_VERSION
+    ));
+
+    let events = env.send(instructions::cancel_listing(invoice, seller, shares_mint), &[seller]).unwrap();
+    assert_eq!(env.balance(&escrow_shares), 0);
+    assert_eq!(env.balance(&pda::ata(&seller, &shares_mint)), 800 * UNIT);
+    assert_eq!(env.listing(&listing).remaining_qty, 0);
+    assert!(matches!(&events[..], [Event::ListingCanceled(e), Event::ListingCanceledV1(_)] if e.qty == 300 * UNIT && e.schema_version == LISTING_CANCELED_SCHEMA_VERSION));
+}
+
+#[test]
+fn marketplace_v2_list_fulfill_cancel() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, buyer, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    let listing = pda::listing(&invoice, &seller).0;
+    let market = pda::market_authority(&listing).0;
+
+    let ix = instructions::create_listing_v2(invoice, seller, shares_mint, usdc, 400 * UNIT, 990_000);
+    let events = env.send(ix, &[seller]).unwrap();
+    assert!(matches!(&events[..], [Event::ListingCreated(e)] if e.market_version == MARKET_V2));
+    // V2 leaves the shares with the seller
+    assert_eq!(env.balance(&pda::ata(&seller, &shares_mint)), INVOICE_AMOUNT);
+
+    env.approve(seller, &shares_mint, &market, 400 * UNIT);
+    env.approve(buyer, &usdc, &market, 99 * UNIT);
+    let seller_usdc_before = env.balance(&pda::ata(&seller, &usdc));
+    let ix = instructions::fulfill_listing_v2(invoice, seller, buyer, shares_mint, spl_token::ID, usdc, spl_token::ID, 100 * UNIT);
+    let events = env.send(ix, &[buyer]).unwrap();
+    assert_eq!(env.balance(&pda::ata(&buyer, &shares_mint)), 100 * UNIT);
+    assert_eq!(env.balance(&pda::ata(&seller, &usdc)), seller_usdc_before + 99 * UNIT);
+    assert_eq!(env.listing(&listing).remaining_qty, 300 * UNIT);
+    assert!(matches!(&events[..], [Event::ListingFulfilled(e), Event::ListingFulfilledV2(_)] if e.market_version == MARKET_V2));
+
+    let events = env.send(instructions::cancel_listing_v2(invoice, seller, shares_mint, spl_token::ID), &[seller]).unwrap();
+    let seller_shares = env.token_account(&pda::ata(&seller, &shares_mint)).unwrap();
+    assert_eq!(seller_shares.delegate, COption::None);
+    assert_eq!(env.listing(&listing).remaining_qty, 0);
+    assert!(matches!(&events[..], [Event::ListingCanceled(e), Event::ListingCanceledV2(_)] if e.qty == 300 * UNIT));
+}
+
+#[test]
+fn exposure_counters_follow_funding_trades_and_settlement() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 400 * UNIT);
+    let (seller, investor, buyer, usdc) = (env.seller, env.investor, env.buyer, env.usdc_mint);
+    let profile = env.seller_profile(&seller);
+    assert_eq!((profile.seller, profile.outstanding, profile.total_funded), (seller, 400 * UNIT, 400 * UNIT));
+    assert_eq!(env.investor_profile(&investor).total_funded, 400 * UNIT);
+    assert_eq!(env.position(&invoice, &investor).amount, 400 * UNIT);
+
+    env.send(instructions::create_listing(invoice, investor, shares_mint, usdc, 100 * UNIT, UNIT), &[investor]).unwrap();
+    let ix = instructions::fulfill_listing(invoice, investor, buyer, shares_mint, usdc, spl_token::ID, 60 * UNIT);
+    env.send(ix, &[buyer]).unwrap();
+    assert_eq!(env.position(&invoice, &investor).amount, 340 * UNIT);
+    assert_eq!(env.position(&invoice, &buyer).amount, 60 * UNIT);
+    assert_eq!(env.investor_profile(&buyer).total_purchased, 60 * UNIT);
+
+    let admin = env.admin;
+    let ix = instructions::set_settled(invoice, admin, seller, usdc, spl_token::ID, 400 * UNIT, None);
+    env.send(ix, &[admin]).unwrap();
+    let profile = env.seller_profile(&seller);
+    assert_eq!((profile.outstanding, profile.total_funded), (0, 400 * UNIT));
+}
+
+#[test]
+fn debtor_acknowledgement_unlocks_funding() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let (seller, debtor, investor) = (env.seller, env.buyer, env.investor);
+    let events = env.send(instructions::set_invoice_debtor(invoice, seller, debtor, true), &[seller]).unwrap();
+    let state = env.invoice(&invoice);
+    assert_eq!(state.debtor, debtor);
+    assert_eq!((state.acknowledgement_required, state.acknowledged), (1, 0));
+    assert!(matches!(&events[..], [Event::InvoiceDebtorSet(e)] if e.debtor == debtor && e.acknowledgement_required));
+
+    let ix = instructions::acknowledge_invoice(invoice, debtor, INVOICE_AMOUNT, state.due_date);
+    let events = env.send(ix, &[debtor]).unwrap();
+    assert_eq!(env.invoice(&invoice).acknowledged, 1);
+    assert!(matches!(&events[..], [Event::InvoiceAcknowledged(e)]
+        if e.invoice == invoice && e.debtor == debtor && e.amount == INVOICE_AMOUNT && e.due_date == state.due_date));
+    env.fund_fractional(invoice, shares_mint, investor, UNIT).unwrap();
+
+    // Naming someone else before funding resets the acknowledgement
+    let (other, _) = env.open_invoice(INVOICE_AMOUNT);
+    env.send(instructions::set_invoice_debtor(other, seller, debtor, true), &[seller]).unwrap();
+    let due_date = env.invoice(&other).due_date;
+    env.send(instructions::acknowledge_invoice(other, debtor, INVOICE_AMOUNT, due_date), &[debtor]).unwrap();
+    env.send(instructions::set_invoice_debtor(other, seller, investor, true), &[seller]).unwrap();
+    assert_eq!(env.invoice(&other).acknowledged, 0);
+}
+
+#[test]
+fn underwriter_rates_invoice_until_funding_starts() {
+    let mut env = TestEnv::new();
+    let underwriter = env.buyer;
+    env.apply_config_change(ConfigChange::Underwriter(underwriter));
+    assert_eq!(env.config().underwriter, underwriter);
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    assert_eq!(env.invoice(&invoice).risk_tier, 0);
+
+    env.send(instructions::set_invoice_risk(invoice, underwriter, 3, 1_400), &[underwriter]).unwrap();
+    let events = env.send(instructions::set_invoice_risk(invoice, underwriter, 2, 1_100), &[underwriter]).unwrap();
+    let state = env.invoice(&invoice);
+    assert_eq!((state.risk_tier, state.target_apr_bps), (2, 1_100));
+    assert!(matches!(&events[..], [Event::InvoiceRiskUpdated(e)]
+        if e.invoice == invoice && e.underwriter == underwriter && e.risk_tier == 2 && e.target_apr_bps == 1_100 && e.seq == state.seq));
+
+    let investor = env.investor;
+    let events = env.fund_fractional(invoice, shares_mint, investor, 500 * UNIT).unwrap();
+    assert!(matches!(&events[..], [Event::InvoiceFunded(e)] if e.risk_tier == 2 && e.target_apr_bps == 1_100));
+    let ix = instructions::create_listing_v2(invoice, investor, shares_mint, env.usdc_mint, 100 * UNIT, UNIT);
+    let events = env.send(ix, &[investor]).unwrap();
+    assert!(matches!(&events[..], [Event::ListingCreated(e)]
+        if e.schema_version == LISTING_CREATED_SCHEMA_VERSION && e.risk_tier == 2 && e.target_apr_bps == 1_100));
+}
+
+#[test]
+fn seller_registry_tracks_issued_funded_repaid_and_defaulted() {
+    let mut env = TestEnv::new();
+    let (admin, seller) = (env.admin, env.seller);
+    let ix = instructions::register_seller(admin, seller, SellerStatus::Approved, 2 * INVOICE_AMOUNT);
+    let events = env.send(ix, &[admin]).unwrap();
+    assert!(matches!(&events[..], [Event::SellerProfileUpdated(e)]
+        if e.seller == seller && e.authority == admin && e.status == SellerStatus::Approved && e.credit_limit == 2 * INVOICE_AMOUNT));
+
+    let (repaid, _) = env.funded_invoice(INVOICE_AMOUNT, 600 * UNIT);
+    let (defaulted, _) = env.funded_invoice(INVOICE_AMOUNT, 400 * UNIT);
+    let usdc = env.usdc_mint;
+    env.send(instructions::set_settled(repaid, admin, seller, usdc, spl_token::ID, 600 * UNIT, None), &[admin]).unwrap();
+
+    env.svm.warp(31 * 24 * 60 * 60);
+    let events = env.send(instructions::mark_defaulted(defaulted, admin, seller), &[admin]).unwrap();
+    assert!(env.invoice(&defaulted).status().unwrap() == InvoiceStatus::Defaulted);
+    assert!(matches!(&events[..], [Event::InvoiceDefaulted(e)] if e.invoice == defaulted && e.amount == 400 * UNIT));
+
+    let profile = env.seller_profile(&seller);
+    assert!(profile.status == SellerStatus::Approved);
+    assert_eq!(profile.credit_limit, 2 * INVOICE_AMOUNT);
+    assert_eq!(profile.outstanding, 0);
+    assert_eq!(profile.total_issued, 2 * INVOICE_AMOUNT);
+    assert_eq!(profile.total_funded, INVOICE_AMOUNT);
+    assert_eq!(profile.total_repaid, 600 * UNIT);
+    assert_eq!(profile.total_defaulted, 400 * UNIT);
+}
+
+#[test]
+fn timelocked_config_change_executes_after_delay() {
+    let mut env = TestEnv::new();
+    let admin = env.admin;
+    let compliance = Pubkey::new_unique();
+    let events = env.send(instructions::queue_config_change(admin, ConfigChange::Compliance(compliance)), &[admin]).unwrap();
+    let eta = env.svm.clock().unix_timestamp + DEFAULT_TIMELOCK_DELAY;
+    assert!(matches!(&events[..], [Event::ConfigChangeQueued(e)] if e.eta == eta));
+
+    env.svm.warp_to_timestamp(eta);
+    let admin_lamports = env.lamports(&admin);
+    let change_rent = env.lamports(&pda::config_change().0);
+    let events = env.send(instructions::execute_config_change(admin), &[]).unwrap();
+
+    assert_eq!(env.config().compliance, compliance);
+    assert!(env.svm.account(&pda::config_change().0).is_none_or(|a| a.lamports == 0));
+    assert_eq!(env.lamports(&admin), admin_lamports + change_rent);
+    assert!(matches!(&events[..], [Event::ConfigChangeExecuted(_)]));
+}
+
+#[test]
+fn cancel_config_change_refunds_proposer() {
+    let mut env = TestEnv::new();
+    let admin = env.admin;
+    env.send(instructions::queue_config_change(admin, ConfigChange::TimelockDelay(60)), &[admin]).unwrap();
+    let events = env.send(instructions::cancel_config_change(admin, admin), &[admin]).unwrap();
+
+    assert!(env.svm.account(&pda::config_change().0).is_none_or(|a| a.lamports == 0));
+    assert_eq!(env.config().timelock_delay, DEFAULT_TIMELOCK_DELAY);
+    assert!(matches!(&events[..], [Event::ConfigChangeCanceled(_)]));
+}
+
+#[test]
+fn admin_rotation_is_timelocked_then_accepted() {
+    let mut env = TestEnv::new();
+    let (old_admin, new_admin) = (env.admin, Pubkey::new_unique());
+    env.apply_config_change(ConfigChange::Admin(new_admin));
+    assert_eq!(env.config().pending_admin, new_admin);
+
+    let events = env.send(instructions::accept_admin(new_admin), &[new_admin]).unwrap();
+    let config = env.config();
+    assert_eq!(config.admin, new_admin);
+    assert_eq!(config.pending_admin, Pubkey::default());
+    assert!(matches!(&events[..], [Event::AdminAccepted(e)] if e.previous_admin == old_admin));
+}
+
+#[test]
+fn admin_cancels_pending_rotation() {
+    let mut env = TestEnv::new();
+    let admin = env.admin;
+    env.apply_config_change(ConfigChange::Admin(Pubkey::new_unique()));
+    let events = env.send(instructions::cancel_admin_proposal(admin), &[admin]).unwrap();
+    assert_eq!(env.config().pending_admin, Pubkey::default());
+    assert!(matches!(&events[..], [Event::AdminProposalCanceled(_)]));
+}
+
+#[test]
+fn pause_and_unpause() {
+    let mut env = TestEnv::new();
+    let admin = env.admin;
+    let events = env.send(instructions::set_paused(admin, PAUSE_FUNDING), &[admin]).unwrap();
+    assert_eq!(env.config().paused, PAUSE_FUNDING);
+    let clock = env.svm.clock();
+    assert!(matches!(&events[..], [Event::PauseUpdated(e)]
+        if e.paused == PAUSE_FUNDING && e.slot == clock.slot && e.timestamp == clock.unix_timestamp));
+
+    env.set_paused(0);
+    env.mint_invoice(INVOICE_AMOUNT);
+}
+
+#[test]
+fn compliance_freezes_and_unfreezes_invoice() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let compliance = env.admin;
+
+    let events = env.send(instructions::set_invoice_frozen(invoice, compliance, true), &[compliance]).unwrap();
+    assert!(env.invoice(&invoice).is_frozen());
+    assert!(matches!(&events[..], [Event::InvoiceFrozenUpdated(e)] if e.frozen));
+
+    env.send(instructions::set_invoice_frozen(invoice, compliance, false), &[compliance]).unwrap();
+    let investor = env.investor;
+    env.fund_fractional(invoice, shares_mint, investor, UNIT).unwrap();
+}
+
+#[test]
+fn kyc_gate_admits_attested_investors() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let (compliance, investor) = (env.admin, env.investor);
+    let events = env.send(instructions::set_kyc_required(compliance, true), &[compliance]).unwrap();
+    assert_eq!(env.config().kyc_required, 1);
+    assert!(matches!(&events[..], [Event::KycRequiredUpdated(e)] if e.invoice.is_none() && e.required));
+
+    let expires_at = env.svm.clock().unix_timestamp + 86_400;
+    let events = env.send(instructions::issue_attestation(compliance, investor, *b"DE", expires_at), &[compliance]).unwrap();
+    let attestation = env.attestation(&investor);
+    assert_eq!((attestation.investor, attestation.issuer), (investor, compliance));
+    assert_eq!((attestation.jurisdiction, attestation.expires_at), (*b"DE", expires_at));
+    assert!(matches!(&events[..], [Event::AttestationIssued(e)] if e.jurisdiction == *b"DE"));
+    env.fund_fractional(invoice, shares_mint, investor, 100 * UNIT).unwrap();
+
+    // Per-invoice gate, with the global one off: an attested buyer can take a V2 listing
+    env.send(instructions::set_kyc_required(compliance, false), &[compliance]).unwrap();
+    let events = env.send(instructions::set_invoice_kyc_required(invoice, compliance, true), &[compliance]).unwrap();
+    assert_eq!(env.invoice(&invoice).kyc_required, 1);
+    assert!(matches!(&events[..], [Event::KycRequiredUpdated(e)] if e.invoice == Some(invoice) && e.seq > 0));
+    let (buyer, usdc) = (env.buyer, env.usdc_mint);
+    env.attest(buyer, 86_400);
+    let listing = pda::listing(&invoice, &investor).0;
+    env.send(instructions::create_listing_v2(invoice, investor, shares_mint, usdc, 10 * UNIT, UNIT), &[investor]).unwrap();
+    env.approve(investor, &shares_mint, &pda::market_authority(&listing).0, 10 * UNIT);
+    env.approve(buyer, &usdc, &pda::market_authority(&listing).0, 10 * UNIT);
+    env.send(instructions::fulfill_listing_v2(invoice, investor, buyer, shares_mint, spl_token::ID, usdc, spl_token::ID, 10 * UNIT), &[buyer]).unwrap();
+    assert_eq!(env.balance(&pda::ata(&buyer, &shares_mint)), 10 * UNIT);
+
+    let events = env.send(instructions::revoke_attestation(compliance, buyer), &[compliance]).unwrap();
+    assert!(env.svm.account(&pda::attestation(&buyer).0).is_none_or(|a| a.lamports == 0));
+    assert!(matches!(&events[..], [Event::AttestationRevoked(e)] if e.investor == buyer));
+}
+
+#[test]
+fn restricted_shares_trade_between_attested_investors() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_restricted_invoice(INVOICE_AMOUNT);
+    let (seller, buyer, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    assert_eq!(env.invoice(&invoice).shares_restricted, 1);
+    assert_eq!(invoice_manager::SHARES_TRANSFER_HOOK_ID, shares_transfer_hook::ID);
+    assert_eq!(env.svm.account(&shares_mint).unwrap().owner, spl_token_2022::ID);
+    assert_eq!(env.svm.account(&pda::extra_account_metas(&shares_mint).0).unwrap().owner, shares_transfer_hook::ID);
+
+    // Funding needs an attestation even though KYC is off
+    env.attest(seller, 86_400);
+    let ix = instructions::fund_invoice_fractional(invoice, seller, env.seller, usdc, spl_token::ID, shares_mint, spl_token_2022::ID, 100 * UNIT);
+    env.send(ix, &[seller]).unwrap();
+    let seller_shares = pda::ata_with_program(&seller, &shares_mint, &spl_token_2022::ID);
+    assert_eq!(env.balance_2022(&seller_shares), 100 * UNIT);
+
+    // The V2 transfer goes through the hook, which finds the buyer's attestation
+    env.attest(buyer, 86_400);
+    let listing = pda::listing(&invoice, &seller).0;
+    let market = pda::market_authority(&listing).0;
+    env.send(instructions::create_listing_v2(invoice, seller, shares_mint, usdc, 40 * UNIT, UNIT), &[seller]).unwrap();
+    env.approve_2022(seller, &shares_mint, &market, 40 * UNIT);
+    env.approve(buyer, &usdc, &market, 40 * UNIT);
+    let ix = instructions::fulfill_listing_v2(invoice, seller, buyer, shares_mint, spl_token_2022::ID, usdc, spl_token::ID, 40 * UNIT);
+    env.send(ix, &[buyer]).unwrap();
+    assert_eq!(env.balance_2022(&pda::ata_with_program(&buyer, &shares_mint, &spl_token_2022::ID)), 40 * UNIT);
+    assert_eq!(env.balance_2022(&seller_shares), 60 * UNIT);
+
+    env.send(instructions::cancel_listing_v2(invoice, seller, shares_mint, spl_token_2022::ID), &[seller]).unwrap();
+    assert_eq!(env.listing(&listing).remaining_qty, 0);
+}
+
+#[test]
+fn verify_invariants_reports_accounting_state() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 600 * UNIT);
+    let (v1_seller, v2_seller, usdc) = (env.investor, env.buyer, env.usdc_mint);
+    env.fund_fractional(invoice, shares_mint, v2_seller, 100 * UNIT).unwrap();
+    env.send(instructions::create_listing(invoice, v1_seller, shares_mint, usdc, 50 * UNIT, UNIT), &[v1_seller]).unwrap();
+    env.send(instructions::create_listing_v2(invoice, v2_seller, shares_mint, usdc, 40 * UNIT, UNIT), &[v2_seller]).unwrap();
+    let v1_listing = pda::listing(&invoice, &v1_seller).0;
+    let v2_listing = pda::listing(&invoice, &v2_seller).0;
+    let listings = [
+        (v1_listing, pda::escrow_shares(&v1_listing, &shares_mint)),
+        (v2_listing, pda::ata(&v2_seller, &shares_mint)),
+    ];
+
+    // The V2 listing is not approved yet, so nothing backs it
+    let report = env.verify_invariants(invoice, &listings);
+    assert_eq!(report.violations, INVARIANT_LISTING_UNBACKED);
+    env.approve(v2_seller, &shares_mint, &pda::market_authority(&v2_listing).0, 40 * UNIT);
+    let report = env.verify_invariants(invoice, &listings);
+    assert_eq!(report.violations, 0);
+    assert!(report.status == InvoiceStatus::Funded);
+    assert_eq!((report.funded_amount, report.escrow_balance, report.shares_supply), (700 * UNIT, 700 * UNIT, 700 * UNIT));
+    assert_eq!((report.listings_checked, report.listed_qty), (2, 90 * UNIT));
+
+    // Shares minted outside of funding and USDC leaking out of escrow are both flagged
+    env.credit_tokens(&v1_seller, &shares_mint, UNIT);
+    let escrow = pda::escrow_token(&invoice, &usdc);
+    let mut drained = env.token_account(&escrow).unwrap();
+    drained.amount -= UNIT;
+    env.write_packed(escrow, drained);
+    let report = env.verify_invariants(invoice, &[]);
+    assert_eq!(report.violations, INVARIANT_SHARES_EXCEED_FUNDING | INVARIANT_ESCROW_SHORTFALL);
+
+    // Listings of another invoice are rejected rather than reported
+    let (other, other_shares) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    env.send(instructions::create_listing(other, v1_seller, other_shares, usdc, UNIT, UNIT), &[v1_seller]).unwrap();
+    let other_listing = pda::listing(&other, &v1_seller).0;
+    let state = env.invoice(&invoice);
+    let ix = instructions::verify_invariants(
+        invoice,
+        usdc,
+        spl_token::ID,
+        Some(state.shares_mint),
+        &[(other_listing, pda::escrow_shares(&other_listing, &other_shares))],
+    );
+    assert_program_error(env.send(ix, &[]), InvoiceError::ListingMismatch);
+}
+
+/// Rewrites `invoice` in the variable-size borsh layout of versions 0 and 1,
+/// sized for the old `space` (a 128-byte hash). Version 0 is the first release.
+fn write_legacy_invoice(env: &mut TestEnv, invoice: &Pubkey, version: u8) -> usize {
+    let state = env.invoice(invoice);
+    let hash = state.metadata_hash().as_bytes().to_vec();
+    let mut data = Invoice::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(state.seller.as_ref());
+    data.extend_from_slice(&state.amount.to_le_bytes());
+    data.extend_from_slice(&(hash.len() as u32).to_le_bytes());
+    data.extend_from_slice(&hash);
+    data.extend_from_slice(&state.due_date.to_le_bytes());
+    data.push(state.status);
+    data.extend_from_slice(state.investor.as_ref());
+    data.extend_from_slice(&state.funded_amount.to_le_bytes());
+    data.extend_from_slice(state.usdc_mint.as_ref());
+    data.push(state.escrow_bump);
+    data.extend_from_slice(state.shares_mint.as_ref());
+    // The first release ended at shares_mint with room for a 128-byte hash;
+    // version 1 appended frozen, seq, the version byte and padding
+    let legacy_space = if version == 0 {
+        294
+    } else {
+        data.push(state.frozen);
+        data.extend_from_slice(&state.seq.to_le_bytes());
+        data.push(version);
+        294 + 1 + 8 + 1 + 128
+    };
+    assert!(data.len() <= legacy_space);
+    data.resize(legacy_space, 0);
+    let mut account = env.svm.account(invoice).unwrap();
+    account.data = data;
+    account.lamports = Rent::default().minimum_balance(legacy_space);
+    env.svm.set_account(*invoice, account);
+    legacy_space
+}
+
+#[test]
+fn migrate_invoice_grows_legacy_account() {
+    let mut env = TestEnv::new();
+    let invoice = Pubkey::new_unique();
+    let due_date = env.svm.clock().unix_timestamp + 86_400;
+    let hash = "h".repeat(128);
+    let ix = instructions::mint_invoice(invoice, env.seller, env.usdc_mint, hash.clone(), INVOICE_AMOUNT, due_date);
+    env.send(ix, &[invoice, env.seller]).unwrap();
+    assert_eq!(env.invoice(&invoice).version, INVOICE_VERSION);
+    let legacy_space = write_legacy_invoice(&mut env, &invoice, 0);
+    assert!(decode_invoice(&env.svm.account(&invoice).unwrap().data).is_err());
+
+    let payer = env.buyer;
+    let payer_before = env.lamports(&payer);
+    let events = env.send(instructions::migrate_invoice(invoice, payer), &[payer]).unwrap();
+
+    let account = env.svm.account(&invoice).unwrap();
+    assert_eq!(account.data.len(), Invoice::SPACE);
+    assert_eq!(account.lamports, Rent::default().minimum_balance(Invoice::SPACE));
+    assert_eq!(payer_before - env.lamports(&payer), Rent::default().minimum_balance(Invoice::SPACE) - Rent::default().minimum_balance(legacy_space));
+    let state = env.invoice(&invoice);
+    assert_eq!(state.version, INVOICE_VERSION);
+    assert_eq!(state.metadata_hash(), hash);
+    assert_eq!((state.seller, state.amount, state.due_date), (env.seller, INVOICE_AMOUNT, due_date));
+    assert_eq!(state.seq, 1);
+    assert!(matches!(&events[..], [Event::InvoiceMigrated(e)] if e.from_version == 0 && e.to_version == INVOICE_VERSION && e.seq == 1));
+
+    // The migrated invoice works with the rest of the program
+    env.send(instructions::create_escrow(invoice, env.seller, env.usdc_mint, spl_token::ID), &[env.seller]).unwrap();
+}
+
+#[test]
+fn migrate_invoice_keeps_funded_v1_state() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, 400 * UNIT);
+    let before = env.invoice(&invoice);
+    write_legacy_invoice(&mut env, &invoice, 1);
+
+    let payer = env.buyer;
+    let events = env.send(instructions::migrate_invoice(invoice, payer), &[payer]).unwrap();
+    assert!(matches!(&events[..], [Event::InvoiceMigrated(e)] if e.from_version == 1 && e.seq == before.seq + 1));
+    let state = env.invoice(&invoice);
+    assert!(state.status().unwrap() == InvoiceStatus::Funded);
+    assert_eq!((state.funded_amount, state.investor, state.shares_mint), (400 * UNIT, env.investor, shares_mint));
+    assert_eq!((state.escrow_bump, state.metadata_hash()), (before.escrow_bump, "hash"));
+
+    // Still fundable through the escrow recorded before the migration
+    let investor = env.investor;
+    env.fund_fractional(invoice, shares_mint, investor, 100 * UNIT).unwrap();
+    assert_eq!(env.invoice(&invoice).funded_amount, 500 * UNIT);
+}
+
+#[test]
+fn settlement_mint_allow_list_is_edited_through_timelock() {
+    let mut env = TestEnv::new();
+    let eurc = Pubkey::new_unique();
+    env.create_mint(eurc, 6);
+    env.list_settlement_mint(eurc, 100 * UNIT);
+    let entry = *env.config().settlement_mint(&eurc).unwrap();
+    assert_eq!((entry.decimals, entry.min_invoice_amount), (6, 100 * UNIT));
+    assert_eq!(env.config().settlement_mints().len(), 2);
+
+    let (invoice, seller) = (Pubkey::new_unique(), env.seller);
+    let due_date = env.svm.clock().unix_timestamp + 86_400;
+    let ix = instructions::mint_invoice(invoice, seller, eurc, "hash".into(), 100 * UNIT, due_date);
+    env.send(ix, &[invoice, seller]).unwrap();
+    assert_eq!(env.invoice(&invoice).usdc_mint, eurc);
+
+    // Listing an already listed mint updates it in place
+    env.list_settlement_mint(eurc, 0);
+    assert_eq!(env.config().settlement_mints().len(), 2);
+    assert_eq!(env.config().settlement_mint(&eurc).unwrap().min_invoice_amount, 0);
+
+    // Removal keeps the remaining entries and leaves existing invoices alone
+    env.apply_config_change(ConfigChange::RemoveSettlementMint(env.usdc_mint));
+    assert!(env.config().settlement_mints() == [SettlementMint { mint: eurc, decimals: 6, min_invoice_amount: 0 }]);
+    env.send(instructions::create_escrow(invoice, seller, eurc, spl_token::ID), &[seller]).unwrap();
+}
+
+#[test]
+fn migrate_listing_grows_first_release_account() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.funded_invoice(INVOICE_AMOUNT, INVOICE_AMOUNT);
+    let (seller, usdc) = (env.investor, env.usdc_mint);
+    let listing = pda::listing(&invoice, &seller).0;
+    env.send(instructions::create_listing(invoice, seller, shares_mint, usdc, 300 * UNIT, UNIT), &[seller]).unwrap();
+    let before = env.listing(&listing);
+    // The first release ended at market_bump, with no seq or version
+    let mut account = env.svm.account(&listing).unwrap();
+    account.data.truncate(Listing::V0_SPACE);
+    account.lamports = Rent::default().minimum_balance(Listing::V0_SPACE);
+    env.svm.set_account(listing, account);
+    assert!(decode_listing(&env.svm.account(&listing).unwrap().data).is_err());
+
+    let payer = env.buyer;
+    let events = env.send(instructions::migrate_listing(listing, payer), &[payer]).unwrap();
+    assert!(matches!(&events[..], [Event::ListingMigrated(e)] if e.from_version == 0 && e.to_version == LISTING_VERSION && e.seq == 1));
+    let account = env.svm.account(&listing).unwrap();
+    assert_eq!(account.data.len(), Listing::SPACE);
+    assert_eq!(account.lamports, Rent::default().minimum_balance(Listing::SPACE));
+    let after = env.listing(&listing);
+    assert_eq!((after.version, after.seq), (LISTING_VERSION, 1));
+    assert_eq!((after.invoice, after.seller, after.shares_mint), (before.invoice, before.seller, before.shares_mint));
+    assert_eq!((after.price, after.remaining_qty, after.bump, after.market_bump), (before.price, before.remaining_qty, before.bump, before.market_bump));
+    assert_program_error(env.send(instructions::migrate_listing(listing, payer), &[payer]), InvoiceError::AlreadyMigrated);
+
+    // The escrowed shares can be reclaimed again
+    env.send(instructions::cancel_listing(invoice, seller, shares_mint), &[seller]).unwrap();
+    assert_eq!(env.balance(&pda::ata(&seller, &shares_mint)), INVOICE_AMOUNT);
+}
+
+#[test]
+fn migrate_config_grows_v1_account() {
+    let mut env = TestEnv::new();
+    let config = pda::config().0;
+    let before = env.config();
+    // v1 ended with kyc_required and 63 reserved bytes, without the mint list
+    let legacy_space = AdminConfig::VERSION_OFFSET + 2 + 63;
+    let mut account = env.svm.account(&config).unwrap();
+    account.data.truncate(legacy_space);
+    account.data[AdminConfig::VERSION_OFFSET] = 1;
+    account.data[AdminConfig::VERSION_OFFSET + 2..].fill(0);
+    account.lamports = Rent::default().minimum_balance(legacy_space);
+    env.svm.set_account(config, account);
+
+    let payer = env.buyer;
+    let events = env.send(instructions::migrate_config(payer), &[payer]).unwrap();
+    assert!(matches!(&events[..], [Event::ConfigMigrated(e)] if e.from_version == 1 && e.to_version == CONFIG_VERSION));
+    let account = env.svm.account(&config).unwrap();
+    assert_eq!(account.data.len(), AdminConfig::SPACE);
+    assert_eq!(account.lamports, Rent::default().minimum_balance(AdminConfig::SPACE));
+    let after = env.config();
+    assert_eq!((after.version, after.admin, after.timelock_delay), (CONFIG_VERSION, before.admin, before.timelock_delay));
+    assert_eq!(after.underwriter, before.admin);
+    assert!(after.settlement_mints().is_empty());
+
+    assert_program_error(env.send(instructions::migrate_config(payer), &[payer]), InvoiceError::AlreadyMigrated);
+}
+
+#[test]
+fn migrate_config_sets_missing_underwriter() {
+    let mut env = TestEnv::new();
+    let (config, admin) = (pda::config().0, env.admin);
+    // Full-size version 2 configs from before the underwriter role
+    let mut state = env.config();
+    state.underwriter = Pubkey::default();
+    let mut account = env.svm.account(&config).unwrap();
+    state.try_serialize(&mut &mut account.data[..]).unwrap();
+    env.svm.set_account(config, account);
+    let invoice = env.mint_invoice(INVOICE_AMOUNT);
+    let ix = instructions::set_invoice_risk(invoice, admin, 1, 900);
+    assert_program_error(env.send(ix.clone(), &[admin]), InvoiceError::Unauthorized);
+
+    let payer = env.buyer;
+    let events = env.send(instructions::migrate_config(payer), &[payer]).unwrap();
+    assert!(matches!(&events[..], [Event::ConfigMigrated(e)] if e.from_version == CONFIG_VERSION && e.to_version == CONFIG_VERSION));
+    assert_eq!(env.config().underwriter, admin);
+    env.send(ix, &[admin]).unwrap();
+    assert_program_error(env.send(instructions::migrate_config(payer), &[payer]), InvoiceError::AlreadyMigrated);
+}
+
+#[test]
+fn migrate_config_upgrades_admin_only_v0_account() {
+    let mut env = TestEnv::new();
+    let config = pda::config().0;
+    let admin = env.admin;
+    // The first release stored only the admin behind the discriminator
+    let mut account = env.svm.account(&config).unwrap();
+    account.data.truncate(AdminConfig::V0_SPACE);
+    account.lamports = Rent::default().minimum_balance(AdminConfig::V0_SPACE);
+    env.svm.set_account(config, account);
+    assert_eq!(env.svm.account(&config).unwrap().data[8..], admin.to_bytes());
+    // The upgrade authority has moved on since deployment
+    let authority = Pubkey::new_unique();
+    env.svm.deploy_invoice_manager(authority);
+
+    let payer = env.buyer;
+    let events = env.send(instructions::migrate_config(payer), &[payer]).unwrap();
+    assert!(matches!(&events[..], [Event::ConfigMigrated(e)] if e.from_version == 0 && e.to_version == CONFIG_VERSION));
+    let account = env.svm.account(&config).unwrap();
+    assert_eq!(account.data.len(), AdminConfig::SPACE);
+    assert_eq!(account.lamports, Rent::default().minimum_balance(AdminConfig::SPACE));
+    let after = env.config();
+    assert_eq!((after.version, after.paused, after.kyc_required), (CONFIG_VERSION, 0, 0));
+    assert_eq!((after.admin, after.pending_admin), (admin, Pubkey::default()));
+    assert_eq!((after.deployer, after.compliance, after.underwriter), (authority, admin, admin));
+    assert_eq!((after.timelock_delay, after.settlement_oracle), (DEFAULT_TIMELOCK_DELAY, Pubkey::default()));
+    assert!(after.settlement_mints().is_empty());
+
+    // The admin keeps control of the config through the timelock
+    let usdc = env.usdc_mint;
+    env.list_settlement_mint(usdc, 0);
+    env.mint_invoice(INVOICE_AMOUNT);
+    assert_program_error(env.send(instructions::migrate_config(payer), &[payer]), InvoiceError::AlreadyMigrated);
+}
+
+#[test]
+fn invoice_layout_offsets_are_stable() {
+    // Published for getProgramAccounts filters; changing them breaks indexers
+    assert_eq!(Invoice::SPACE, 560);
+    assert_eq!(
+        [INVOICE_VERSION_OFFSET, INVOICE_STATUS_OFFSET, INVOICE_FROZEN_OFFSET, INVOICE_SELLER_OFFSET],
+        [8, 9, 10, 16]
+    );
+    assert_eq!([INVOICE_USDC_MINT_OFFSET, INVOICE_INVESTOR_OFFSET, INVOICE_SHARES_MINT_OFFSET], [48, 80, 112]);
+    assert_eq!([INVOICE_RISK_TIER_OFFSET, INVOICE_DEBTOR_OFFSET], [15, 308]);
+
+    let mut env = TestEnv::new();
+    let (invoice, _) = env.funded_invoice(INVOICE_AMOUNT, UNIT);
+    let data = env.svm.account(&invoice).unwrap().data;
+    assert_eq!(data.len(), Invoice::SPACE);
+    for filter in invoice_filters(Some(InvoiceStatus::Funded), Some(&env.usdc_mint)) {
+        assert_eq!(data[filter.offset..filter.offset + filter.bytes.len()], filter.bytes[..]);
+    }
+    assert_eq!(data[INVOICE_SELLER_OFFSET..INVOICE_SELLER_OFFSET + 32], env.seller.to_bytes());
+    assert_eq!(data[INVOICE_INVESTOR_OFFSET..INVOICE_INVESTOR_OFFSET + 32], env.investor.to_bytes());
+    let open = invoice_filters(Some(InvoiceStatus::Open), None);
+    assert_ne!(data[open[1].offset], open[1].bytes[0]);
+}
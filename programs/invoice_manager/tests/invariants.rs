@@ -0,0 +1,251 @@
+//! Property-based invariant checks: random sequences of fractional funding,
+//! marketplace and settlement calls against one invoice, with the accounting
+//! invariants asserted after every step, whether the step succeeded or not.
+//!
+//! Failing sequences are shrunk by proptest and persisted under
+//! `tests/invariants.proptest-regressions`. `PROPTEST_CASES` raises the number
+//! of sequences for longer fuzzing runs.
+
+mod common;
+
+use std::collections::HashMap;
+
+use anchor_lang::solana_program::instruction::Instruction;
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::solana_program::pubkey::Pubkey;
+use common::*;
+use invoice_manager::{InvoiceStatus, INVARIANT_LISTING_UNBACKED};
+use invoice_manager_client::events::InvoiceManagerEvent;
+use invoice_manager_client::{instructions, pda};
+use proptest::prelude::*;
+
+const TRADERS: usize = 3;
+
+#[derive(Clone, Debug)]
+enum Action {
+    Fund { investor: usize, amount: u64 },
+    List { seller: usize, v2: bool, qty: u64, price: u64 },
+    Fulfill { seller: usize, buyer: usize, qty: u64 },
+    Cancel { seller: usize },
+    /// Settles for the funded amount, or for less when `short` is set
+    Settle { short: bool },
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    let trader = 0..TRADERS;
+    prop_oneof![
+        4 => (trader.clone(), 0..=INVOICE_AMOUNT / 2)
+            .prop_map(|(investor, amount)| Action::Fund { investor, amount }),
+        3 => (trader.clone(), any::<bool>(), 0..=300 * UNIT, 1..=2 * UNIT)
+            .prop_map(|(seller, v2, qty, price)| Action::List { seller, v2, qty, price }),
+        4 => (trader.clone(), trader.clone(), 0..=100 * UNIT)
+            .prop_map(|(seller, buyer, qty)| Action::Fulfill { seller, buyer, qty }),
+        1 => trader.prop_map(|seller| Action::Cancel { seller }),
+        1 => any::<bool>().prop_map(|short| Action::Settle { short }),
+    ]
+}
+
+struct Harness {
+    env: TestEnv,
+    invoice: Pubkey,
+    shares_mint: Pubkey,
+    traders: [Pubkey; TRADERS],
+    /// Market version of each trader's listing, once created
+    listings: HashMap<usize, bool>,
+    total_funded: u64,
+    disbursed: u64,
+    usdc_supply: u64,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let mut env = TestEnv::new();
+        let third = Pubkey::new_unique();
+        env.svm.airdrop(&third, 100 * SOL);
+        env.mint_usdc(&third, 1_000_000 * UNIT);
+        let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+        let usdc_supply = env.mint(&env.usdc_mint).supply;
+        Self {
+            traders: [env.investor, env.buyer, third],
+            env,
+            invoice,
+            shares_mint,
+            listings: HashMap::new(),
+            total_funded: 0,
+            disbursed: 0,
+            usdc_supply,
+        }
+    }
+
+    fn listing(&self, seller: usize) -> Pubkey {
+        pda::listing(&self.invoice, &self.traders[seller]).0
+    }
+
+    /// Maps a random trader index onto one with an open listing, when any has,
+    /// so fulfill and cancel mostly hit live listings
+    fn listed_seller(&self, index: usize) -> usize {
+        let mut listed: Vec<usize> = self
+            .listings
+            .keys()
+            .copied()
+            .filter(|&seller| self.env.listing(&self.listing(seller)).remaining_qty > 0)
+            .collect();
+        listed.sort_unstable();
+        if listed.is_empty() { index } else { listed[index % listed.len()] }
+    }
+
+    fn approve(&self, owner: Pubkey, mint: &Pubkey, delegate: &Pubkey, amount: u64) -> Instruction {
+        spl_token::instruction::approve(&spl_token::ID, &pda::ata(&owner, mint), delegate, &owner, &[], amount).unwrap()
+    }
+
+    fn apply(&mut self, action: &Action) {
+        let (invoice, shares_mint, usdc) = (self.invoice, self.shares_mint, self.env.usdc_mint);
+        match *action {
+            Action::Fund { investor, amount } => {
+                if self.env.fund_fractional(invoice, shares_mint, self.traders[investor], amount).is_ok() {
+                    self.total_funded += amount;
+                }
+            }
+            Action::List { seller, v2, qty, price } => {
+                let seller_key = self.traders[seller];
+                let result = if v2 {
+                    let market = pda::market_authority(&self.listing(seller)).0;
+                    let ixs = [
+                        instructions::create_listing_v2(invoice, seller_key, shares_mint, usdc, qty, price),
+                        self.approve(seller_key, &shares_mint, &market, qty),
+                    ];
+                    self.env.send_all(&ixs, &[seller_key])
+                } else {
+                    let ix = instructions::create_listing(invoice, seller_key, shares_mint, usdc, qty, price);
+                    self.env.send(ix, &[seller_key])
+                };
+                if result.is_ok() {
+                    self.listings.insert(seller, v2);
+                }
+            }
+            Action::Fulfill { seller, buyer, qty } => {
+                let seller = self.listed_seller(seller);
+                let (seller_key, buyer_key) = (self.traders[seller], self.traders[buyer]);
+                if self.listings.get(&seller) == Some(&true) {
+                    let listing = self.env.listing(&self.listing(seller));
+                    let total = (qty as u128 * listing.price as u128 / 1_000_000) as u64;
+                    let market = pda::market_authority(&self.listing(seller)).0;
+                    let ixs = [
+                        self.approve(buyer_key, &usdc, &market, total),
+                        instructions::fulfill_listing_v2(invoice, seller_key, buyer_key, shares_mint, spl_token::ID, usdc, spl_token::ID, qty),
+                    ];
+                    let _ = self.env.send_all(&ixs, &[buyer_key]);
+                } else {
+                    let ix = instructions::fulfill_listing(invoice, seller_key, buyer_key, shares_mint, usdc, spl_token::ID, qty);
+                    let _ = self.env.send(ix, &[buyer_key]);
+                }
+            }
+            Action::Cancel { seller } => {
+                let seller = self.listed_seller(seller);
+                let seller_key = self.traders[seller];
+                let ix = if self.listings.get(&seller) == Some(&true) {
+                    instructions::cancel_listing_v2(invoice, seller_key, shares_mint, spl_token::ID)
+                } else {
+                    instructions::cancel_listing(invoice, seller_key, shares_mint)
+                };
+                let _ = self.env.send(ix, &[seller_key]);
+            }
+            Action::Settle { short } => {
+                let funded = self.env.invoice(&invoice).funded_amount;
+                let amount = if short { funded / 2 } else { funded };
+                let admin = self.env.admin;
+                let ix = instructions::set_settled(invoice, admin, self.env.seller, usdc, spl_token::ID, amount, None);
+                if let Ok(events) = self.env.send(ix, &[admin]) {
+                    for event in events {
+                        if let InvoiceManagerEvent::InvoiceSettled(settled) = event {
+                            self.disbursed += settled.amount;
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn check_invariants(&mut self) {
+        let env = &self.env;
+        let invoice = env.invoice(&self.invoice);
+        let escrow = env.balance(&pda::escrow_token(&self.invoice, &env.usdc_mint));
+        let shares_supply = env.mint(&self.shares_mint).supply;
+
+        // Funding and escrow accounting
+        assert_eq!(invoice.funded_amount, self.total_funded, "funded_amount drifted from successful fundings");
+        assert!(invoice.funded_amount <= invoice.amount, "invoice overfunded");
+        assert_eq!(escrow, invoice.funded_amount - self.disbursed, "escrow != funded_amount - disbursed");
+        assert_eq!(shares_supply, self.total_funded, "shares supply != total funded");
+        match invoice.status().unwrap() {
+            InvoiceStatus::Open => assert_eq!(invoice.funded_amount, 0),
+            InvoiceStatus::Funded => assert!(invoice.funded_amount > 0 && self.disbursed == 0),
+            InvoiceStatus::Settled => assert_eq!(self.disbursed, invoice.funded_amount),
+            InvoiceStatus::Defaulted => assert_eq!(self.disbursed, 0),
+        }
+
+        // USDC and shares are only ever moved, never created or lost
+        let usdc_held: u64 = self
+            .traders
+            .iter()
+            .chain([&env.seller])
+            .map(|owner| env.balance(&pda::ata(owner, &env.usdc_mint)))
+            .sum::<u64>()
+            + escrow;
+        assert_eq!(usdc_held, self.usdc_supply, "USDC not conserved");
+        let mut shares_held = 0;
+        for owner in &self.traders {
+            shares_held += env.balance(&pda::ata(owner, &self.shares_mint));
+        }
+
+        // Listings never promise more shares than back them
+        for (&seller, &v2) in &self.listings {
+            let listing_key = self.listing(seller);
+            let listing = env.listing(&listing_key);
+            let market = pda::market_authority(&listing_key).0;
+            if v2 {
+                if listing.remaining_qty > 0 {
+                    let seller_shares = env.token_account(&pda::ata(&self.traders[seller], &self.shares_mint)).unwrap();
+                    assert_eq!(seller_shares.delegate, COption::Some(market), "open V2 listing lost its delegate");
+                    assert!(listing.remaining_qty <= seller_shares.delegated_amount, "V2 listing exceeds delegated shares");
+                }
+            } else {
+                let escrowed = env.balance(&pda::escrow_shares(&listing_key, &self.shares_mint));
+                assert!(listing.remaining_qty <= escrowed, "V1 listing exceeds escrowed shares");
+                shares_held += escrowed;
+            }
+        }
+        assert_eq!(shares_held, shares_supply, "shares not conserved");
+
+        // The on-chain check agrees. V2 listings may legitimately list more
+        // shares than the seller holds, which it reports as unbacked.
+        let mut pairs = Vec::new();
+        let mut unbacked = false;
+        for (&seller, &v2) in &self.listings {
+            let listing_key = self.listing(seller);
+            let backing = if v2 {
+                pda::ata(&self.traders[seller], &self.shares_mint)
+            } else {
+                pda::escrow_shares(&listing_key, &self.shares_mint)
+            };
+            let remaining = env.listing(&listing_key).remaining_qty;
+            unbacked |= v2 && remaining > env.balance(&backing);
+            pairs.push((listing_key, backing));
+        }
+        let report = self.env.verify_invariants(self.invoice, &pairs);
+        let expected = if unbacked { INVARIANT_LISTING_UNBACKED } else { 0 };
+        assert_eq!(report.violations, expected, "verify_invariants disagrees");
+    }
+}
+
+proptest! {
+    #[test]
+    fn accounting_invariants_hold(actions in prop::collection::vec(action(), 1..40)) {
+        let mut harness = Harness::new();
+        harness.check_invariants();
+        for action in &actions {
+            harness.apply(action);
+            harness.check_invariants();
+        }
+    }
+}
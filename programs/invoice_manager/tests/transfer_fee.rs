@@ -0,0 +1,96 @@
+//! USDC as a Token-2022 mint with the transfer fee extension: funding credits
+//! what the escrow received, and every other leg moves through `transfer_checked`.
+
+mod common;
+
+use common::*;
+use invoice_manager::{InvoiceError, InvoiceStatus};
+use invoice_manager_client::events::InvoiceManagerEvent as Event;
+use invoice_manager_client::{instructions, pda};
+
+/// 1% on every transfer, capped at 5 USDC
+const FEE_BPS: u16 = 100;
+const MAX_FEE: u64 = 5 * UNIT;
+
+#[test]
+fn funding_credits_amount_net_of_fee() {
+    let mut env = TestEnv::with_transfer_fee(FEE_BPS, MAX_FEE);
+    let (invoice, shares_mint) = env.open_invoice(10_000 * UNIT);
+    let (investor, usdc, program) = (env.investor, env.usdc_mint, env.usdc_program);
+    let escrow = pda::escrow_token_with_program(&invoice, &usdc, &program);
+
+    let ix = instructions::fund_invoice(invoice, investor, env.seller, usdc, program, 400 * UNIT);
+    let events = env.send(ix, &[investor]).unwrap();
+    assert_eq!(env.balance_2022(&escrow), 396 * UNIT);
+    assert_eq!(env.invoice(&invoice).funded_amount, 396 * UNIT);
+    assert!(matches!(&events[..], [Event::InvoiceFunded(e)] if e.amount == 396 * UNIT && e.funded_amount == 396 * UNIT));
+
+    // Fractional funding mints shares for the net amount; the fee hits its cap here
+    let events = env.fund_fractional(invoice, shares_mint, investor, 1_000 * UNIT).unwrap();
+    assert_eq!(env.balance(&pda::ata(&investor, &shares_mint)), 995 * UNIT);
+    assert_eq!(env.balance_2022(&escrow), 1_391 * UNIT);
+    assert!(matches!(&events[..], [Event::InvoiceFunded(e)] if e.amount == 995 * UNIT && e.shares_minted == 995 * UNIT));
+
+    let report = env.verify_invariants(invoice, &[]);
+    assert_eq!(report.violations, 0);
+    assert_eq!(report.escrow_balance, report.funded_amount);
+}
+
+#[test]
+fn overfund_is_checked_against_amount_received() {
+    let mut env = TestEnv::with_transfer_fee(FEE_BPS, MAX_FEE);
+    let (invoice, _) = env.open_invoice(100 * UNIT);
+    let (investor, usdc, program) = (env.investor, env.usdc_mint, env.usdc_program);
+
+    // 101 gross arrives as 99.99, which still fits under the face value
+    env.send(instructions::fund_invoice(invoice, investor, env.seller, usdc, program, 101 * UNIT), &[investor]).unwrap();
+    assert_eq!(env.invoice(&invoice).funded_amount, 99_990_000);
+
+    let ix = instructions::fund_invoice(invoice, investor, env.seller, usdc, program, UNIT);
+    assert_program_error(env.send(ix, &[investor]), InvoiceError::Overfund);
+}
+
+#[test]
+fn settlement_and_trades_with_fee_mint() {
+    let mut env = TestEnv::with_transfer_fee(FEE_BPS, MAX_FEE);
+    let (invoice, shares_mint) = env.open_invoice(INVOICE_AMOUNT);
+    let (investor, buyer, usdc, program) = (env.investor, env.buyer, env.usdc_mint, env.usdc_program);
+    env.fund_fractional(invoice, shares_mint, investor, 500 * UNIT).unwrap();
+    let funded = env.invoice(&invoice).funded_amount;
+    assert_eq!(funded, 495 * UNIT);
+
+    // V1: the buyer pays the full price, the seller receives it net of the fee
+    let ix = instructions::create_listing(invoice, investor, shares_mint, usdc, 200 * UNIT, UNIT);
+    env.send(ix, &[investor]).unwrap();
+    let seller_usdc_before = env.balance_2022(&env.usdc_ata(&investor));
+    let buyer_usdc_before = env.balance_2022(&env.usdc_ata(&buyer));
+    let ix = instructions::fulfill_listing(invoice, investor, buyer, shares_mint, usdc, program, 100 * UNIT);
+    let events = env.send(ix, &[buyer]).unwrap();
+    assert_eq!(env.balance_2022(&env.usdc_ata(&buyer)), buyer_usdc_before - 100 * UNIT);
+    assert_eq!(env.balance_2022(&env.usdc_ata(&investor)), seller_usdc_before + 99 * UNIT);
+    assert_eq!(env.balance(&pda::ata(&buyer, &shares_mint)), 100 * UNIT);
+    assert!(matches!(&events[..], [Event::ListingFulfilled(e), _] if e.total == 100 * UNIT));
+
+    // V2: the buyer resells; the USDC leg is a delegated Token-2022 transfer
+    let listing = pda::listing(&invoice, &buyer).0;
+    let market = pda::market_authority(&listing).0;
+    let ix = instructions::create_listing_v2(invoice, buyer, shares_mint, usdc, 50 * UNIT, 2 * UNIT);
+    env.send(ix, &[buyer]).unwrap();
+    env.approve(buyer, &shares_mint, &market, 50 * UNIT);
+    env.approve_2022(investor, &usdc, &market, 100 * UNIT);
+    let seller_usdc_before = env.balance_2022(&env.usdc_ata(&buyer));
+    let ix = instructions::fulfill_listing_v2(invoice, buyer, investor, shares_mint, spl_token::ID, usdc, program, 50 * UNIT);
+    env.send(ix, &[investor]).unwrap();
+    assert_eq!(env.balance_2022(&env.usdc_ata(&buyer)), seller_usdc_before + 99 * UNIT);
+    assert_eq!(env.balance(&pda::ata(&buyer, &shares_mint)), 50 * UNIT);
+
+    // Settlement pays out the escrow in full; the fee comes out of what the seller receives
+    let seller = env.seller;
+    let seller_usdc_before = env.balance_2022(&env.usdc_ata(&seller));
+    let admin = env.admin;
+    let ix = instructions::set_settled(invoice, admin, seller, usdc, program, funded, None);
+    env.send(ix, &[admin]).unwrap();
+    assert_eq!(env.balance_2022(&pda::escrow_token_with_program(&invoice, &usdc, &program)), 0);
+    assert_eq!(env.balance_2022(&env.usdc_ata(&seller)), seller_usdc_before + funded - funded / 100);
+    assert!(env.invoice(&invoice).status().unwrap() == InvoiceStatus::Settled);
+}
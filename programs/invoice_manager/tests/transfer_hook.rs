@@ -0,0 +1,116 @@
+//! The shares transfer hook, exercised through direct Token-2022 transfers of
+//! restricted shares.
+
+mod common;
+
+use anchor_lang::error::ERROR_CODE_OFFSET;
+use anchor_lang::solana_program::instruction::{AccountMeta, Instruction};
+use anchor_lang::solana_program::program_error::ProgramError;
+use anchor_lang::solana_program::pubkey::Pubkey;
+use common::*;
+use invoice_manager::InvoiceError;
+use invoice_manager_client::{instructions, pda};
+use shares_transfer_hook::HookError;
+
+fn hook_error(err: HookError) -> ProgramError {
+    ProgramError::Custom(ERROR_CODE_OFFSET + err as u32)
+}
+
+/// Investor holding 100 restricted shares
+fn restricted_holder(env: &mut TestEnv) -> (Pubkey, Pubkey) {
+    let (invoice, shares_mint) = env.open_restricted_invoice(INVOICE_AMOUNT);
+    let (investor, usdc) = (env.investor, env.usdc_mint);
+    env.attest(investor, 86_400);
+    let ix = instructions::fund_invoice_fractional(invoice, investor, env.seller, usdc, spl_token::ID, shares_mint, spl_token_2022::ID, 100 * UNIT);
+    env.send(ix, &[investor]).unwrap();
+    (invoice, shares_mint)
+}
+
+/// Creates `recipient`'s shares account and transfers `amount` to it from the
+/// investor, with the accounts the hook resolves appended
+fn transfer(env: &mut TestEnv, shares_mint: &Pubkey, recipient: Pubkey, amount: u64) -> Result<Vec<String>, TxError> {
+    let investor = env.investor;
+    let create = spl_associated_token_account::instruction::create_associated_token_account_idempotent(
+        &investor,
+        &recipient,
+        shares_mint,
+        &spl_token_2022::ID,
+    );
+    let mut ix: Instruction = spl_token_2022::instruction::transfer_checked(
+        &spl_token_2022::ID,
+        &pda::ata_with_program(&investor, shares_mint, &spl_token_2022::ID),
+        shares_mint,
+        &pda::ata_with_program(&recipient, shares_mint, &spl_token_2022::ID),
+        &investor,
+        &[],
+        amount,
+        6,
+    )
+    .unwrap();
+    ix.accounts.extend([
+        AccountMeta::new_readonly(pda::extra_account_metas(shares_mint).0, false),
+        AccountMeta::new_readonly(invoice_manager::ID, false),
+        AccountMeta::new_readonly(pda::attestation(&recipient).0, false),
+        AccountMeta::new_readonly(shares_transfer_hook::ID, false),
+    ]);
+    env.svm.process(&[create, ix], &[investor])
+}
+
+#[test]
+fn hook_admits_attested_recipient() {
+    let mut env = TestEnv::new();
+    let (_, shares_mint) = restricted_holder(&mut env);
+    let buyer = env.buyer;
+    env.attest(buyer, 86_400);
+    let logs = transfer(&mut env, &shares_mint, buyer, 30 * UNIT).unwrap();
+    assert!(logs.iter().any(|line| line.starts_with(&format!("Program {} invoke", shares_transfer_hook::ID))));
+    assert_eq!(env.balance_2022(&pda::ata_with_program(&buyer, &shares_mint, &spl_token_2022::ID)), 30 * UNIT);
+}
+
+#[test]
+fn hook_rejects_unattested_or_expired_recipient() {
+    let mut env = TestEnv::new();
+    let (_, shares_mint) = restricted_holder(&mut env);
+    let buyer = env.buyer;
+    let result = transfer(&mut env, &shares_mint, buyer, UNIT);
+    assert_eq!(result.unwrap_err().error, hook_error(HookError::RecipientNotAttested));
+
+    env.attest(buyer, 60);
+    env.svm.warp(60);
+    let result = transfer(&mut env, &shares_mint, buyer, UNIT);
+    assert_eq!(result.unwrap_err().error, hook_error(HookError::AttestationExpired));
+}
+
+#[test]
+fn hook_rejects_direct_execute() {
+    let mut env = TestEnv::new();
+    let (_, shares_mint) = restricted_holder(&mut env);
+    let investor = env.investor;
+    let source = pda::ata_with_program(&investor, &shares_mint, &spl_token_2022::ID);
+    // Calling the hook outside of a transfer, as if sending the investor's shares to themself
+    let ix = spl_transfer_hook_interface::instruction::execute_with_extra_account_metas(
+        &shares_transfer_hook::ID,
+        &source,
+        &shares_mint,
+        &source,
+        &investor,
+        &pda::extra_account_metas(&shares_mint).0,
+        &[
+            AccountMeta::new_readonly(invoice_manager::ID, false),
+            AccountMeta::new_readonly(pda::attestation(&investor).0, false),
+        ],
+        UNIT,
+    );
+    let result = env.svm.process(&[ix], &[investor]);
+    assert_eq!(result.unwrap_err().error, hook_error(HookError::NotTransferring));
+}
+
+#[test]
+fn restricted_funding_requires_attestation() {
+    let mut env = TestEnv::new();
+    let (invoice, shares_mint) = env.open_restricted_invoice(INVOICE_AMOUNT);
+    let (investor, usdc) = (env.investor, env.usdc_mint);
+    let ix = instructions::fund_invoice_fractional(invoice, investor, env.seller, usdc, spl_token::ID, shares_mint, spl_token_2022::ID, UNIT);
+    assert_program_error(env.send(ix, &[investor]), InvoiceError::AttestationMissing);
+}
+
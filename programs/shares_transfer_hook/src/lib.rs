@@ -0,0 +1,141 @@
+// Anchor 0.31's generated IDL handlers still call `AccountInfo::realloc`.
+#![allow(deprecated)]
+
+//! Token-2022 transfer hook for restricted invoice shares. Mints created by
+//! invoice_manager's `init_shares_restricted` point their TransferHook extension
+//! here, so every transfer of their shares must land in a wallet holding an
+//! unexpired invoice_manager `InvestorAttestation`.
+
+use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::extension::transfer_hook::{TransferHook, TransferHookAccount};
+use anchor_spl::token_2022::spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
+use anchor_spl::token_2022::spl_token_2022::state::{Account as Token2022Account, Mint as Token2022Mint};
+use anchor_spl::token_interface::{Mint, TokenAccount};
+use invoice_manager::InvestorAttestation;
+use spl_discriminator::SplDiscriminate;
+use spl_tlv_account_resolution::account::ExtraAccountMeta;
+use spl_tlv_account_resolution::seeds::Seed;
+use spl_tlv_account_resolution::state::ExtraAccountMetaList;
+use spl_transfer_hook_interface::instruction::ExecuteInstruction;
+
+declare_id!("3sr3Q2ukAuj4CaS3LzSY8FjRVfYZCBMYMNrYhPuQpXfh");
+
+/// Seed of the validation account listing the extra accounts of `execute`,
+/// fixed by the transfer-hook interface
+pub const EXTRA_ACCOUNT_METAS_SEED: &[u8] = b"extra-account-metas";
+
+/// Extra accounts Token-2022 resolves for `execute`, after the interface's
+/// five (source, mint, destination, owner, validation account): the
+/// invoice_manager program, then the destination owner's attestation PDA,
+/// whose seed is read from bytes 32..64 (the owner) of the destination account.
+pub fn extra_account_metas() -> Result<Vec<ExtraAccountMeta>> {
+    Ok(vec![
+        ExtraAccountMeta::new_with_pubkey(&invoice_manager::ID, false, false)?,
+        ExtraAccountMeta::new_external_pda_with_seeds(
+            5,
+            &[
+                Seed::Literal { bytes: b"attestation".to_vec() },
+                Seed::AccountData { account_index: 2, data_index: 32, length: 32 },
+            ],
+            false,
+            false,
+        )?,
+    ])
+}
+
+#[program]
+pub mod shares_transfer_hook {
+    use super::*;
+
+    // Writes the validation account of a mint whose transfer hook is this
+    // program. Its content is fixed, so anyone may pay for it.
+    pub fn initialize_extra_account_meta_list(ctx: Context<InitializeExtraAccountMetaList>) -> Result<()> {
+        {
+            let mint_info = ctx.accounts.mint.to_account_info();
+            let data = mint_info.try_borrow_data()?;
+            let mint = StateWithExtensions::<Token2022Mint>::unpack(&data)?;
+            let hook = mint.get_extension::<TransferHook>().map_err(|_| HookError::WrongHookProgram)?;
+            require!(Option::<Pubkey>::from(hook.program_id) == Some(crate::ID), HookError::WrongHookProgram);
+        }
+        let metas = extra_account_metas()?;
+        ExtraAccountMetaList::init::<ExecuteInstruction>(
+            &mut ctx.accounts.extra_account_meta_list.try_borrow_mut_data()?,
+            &metas,
+        )?;
+        Ok(())
+    }
+
+    #[instruction(discriminator = ExecuteInstruction::SPL_DISCRIMINATOR_SLICE)]
+    pub fn execute(ctx: Context<Execute>, _amount: u64) -> Result<()> {
+        // Only Token-2022 in the middle of a transfer sets the flag
+        {
+            let source_info = ctx.accounts.source_token.to_account_info();
+            let data = source_info.try_borrow_data()?;
+            let source = StateWithExtensions::<Token2022Account>::unpack(&data)?;
+            let hook_account = source.get_extension::<TransferHookAccount>()?;
+            require!(bool::from(hook_account.transferring), HookError::NotTransferring);
+        }
+
+        let recipient = ctx.accounts.destination_token.owner;
+        let attestation = &ctx.accounts.attestation;
+        require!(
+            attestation.owner == &invoice_manager::ID && !attestation.data_is_empty(),
+            HookError::RecipientNotAttested
+        );
+        let attestation = InvestorAttestation::try_deserialize(&mut &attestation.try_borrow_data()?[..])?;
+        require_keys_eq!(attestation.investor, recipient, HookError::RecipientNotAttested);
+        require!(Clock::get()?.unix_timestamp < attestation.expires_at, HookError::AttestationExpired);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeExtraAccountMetaList<'info> {
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    /// CHECK: written as a TLV ExtraAccountMetaList
+    #[account(
+        init,
+        payer = payer,
+        space = ExtraAccountMetaList::size_of(2)?,
+        seeds = [EXTRA_ACCOUNT_METAS_SEED, mint.key().as_ref()],
+        bump,
+    )]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+    #[account(mint::token_program = anchor_spl::token_2022::ID)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    pub system_program: Program<'info, System>,
+}
+
+// Account order is fixed by the transfer-hook interface
+#[derive(Accounts)]
+pub struct Execute<'info> {
+    #[account(token::mint = mint)]
+    pub source_token: InterfaceAccount<'info, TokenAccount>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(token::mint = mint)]
+    pub destination_token: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: source owner or delegate, already authorized by Token-2022
+    pub owner: UncheckedAccount<'info>,
+    /// CHECK: validation account; its content is what Token-2022 resolved the extras from
+    #[account(seeds = [EXTRA_ACCOUNT_METAS_SEED, mint.key().as_ref()], bump)]
+    pub extra_account_meta_list: UncheckedAccount<'info>,
+    /// CHECK: only used to derive the attestation PDA
+    #[account(address = invoice_manager::ID)]
+    pub invoice_manager_program: UncheckedAccount<'info>,
+    /// CHECK: the recipient's InvestorAttestation PDA, which may not exist
+    #[account(
+        seeds = [b"attestation", destination_token.owner.as_ref()],
+        bump,
+        seeds::program = invoice_manager::ID,
+    )]
+    pub attestation: UncheckedAccount<'info>,
+}
+
+#[error_code]
+pub enum HookError {
+    #[msg("Mint's transfer hook is not this program")] WrongHookProgram,
+    #[msg("Hook invoked outside of a token transfer")] NotTransferring,
+    #[msg("Recipient has no investor attestation")] RecipientNotAttested,
+    #[msg("Recipient's investor attestation expired")] AttestationExpired,
+}
@@ -54,6 +54,12 @@ async function main() {
     program.programId,
   );
 
+  // init_config must be signed by the program's upgrade authority, proven via ProgramData
+  const [programDataPda] = web3.PublicKey.findProgramAddressSync(
+    [program.programId.toBuffer()],
+    new web3.PublicKey('BPFLoaderUpgradeab1e11111111111111111111111'),
+  );
+
   console.log('Admin pubkey (from RELAYER_KEYPAIR_PATH):', admin.toBase58());
   console.log('Config PDA:', configPda.toBase58());
 
@@ -62,6 +68,8 @@ async function main() {
     .accounts({
       config: configPda,
       payer: admin,
+      program: program.programId,
+      programData: programDataPda,
       systemProgram: web3.SystemProgram.programId,
     })
     .rpc();